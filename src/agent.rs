@@ -1,11 +1,40 @@
 #[cfg(feature = "cookie")]
 use cookie::{Cookie, CookieJar};
+#[cfg(feature = "cookie")]
+use std::collections::HashSet;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::Arc;
 use std::sync::Mutex;
+use std::time::Duration;
 
+use crate::dns::{DnsCache, DEFAULT_DNS_CACHE_TTL};
 use crate::header::{self, Header};
-use crate::pool::ConnectionPool;
-use crate::request::Request;
+use crate::pool::{
+    ConnectionPool, DEFAULT_MAX_IDLE_AGE, DEFAULT_MAX_IDLE_CONNECTIONS,
+    DEFAULT_MAX_IDLE_CONNECTIONS_PER_HOST,
+};
+use crate::proxy::Proxy;
+use crate::request::{Request, DEFAULT_REDIRECTS};
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+use crate::tls::{ClientCert, TlsOptions, TlsVersion};
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+use crate::Error;
+#[cfg(feature = "cookie")]
+use url::Url;
+
+pub(crate) type ResolverFn = dyn Fn(&str) -> io::Result<Vec<SocketAddr>> + Send + Sync;
+
+/// A function resolving `host:port` into socket addresses, set with
+/// [`Agent::resolver()`](struct.Agent.html#method.resolver).
+#[derive(Clone)]
+pub(crate) struct Resolver(pub(crate) Arc<ResolverFn>);
+
+impl ::std::fmt::Debug for Resolver {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        write!(f, "Resolver")
+    }
+}
 
 /// Agents keep state between requests.
 ///
@@ -35,14 +64,123 @@ use crate::request::Request;
 ///
 /// println!("Secret is: {}", secret.into_string().unwrap());
 /// ```
-#[derive(Debug, Default, Clone)]
+///
+/// An `Agent` is `Send` and `Sync`, and cloning one is cheap: the clone shares the
+/// connection pool and cookies. Requests on many threads can use the same agent,
+/// through an `Arc` or a clone each, and reuse each other's idle connections.
+///
+/// ```
+/// let agent = ureq::agent();
+///
+/// let threads: Vec<_> = (0..4)
+///     .map(|_| {
+///         let agent = agent.clone();
+///         std::thread::spawn(move || agent.get("http://localhost/").call())
+///     })
+///     .collect();
+///
+/// for thread in threads {
+///     println!("{:?}", thread.join().unwrap());
+/// }
+/// ```
+///
+/// Settings such as [`timeout()`](#method.timeout), [`redirects()`](#method.redirects),
+/// [`user_agent()`](#method.user_agent) and [`set_proxy()`](#method.set_proxy) are the
+/// defaults of the requests made with the agent, which can override them. To derive
+/// several agents from a common base, clone it, change the clone and `build()` that.
+///
+/// ```
+/// use std::time::Duration;
+///
+/// let mut base = ureq::agent();
+/// base.timeout(Duration::from_secs(30)).user_agent("my-app/1.0");
+///
+/// let api = base.clone().set("X-Api-Key", "foobar").build();
+/// let crawler = base.clone().redirects(10).build();
+/// ```
+#[derive(Debug, Clone)]
 pub struct Agent {
     /// Copied into each request of this agent.
     pub(crate) headers: Vec<Header>,
+    /// Sent with each request of this agent, unless the request sets them.
+    pub(crate) default_headers: Vec<Header>,
+    /// Whether to decode compressed response bodies.
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    pub(crate) compression: bool,
+    /// Limits for the connection pool created by build().
+    pub(crate) max_idle_connections: usize,
+    pub(crate) max_idle_connections_per_host: usize,
+    pub(crate) max_idle_age: Duration,
+    /// How long looked up addresses are reused, zero to not reuse them.
+    pub(crate) dns_cache_ttl: Duration,
+    /// Resolves host names instead of the system resolver, if set.
+    pub(crate) resolver: Option<Resolver>,
+    /// Whether to turn off Nagle's algorithm on new connections.
+    pub(crate) tcp_nodelay: bool,
+    /// How long a new connection is idle before TCP keepalive probes start, if at all.
+    pub(crate) tcp_keepalive: Option<Duration>,
+    /// The source address and network interface of new connections, if set.
+    pub(crate) local_address: Option<IpAddr>,
+    pub(crate) bind_device: Option<String>,
+    /// Whether connections are kept open and reused.
+    pub(crate) keepalive: bool,
+    /// Limit for uploads and downloads, each, if any.
+    pub(crate) max_bytes_per_sec: Option<u64>,
+    /// Largest response body to read, if any.
+    pub(crate) max_response_size: Option<u64>,
+    /// Size of the buffers for copying bodies, if not the defaults.
+    pub(crate) buffer_size: Option<usize>,
+    /// Overall timeout of each request, if any.
+    pub(crate) timeout: Option<Duration>,
+    /// How many redirects each request follows.
+    pub(crate) redirects: u32,
+    /// Proxy server used by each request, if any.
+    pub(crate) proxy: Option<Proxy>,
+    /// TLS settings, and the TLS config built from them by build().
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub(crate) tls: TlsOptions,
+    #[cfg(feature = "tls")]
+    pub(crate) tls_config: Option<crate::request::TLSClientConfig>,
+    #[cfg(feature = "native-tls")]
+    pub(crate) tls_connector: Option<crate::request::TLSConnector>,
     /// Reused agent state for repeated requests from this agent.
     pub(crate) state: Arc<Mutex<Option<AgentState>>>,
 }
 
+impl Default for Agent {
+    fn default() -> Self {
+        Agent {
+            headers: vec![],
+            default_headers: vec![],
+            #[cfg(any(feature = "gzip", feature = "brotli"))]
+            compression: true,
+            max_idle_connections: DEFAULT_MAX_IDLE_CONNECTIONS,
+            max_idle_connections_per_host: DEFAULT_MAX_IDLE_CONNECTIONS_PER_HOST,
+            max_idle_age: DEFAULT_MAX_IDLE_AGE,
+            dns_cache_ttl: DEFAULT_DNS_CACHE_TTL,
+            max_bytes_per_sec: None,
+            max_response_size: None,
+            buffer_size: None,
+            timeout: None,
+            redirects: DEFAULT_REDIRECTS,
+            proxy: None,
+            keepalive: true,
+            tcp_nodelay: false,
+            tcp_keepalive: None,
+            local_address: None,
+            bind_device: None,
+            resolver: None,
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            tls: TlsOptions::default(),
+            #[cfg(feature = "tls")]
+            tls_config: None,
+            #[cfg(feature = "native-tls")]
+            tls_connector: None,
+            state: Default::default(),
+        }
+    }
+}
+
 /// Container of the state
 ///
 /// *Internal API*.
@@ -50,17 +188,26 @@ pub struct Agent {
 pub(crate) struct AgentState {
     /// Reused connections between requests.
     pub(crate) pool: ConnectionPool,
+    /// Host addresses looked up by earlier requests.
+    pub(crate) dns: DnsCache,
     /// Cookies saved between requests.
     #[cfg(feature = "cookie")]
     pub(crate) jar: CookieJar,
+    /// The names of the cookies in the jar that were set without a `Domain`. They are
+    /// only sent to the host that set them, not to its subdomains.
+    #[cfg(feature = "cookie")]
+    pub(crate) host_only: HashSet<String>,
 }
 
 impl AgentState {
-    fn new() -> Self {
+    fn new(pool: ConnectionPool, dns: DnsCache) -> Self {
         AgentState {
-            pool: ConnectionPool::new(),
+            pool,
+            dns,
             #[cfg(feature = "cookie")]
             jar: CookieJar::new(),
+            #[cfg(feature = "cookie")]
+            host_only: HashSet::new(),
         }
     }
     pub fn pool(&mut self) -> &mut ConnectionPool {
@@ -89,7 +236,39 @@ impl Agent {
     pub fn build(&self) -> Self {
         Agent {
             headers: self.headers.clone(),
-            state: Arc::new(Mutex::new(Some(AgentState::new()))),
+            default_headers: self.default_headers.clone(),
+            #[cfg(any(feature = "gzip", feature = "brotli"))]
+            compression: self.compression,
+            max_idle_connections: self.max_idle_connections,
+            max_idle_connections_per_host: self.max_idle_connections_per_host,
+            max_idle_age: self.max_idle_age,
+            dns_cache_ttl: self.dns_cache_ttl,
+            max_bytes_per_sec: self.max_bytes_per_sec,
+            max_response_size: self.max_response_size,
+            buffer_size: self.buffer_size,
+            timeout: self.timeout,
+            redirects: self.redirects,
+            proxy: self.proxy.clone(),
+            keepalive: self.keepalive,
+            tcp_nodelay: self.tcp_nodelay,
+            tcp_keepalive: self.tcp_keepalive,
+            local_address: self.local_address,
+            bind_device: self.bind_device.clone(),
+            resolver: self.resolver.clone(),
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            tls: self.tls.clone(),
+            #[cfg(feature = "tls")]
+            tls_config: self.tls.rustls_config(),
+            #[cfg(feature = "native-tls")]
+            tls_connector: self.tls.native_tls_connector(),
+            state: Arc::new(Mutex::new(Some(AgentState::new(
+                ConnectionPool::new(
+                    self.max_idle_connections,
+                    self.max_idle_connections_per_host,
+                    self.max_idle_age,
+                ),
+                DnsCache::new(self.dns_cache_ttl),
+            )))),
         }
     }
 
@@ -116,6 +295,43 @@ impl Agent {
         self
     }
 
+    /// Set a header field that is sent with all requests using the agent, unless
+    /// the request sets a header of the same name, in any case.
+    ///
+    /// Unlike [`set()`](#method.set), a header set on the request replaces the
+    /// default one, also for `X-` headers that otherwise can be repeated.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .set_default_header("User-Agent", "my-api-client/1.0")
+    ///     .set_default_header("X-Api-Key", "foobar")
+    ///     .build();
+    ///
+    /// // sent with "x-api-key: other" and "User-Agent: my-api-client/1.0"
+    /// let r = agent
+    ///     .get("/my-page")
+    ///     .set("x-api-key", "other")
+    ///     .call();
+    /// ```
+    pub fn set_default_header(&mut self, header: &str, value: &str) -> &mut Agent {
+        self.default_headers.retain(|h| !h.is_name(header));
+        self.default_headers.push(Header::new(header, value));
+        self
+    }
+
+    /// The `User-Agent` sent with each request of this agent, unless the request
+    /// sets one, instead of the default `ureq`. The same as
+    /// [`set_default_header()`](#method.set_default_header) with `User-Agent`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .user_agent("my-api-client/1.0")
+    ///     .build();
+    /// ```
+    pub fn user_agent(&mut self, user_agent: &str) -> &mut Agent {
+        self.set_default_header("User-Agent", user_agent)
+    }
+
     /// Basic auth that will be present in all requests using the agent.
     ///
     /// ```
@@ -152,6 +368,499 @@ impl Agent {
         self
     }
 
+    /// Turn decoding of compressed response bodies on or off.
+    ///
+    /// Requires feature `ureq = { version = "*", features = ["gzip"] }` or `["brotli"]`
+    ///
+    /// Defaults to `true`, which means that requests are sent with `Accept-Encoding: gzip, deflate`
+    /// (and/or `br`) unless that header is set, and a response with `Content-Encoding: gzip`,
+    /// `deflate` or `br` is decoded when reading it. Set to `false` to send no `Accept-Encoding`
+    /// and read the raw bytes.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .compression(false)
+    ///     .build();
+    /// ```
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    pub fn compression(&mut self, enabled: bool) -> &mut Agent {
+        self.compression = enabled;
+        self
+    }
+
+    /// Limit how fast requests of this agent send and receive, to at most the given
+    /// number of bytes per second in each direction. This counts everything sent on
+    /// a connection: the request line, headers and body.
+    ///
+    /// The time spent held back counts against the overall
+    /// [`timeout()`](struct.Request.html#method.timeout) of a request.
+    ///
+    /// Defaults to no limit.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .max_bytes_per_sec(100 * 1024)
+    ///     .build();
+    /// ```
+    pub fn max_bytes_per_sec(&mut self, max: u64) -> &mut Agent {
+        self.max_bytes_per_sec = Some(max);
+        self
+    }
+
+    /// Limit the size of response bodies read by requests of this agent, to
+    /// guard against servers sending more than can be held in memory.
+    ///
+    /// Reading a larger body from [`into_reader()`](struct.Response.html#method.into_reader),
+    /// [`into_string()`](struct.Response.html#method.into_string) and the like fails with
+    /// [`Error::ResponseTooLarge`](enum.Error.html#variant.ResponseTooLarge) once the limit
+    /// is passed. A body with a larger `Content-Length` fails before anything is read.
+    /// The limit applies to the body after it's decompressed.
+    ///
+    /// Defaults to no limit.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .max_response_size(10 * 1024 * 1024)
+    ///     .build();
+    /// ```
+    pub fn max_response_size(&mut self, max: u64) -> &mut Agent {
+        self.max_response_size = Some(max);
+        self
+    }
+
+    /// Sets the size in bytes of the buffers used to copy request bodies from a
+    /// reader, and response bodies in [`copy_to()`](struct.Response.html#method.copy_to).
+    /// For chunked request bodies, this is the most a chunk holds. Larger buffers can
+    /// speed up big transfers, smaller ones save memory.
+    ///
+    /// Defaults to 8 KiB, and chunks of up to 16 KiB, the max size of a TLS fragment.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .buffer_size(64 * 1024)
+    ///     .build();
+    /// ```
+    pub fn buffer_size(&mut self, size: usize) -> &mut Agent {
+        self.buffer_size = Some(size);
+        self
+    }
+
+    /// Timeout for the whole of each request of this agent, like
+    /// [`Request::timeout()`](struct.Request.html#method.timeout), which overrides it.
+    ///
+    /// Defaults to no timeout.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .timeout(std::time::Duration::from_secs(30))
+    ///     .build();
+    /// ```
+    pub fn timeout(&mut self, timeout: Duration) -> &mut Agent {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// How many redirects requests of this agent follow, like
+    /// [`Request::redirects()`](struct.Request.html#method.redirects), which overrides it.
+    ///
+    /// Defaults to `5`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .redirects(0)
+    ///     .build();
+    /// ```
+    pub fn redirects(&mut self, n: u32) -> &mut Agent {
+        self.redirects = n;
+        self
+    }
+
+    /// The proxy server requests of this agent connect through, like
+    /// [`Request::set_proxy()`](struct.Request.html#method.set_proxy), which overrides it.
+    ///
+    /// ```
+    /// let proxy = ureq::Proxy::new("user:password@cool.proxy:9090").unwrap();
+    /// let agent = ureq::agent()
+    ///     .set_proxy(proxy)
+    ///     .build();
+    /// ```
+    pub fn set_proxy(&mut self, proxy: Proxy) -> &mut Agent {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    /// Resolve host names with the given function, instead of the system resolver.
+    ///
+    /// The function is called with `host:port`, like `example.com:443`, for the
+    /// server or proxy to connect to. An error fails the request with
+    /// [`Error::DnsFailed`](enum.Error.html#variant.DnsFailed). Hosts that are IP
+    /// addresses are not resolved.
+    ///
+    /// ```
+    /// use std::net::ToSocketAddrs;
+    ///
+    /// let agent = ureq::agent()
+    ///     .resolver(|addr| match addr {
+    ///         "api.example.com:443" => Ok(vec!["127.0.0.1:8443".parse().unwrap()]),
+    ///         addr => addr.to_socket_addrs().map(Iterator::collect),
+    ///     })
+    ///     .build();
+    /// ```
+    pub fn resolver<F>(&mut self, f: F) -> &mut Agent
+    where
+        F: Fn(&str) -> io::Result<Vec<SocketAddr>> + Send + Sync + 'static,
+    {
+        self.resolver = Some(Resolver(Arc::new(f)));
+        self
+    }
+
+    /// Set `TCP_NODELAY` on new connections, which turns off Nagle's algorithm, so small
+    /// requests are sent right away instead of waiting for earlier packets to be acked.
+    /// Connections reused from the pool keep the setting they were made with.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .tcp_nodelay(true)
+    ///     .build();
+    /// ```
+    pub fn tcp_nodelay(&mut self, enabled: bool) -> &mut Agent {
+        self.tcp_nodelay = enabled;
+        self
+    }
+
+    /// Turn on TCP keepalive (`SO_KEEPALIVE`) for new connections, so the operating system
+    /// sends probes once a connection has been idle this long, and finds out about a peer
+    /// that went away. This is about the socket, not the reuse of connections, see
+    /// [`pool()`](#method.pool) for that. Like `tcp_nodelay()`, connections reused from the
+    /// pool keep the setting they were made with.
+    ///
+    /// Defaults to off.
+    ///
+    /// ```
+    /// use std::time::Duration;
+    ///
+    /// let agent = ureq::agent()
+    ///     .tcp_keepalive(Duration::from_secs(60))
+    ///     .build();
+    /// ```
+    pub fn tcp_keepalive(&mut self, idle: Duration) -> &mut Agent {
+        self.tcp_keepalive = Some(idle);
+        self
+    }
+
+    /// Make new connections from this local address, for a host with several. Only the
+    /// addresses of the server of the same family (IPv4 or IPv6) are tried. When the socket
+    /// can't be bound, say because the address isn't one of this host, the request fails
+    /// with [`Error::BindFailed`](enum.Error.html#variant.BindFailed).
+    ///
+    /// Connections through a SOCKS proxy are made by the `socks` crate and don't use it.
+    ///
+    /// ```
+    /// use std::net::Ipv4Addr;
+    ///
+    /// let agent = ureq::agent()
+    ///     .local_address(Ipv4Addr::LOCALHOST.into())
+    ///     .build();
+    /// ```
+    pub fn local_address(&mut self, addr: IpAddr) -> &mut Agent {
+        self.local_address = Some(addr);
+        self
+    }
+
+    /// Make new connections through the network interface with this name, such as `eth1`,
+    /// with `SO_BINDTODEVICE`. This usually needs `CAP_NET_RAW`, without it, or when
+    /// there isn't such an interface, the request fails with
+    /// [`Error::BindFailed`](enum.Error.html#variant.BindFailed).
+    ///
+    /// Like [`local_address()`](#method.local_address) it doesn't apply to SOCKS proxies.
+    /// Only on Linux.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .bind_device("lo")
+    ///     .build();
+    /// ```
+    #[cfg(target_os = "linux")]
+    pub fn bind_device(&mut self, interface: &str) -> &mut Agent {
+        self.bind_device = Some(interface.to_string());
+        self
+    }
+
+    /// Turn the reuse of connections on or off. When off, every request is sent with
+    /// `Connection: close` on a new connection, like
+    /// [`no_keepalive()`](struct.Request.html#method.no_keepalive) does for one request.
+    ///
+    /// Defaults to `true`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .pool(false)
+    ///     .build();
+    /// ```
+    pub fn pool(&mut self, enabled: bool) -> &mut Agent {
+        self.keepalive = enabled;
+        self
+    }
+
+    /// Sets the maximum number of idle connections the pool keeps, across all hosts.
+    /// When the pool is full, the connection that has been idle the longest is closed
+    /// to make room. Setting this to `0` turns pooling off.
+    ///
+    /// Defaults to `100`. Takes effect for the agent returned by `build()`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .max_idle_connections(10)
+    ///     .build();
+    /// ```
+    pub fn max_idle_connections(&mut self, max: usize) -> &mut Agent {
+        self.max_idle_connections = max;
+        self
+    }
+
+    /// Sets the maximum number of idle connections the pool keeps for a single host
+    /// (scheme, hostname, port and proxy). When the host is at its limit, its
+    /// connection that has been idle the longest is closed to make room.
+    ///
+    /// Defaults to `1`. Takes effect for the agent returned by `build()`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .max_idle_connections_per_host(4)
+    ///     .build();
+    /// ```
+    pub fn max_idle_connections_per_host(&mut self, max: usize) -> &mut Agent {
+        self.max_idle_connections_per_host = max;
+        self
+    }
+
+    /// Sets how long a connection may sit idle in the pool and still be reused.
+    /// Older connections are closed instead of handed out, since the server has
+    /// likely closed them already, and the request opens a fresh connection.
+    ///
+    /// Defaults to 15 seconds, which is within most servers' keepalive timeout.
+    /// Takes effect for the agent returned by `build()`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .max_idle_age(std::time::Duration::from_secs(5))
+    ///     .build();
+    /// ```
+    pub fn max_idle_age(&mut self, age: Duration) -> &mut Agent {
+        self.max_idle_age = age;
+        self
+    }
+
+    /// Sets how long the addresses a host name resolves to are reused by later
+    /// requests, rather than looked up again. Zero looks up every time.
+    ///
+    /// The system resolver doesn't tell how long the DNS records are valid, so this
+    /// is used for all hosts. Addresses that can't be connected to are forgotten,
+    /// so a host that moved is looked up again. Hosts resolved by a
+    /// [`resolver()`](#method.resolver) aren't cached.
+    ///
+    /// Defaults to 60 seconds. Takes effect for the agent returned by `build()`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .dns_cache_ttl(std::time::Duration::from_secs(10))
+    ///     .build();
+    /// ```
+    pub fn dns_cache_ttl(&mut self, ttl: Duration) -> &mut Agent {
+        self.dns_cache_ttl = ttl;
+        self
+    }
+
+    /// Sets a client certificate to present when a server asks for one during the
+    /// TLS handshake, for servers that use mutual TLS.
+    ///
+    /// `cert_chain` is the client certificate followed by any intermediates, and `key`
+    /// its private key, each either PEM or DER encoded. A DER key, and any key used
+    /// with `native-tls`, must be PKCS#8. Returns
+    /// [`Error::BadCertificate`](enum.Error.html#variant.BadCertificate) if they can't be
+    /// used. A server that rejects the certificate fails the request with
+    /// [`Error::TlsHandshake`](enum.Error.html#variant.TlsHandshake).
+    ///
+    /// Takes effect for the agent returned by `build()`.
+    ///
+    /// ```no_run
+    /// let cert = std::fs::read("client.pem").unwrap();
+    /// let key = std::fs::read("client.key").unwrap();
+    /// let agent = ureq::agent()
+    ///     .client_certificate(&cert, &key)
+    ///     .unwrap()
+    ///     .build();
+    /// ```
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub fn client_certificate(
+        &mut self,
+        cert_chain: &[u8],
+        key: &[u8],
+    ) -> Result<&mut Agent, Error> {
+        self.tls.client_cert = Some(ClientCert::parse(cert_chain, key)?);
+        Ok(self)
+    }
+
+    /// Trusts a CA certificate for https connections, in addition to the
+    /// default root certificates, e.g. for servers signed by a private CA.
+    ///
+    /// `cert` is PEM or DER encoded. A PEM file may hold several certificates,
+    /// and calling this again adds more. Returns
+    /// [`Error::BadCertificate`](enum.Error.html#variant.BadCertificate) if it
+    /// can't be used.
+    ///
+    /// Takes effect for the agent returned by `build()`.
+    ///
+    /// ```no_run
+    /// let ca = std::fs::read("internal-ca.pem").unwrap();
+    /// let agent = ureq::agent()
+    ///     .add_root_certificate(&ca)
+    ///     .unwrap()
+    ///     .build();
+    /// ```
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub fn add_root_certificate(&mut self, cert: &[u8]) -> Result<&mut Agent, Error> {
+        self.tls.add_root_certificates(cert)?;
+        Ok(self)
+    }
+
+    /// Accept any certificate and any hostname from https servers. Off by default.
+    ///
+    /// **This disables the protection TLS gives against man-in-the-middle
+    /// attacks.** It's meant for tests against a local server with a self-signed
+    /// certificate. To trust such a server in production, use
+    /// [`add_root_certificate`](#method.add_root_certificate) instead.
+    ///
+    /// Takes effect for the agent returned by `build()`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .danger_accept_invalid_certs(true)
+    ///     .build();
+    /// ```
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub fn danger_accept_invalid_certs(&mut self, accept: bool) -> &mut Agent {
+        self.tls.accept_invalid_certs = accept;
+        self
+    }
+
+    /// Only accept https servers whose certificate has one of the given public keys,
+    /// in addition to the normal verification of the certificate.
+    ///
+    /// Each pin is the base64 encoded SHA-256 hash of a DER encoded public key
+    /// (SubjectPublicKeyInfo), the format of HPKP and curl's `--pinnedpubkey`,
+    /// with or without `sha256//` in front. Give several to rotate keys. A server
+    /// with another key fails the request with
+    /// [`Error::PinMismatch`](enum.Error.html#variant.PinMismatch). Calling this
+    /// again replaces the pins, and an empty list turns pinning off.
+    ///
+    /// Returns [`Error::BadCertificate`](enum.Error.html#variant.BadCertificate) if a
+    /// pin isn't a SHA-256 hash.
+    ///
+    /// Takes effect for the agent returned by `build()`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .pinned_public_keys(&[
+    ///         "sha256//6BSf7/GUGXZy+7SlCVVH7U/BZXvVYyWSC8XkuuRDoGc=",
+    ///         "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+    ///     ])
+    ///     .unwrap()
+    ///     .build();
+    /// ```
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub fn pinned_public_keys(&mut self, pins: &[&str]) -> Result<&mut Agent, Error> {
+        self.tls.pinned_keys = pins
+            .iter()
+            .map(|pin| crate::tls::parse_pin(pin))
+            .collect::<Result<_, _>>()?;
+        Ok(self)
+    }
+
+    /// The oldest TLS version allowed for https connections. Defaults to TLS 1.2.
+    ///
+    /// rustls doesn't have TLS 1.0 and 1.1, so with it older versions than 1.2 are
+    /// never used. A server that doesn't support any version between this and
+    /// [`max_tls_version`](#method.max_tls_version) fails the request with
+    /// [`Error::TlsVersion`](enum.Error.html#variant.TlsVersion).
+    ///
+    /// Takes effect for the agent returned by `build()`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .min_tls_version(ureq::TlsVersion::Tls13)
+    ///     .build();
+    /// ```
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub fn min_tls_version(&mut self, version: TlsVersion) -> &mut Agent {
+        self.tls.min_version = Some(version);
+        self
+    }
+
+    /// The newest TLS version allowed for https connections. Defaults to the newest
+    /// the TLS backend has.
+    ///
+    /// Takes effect for the agent returned by `build()`.
+    ///
+    /// ```
+    /// let agent = ureq::agent()
+    ///     .max_tls_version(ureq::TlsVersion::Tls12)
+    ///     .build();
+    /// ```
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub fn max_tls_version(&mut self, version: TlsVersion) -> &mut Agent {
+        self.tls.max_version = Some(version);
+        self
+    }
+
+    /// Set the rustls client config used for all https connections of this agent.
+    ///
+    /// The config is used as is. The agent's other TLS settings, such as
+    /// [`add_root_certificate`](#method.add_root_certificate), are ignored when one
+    /// is set. A config set on a request with
+    /// [`set_tls_config`](struct.Request.html#method.set_tls_config) takes precedence.
+    ///
+    /// Takes effect for the agent returned by `build()`.
+    ///
+    /// ```
+    /// let tls_config = std::sync::Arc::new(rustls::ClientConfig::new());
+    /// let agent = ureq::agent()
+    ///     .set_tls_config(tls_config)
+    ///     .build();
+    /// ```
+    #[cfg(feature = "tls")]
+    pub fn set_tls_config(&mut self, tls_config: Arc<rustls::ClientConfig>) -> &mut Agent {
+        self.tls.custom_config = Some(crate::request::TLSClientConfig(tls_config));
+        self
+    }
+
+    /// Set the native-tls connector used for all https connections of this agent.
+    ///
+    /// The connector is used as is. The agent's other TLS settings, such as
+    /// [`add_root_certificate`](#method.add_root_certificate), are ignored when one
+    /// is set.
+    ///
+    /// Takes effect for the agent returned by `build()`.
+    ///
+    /// ```
+    /// # #[cfg(feature = "native-tls")] {
+    /// let connector = native_tls::TlsConnector::new().unwrap();
+    /// let agent = ureq::agent()
+    ///     .set_tls_connector(std::sync::Arc::new(connector))
+    ///     .build();
+    /// # }
+    /// ```
+    #[cfg(feature = "native-tls")]
+    pub fn set_tls_connector(
+        &mut self,
+        tls_connector: Arc<native_tls::TlsConnector>,
+    ) -> &mut Agent {
+        self.tls.custom_connector = Some(crate::request::TLSConnector::Built(tls_connector));
+        self
+    }
+
     /// Request by providing the HTTP verb such as `GET`, `POST`...
     ///
     /// ```
@@ -186,12 +895,67 @@ impl Agent {
             .cloned()
     }
 
-    /// Set a cookie in this agent.
+    /// Gets the cookie with the given name that this agent would send with a
+    /// request to the url, unlike [`cookie()`](#method.cookie) which doesn't look
+    /// at its domain and path.
     ///
     /// ```
     /// let agent = ureq::agent();
+    /// let cookie = ureq::Cookie::build("name", "value")
+    ///     .domain("example.com")
+    ///     .path("/app")
+    ///     .finish();
+    /// agent.set_cookie(cookie);
+    ///
+    /// let url = ureq::Url::parse("https://www.example.com/app/login").unwrap();
+    /// assert!(agent.cookie_for("name", &url).is_some());
+    ///
+    /// let elsewhere = ureq::Url::parse("https://www.example.com/other").unwrap();
+    /// assert!(agent.cookie_for("name", &elsewhere).is_none());
+    /// ```
+    #[cfg(feature = "cookie")]
+    pub fn cookie_for(&self, name: &str, url: &Url) -> Option<Cookie<'static>> {
+        self.cookies_for(url).into_iter().find(|c| c.name() == name)
+    }
+
+    /// Gets all the cookies that this agent would send with a request to the url.
     ///
-    /// let cookie = ureq::Cookie::new("name", "value");
+    /// ```
+    /// let agent = ureq::agent();
+    /// let cookie = ureq::Cookie::build("name", "value")
+    ///     .domain("example.com")
+    ///     .path("/")
+    ///     .finish();
+    /// agent.set_cookie(cookie);
+    ///
+    /// let url = ureq::Url::parse("https://example.com/").unwrap();
+    /// for cookie in agent.cookies_for(&url) {
+    ///     println!("{}={}", cookie.name(), cookie.value());
+    /// }
+    /// ```
+    #[cfg(feature = "cookie")]
+    pub fn cookies_for(&self, url: &Url) -> Vec<Cookie<'static>> {
+        let state = self.state.lock().unwrap();
+        match state.as_ref() {
+            None => vec![],
+            Some(state) => crate::unit::match_cookies(state, url, true)
+                .into_iter()
+                .cloned()
+                .collect(),
+        }
+    }
+
+    /// Set a cookie in this agent, replacing any cookie with the same name. It is
+    /// sent with the next requests that match its domain, including subdomains, and
+    /// path. A cookie without a domain has no host to be sent to, and is ignored.
+    ///
+    /// ```
+    /// let agent = ureq::agent();
+    ///
+    /// let cookie = ureq::Cookie::build("name", "value")
+    ///     .domain("example.com")
+    ///     .path("/")
+    ///     .finish();
     /// agent.set_cookie(cookie);
     /// ```
     #[cfg(feature = "cookie")]
@@ -199,12 +963,74 @@ impl Agent {
         let mut state = self.state.lock().unwrap();
         match state.as_mut() {
             None => (),
+            Some(_) if cookie.domain().is_none() => (),
             Some(state) => {
+                state.host_only.remove(cookie.name());
                 state.jar.add_original(cookie);
             }
         }
     }
 
+    /// Remove the cookie with the given name from this agent.
+    ///
+    /// ```
+    /// let agent = ureq::agent();
+    ///
+    /// let cookie = ureq::Cookie::build("name", "value")
+    ///     .domain("example.com")
+    ///     .finish();
+    /// agent.set_cookie(cookie);
+    /// agent.remove_cookie("name");
+    /// assert!(agent.cookie("name").is_none());
+    /// ```
+    #[cfg(feature = "cookie")]
+    pub fn remove_cookie(&self, name: &str) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(state) = state.as_mut() {
+            state.host_only.remove(name);
+            state.jar.force_remove(Cookie::named(name));
+        }
+    }
+
+    /// Write the cookies of this agent in the Netscape `cookies.txt` format, as
+    /// read by curl and wget, so they can be loaded again with
+    /// [`cookie_store_load`](#method.cookie_store_load). Expired cookies are left out.
+    ///
+    /// ```no_run
+    /// let agent = ureq::agent();
+    /// agent.get("https://example.com/login").call();
+    ///
+    /// let file = std::fs::File::create("cookies.txt").unwrap();
+    /// agent.cookie_store_save(file).unwrap();
+    /// ```
+    #[cfg(feature = "cookie")]
+    pub fn cookie_store_save<W: std::io::Write>(&self, writer: W) -> std::io::Result<()> {
+        let state = self.state.lock().unwrap();
+        match state.as_ref() {
+            None => crate::cookies::save(&CookieJar::new(), &HashSet::new(), writer),
+            Some(state) => crate::cookies::save(&state.jar, &state.host_only, writer),
+        }
+    }
+
+    /// Add the cookies of a Netscape `cookies.txt` file to this agent, such as one
+    /// written by [`cookie_store_save`](#method.cookie_store_save). Expired cookies
+    /// are skipped.
+    ///
+    /// ```no_run
+    /// let agent = ureq::agent();
+    ///
+    /// let file = std::fs::File::open("cookies.txt").unwrap();
+    /// agent.cookie_store_load(file).unwrap();
+    /// ```
+    #[cfg(feature = "cookie")]
+    pub fn cookie_store_load<R: std::io::Read>(&self, reader: R) -> std::io::Result<()> {
+        let mut state = self.state.lock().unwrap();
+        match state.as_mut() {
+            None => Ok(()),
+            Some(state) => crate::cookies::load(&mut state.jar, &mut state.host_only, reader),
+        }
+    }
+
     /// Make a GET request from this agent.
     pub fn get(&self, path: &str) -> Request {
         self.request("GET", path)
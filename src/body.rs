@@ -1,5 +1,7 @@
-use crate::stream::Stream;
-use std::io::{copy, empty, Cursor, Read, Result as IoResult, Write};
+use crate::multipart::Multipart;
+use crate::stream::DeadlineStream;
+use std::io::{copy, Cursor, Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use std::sync::Arc;
 
 #[cfg(feature = "charset")]
 use crate::response::DEFAULT_CHARACTER_SET;
@@ -19,8 +21,9 @@ pub(crate) enum Payload {
     Text(String, String),
     #[cfg(feature = "json")]
     JSON(SerdeValue),
-    Reader(Box<dyn Read + 'static>),
+    Reader(Box<dyn Read + 'static>, Option<u64>),
     Bytes(Vec<u8>),
+    Multipart(Multipart),
 }
 
 impl ::std::fmt::Debug for Payload {
@@ -30,8 +33,9 @@ impl ::std::fmt::Debug for Payload {
             Payload::Text(t, _) => write!(f, "{}", t),
             #[cfg(feature = "json")]
             Payload::JSON(_) => write!(f, "JSON"),
-            Payload::Reader(_) => write!(f, "Reader"),
+            Payload::Reader(..) => write!(f, "Reader"),
             Payload::Bytes(v) => write!(f, "{:?}", v),
+            Payload::Multipart(m) => write!(f, "{:?}", m),
         }
     }
 }
@@ -48,6 +52,8 @@ impl Default for Payload {
 pub(crate) struct SizedReader {
     pub size: Option<usize>,
     pub reader: Box<dyn Read + 'static>,
+    // bodies held in memory are kept around so they can be sent again.
+    replay: Option<Arc<[u8]>>,
 }
 
 impl ::std::fmt::Debug for SizedReader {
@@ -58,14 +64,40 @@ impl ::std::fmt::Debug for SizedReader {
 
 impl SizedReader {
     fn new(size: Option<usize>, reader: Box<dyn Read + 'static>) -> Self {
-        SizedReader { size, reader }
+        SizedReader {
+            size,
+            reader,
+            replay: None,
+        }
+    }
+
+    fn from_bytes(size: Option<usize>, bytes: Arc<[u8]>) -> Self {
+        SizedReader {
+            size,
+            reader: Box::new(Cursor::new(bytes.clone())),
+            replay: Some(bytes),
+        }
+    }
+
+    /// Whether this body is known to have nothing to send.
+    pub fn is_empty(&self) -> bool {
+        self.size == Some(0) || self.replay.as_ref().map(|b| b.is_empty()) == Some(true)
+    }
+
+    /// A new reader that starts over from the beginning of the same body.
+    ///
+    /// Only possible for bodies held in memory, not for bodies from a user provided reader.
+    pub fn try_clone(&self) -> Option<SizedReader> {
+        self.replay
+            .as_ref()
+            .map(|bytes| SizedReader::from_bytes(self.size, bytes.clone()))
     }
 }
 
 impl Payload {
     pub fn into_read(self) -> SizedReader {
         match self {
-            Payload::Empty => SizedReader::new(None, Box::new(empty())),
+            Payload::Empty => SizedReader::from_bytes(None, Arc::new([])),
             Payload::Text(text, _charset) => {
                 #[cfg(feature = "charset")]
                 let bytes = {
@@ -77,23 +109,106 @@ impl Payload {
                 #[cfg(not(feature = "charset"))]
                 let bytes = text.into_bytes();
                 let len = bytes.len();
-                let cursor = Cursor::new(bytes);
-                SizedReader::new(Some(len), Box::new(cursor))
+                SizedReader::from_bytes(Some(len), bytes.into())
             }
             #[cfg(feature = "json")]
             Payload::JSON(v) => {
                 let bytes = serde_json::to_vec(&v).expect("Bad JSON in payload");
                 let len = bytes.len();
-                let cursor = Cursor::new(bytes);
-                SizedReader::new(Some(len), Box::new(cursor))
+                SizedReader::from_bytes(Some(len), bytes.into())
+            }
+            Payload::Reader(read, None) => SizedReader::new(None, read),
+            Payload::Reader(read, Some(len)) => {
+                SizedReader::new(Some(len as usize), Box::new(LenReader::new(read, len)))
             }
-            Payload::Reader(read) => SizedReader::new(None, read),
             Payload::Bytes(bytes) => {
                 let len = bytes.len();
-                let cursor = Cursor::new(bytes);
-                SizedReader::new(Some(len), Box::new(cursor))
+                SizedReader::from_bytes(Some(len), bytes.into())
+            }
+            Payload::Multipart(form) => {
+                let len = form.len().map(|len| len as usize);
+                SizedReader::new(len, Box::new(form.into_reader()))
+            }
+        }
+    }
+}
+
+/// Reads exactly `len` bytes, and fails if the inner reader ends before that.
+///
+/// A body sent with a `Content-Length` that runs short would leave the server
+/// waiting for the rest, so we'd rather fail the request.
+pub(crate) struct LenReader {
+    inner: Box<dyn Read + 'static>,
+    left: u64,
+}
+
+impl LenReader {
+    pub(crate) fn new(inner: Box<dyn Read + 'static>, len: u64) -> Self {
+        LenReader { inner, left: len }
+    }
+}
+
+impl Read for LenReader {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.left == 0 {
+            return Ok(0);
+        }
+        let max = buf.len().min(self.left.min(usize::MAX as u64) as usize);
+        let n = self.inner.read(&mut buf[..max])?;
+        if n == 0 {
+            return Err(IoError::new(
+                ErrorKind::UnexpectedEof,
+                format!("body ended {} bytes short of its length", self.left),
+            ));
+        }
+        self.left -= n as u64;
+        Ok(n)
+    }
+}
+
+/// Reads the chunks produced by a closure, for [`Request::send_with()`](struct.Request.html#method.send_with).
+///
+/// The closure is called with an empty buffer to append the next chunk to, and
+/// returns `false` after the last one. Empty chunks are skipped, since reading
+/// nothing would end the body early.
+pub(crate) struct PullReader<F> {
+    produce: F,
+    chunk: Vec<u8>,
+    pos: usize,
+    done: bool,
+}
+
+impl<F> PullReader<F>
+where
+    F: FnMut(&mut Vec<u8>) -> IoResult<bool>,
+{
+    pub(crate) fn new(produce: F) -> Self {
+        PullReader {
+            produce,
+            chunk: vec![],
+            pos: 0,
+            done: false,
+        }
+    }
+}
+
+impl<F> Read for PullReader<F>
+where
+    F: FnMut(&mut Vec<u8>) -> IoResult<bool>,
+{
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        while self.pos == self.chunk.len() {
+            if self.done {
+                return Ok(0);
             }
+            self.chunk.clear();
+            self.pos = 0;
+            self.done = !(self.produce)(&mut self.chunk)?;
         }
+        let n = buf.len().min(self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
     }
 }
 
@@ -108,23 +223,43 @@ const CHUNK_MAX_PAYLOAD_SIZE: usize = CHUNK_MAX_SIZE - CHUNK_HEADER_MAX_SIZE - C
 // 2) chunked_transfer's Encoder issues 4 separate write() per chunk. This is costly
 //    overhead. Instead, we do a single write() per chunk.
 // The measured benefit on a Linux machine is a 50% reduction in CPU usage on a https connection.
-fn copy_chunked<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> IoResult<u64> {
+//
+// With flush, each chunk is what one read() gives, and is flushed once written.
+fn copy_chunked<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    max_payload_size: usize,
+    flush: bool,
+) -> IoResult<u64> {
     // The chunk layout is:
     // header:header_max_size | payload:max_payload_size | footer:footer_size
-    let mut chunk = Vec::with_capacity(CHUNK_MAX_SIZE);
+    let header_max_size = format!("{:x}\r\n", max_payload_size).len();
+    let mut chunk = Vec::with_capacity(header_max_size + max_payload_size + CHUNK_FOOTER_SIZE);
     let mut written = 0;
     loop {
         // We first read the payload
-        chunk.resize(CHUNK_HEADER_MAX_SIZE, 0);
-        let payload_size = reader
-            .take(CHUNK_MAX_PAYLOAD_SIZE as u64)
-            .read_to_end(&mut chunk)?;
+        chunk.resize(header_max_size, 0);
+        let payload_size = if flush {
+            chunk.resize(header_max_size + max_payload_size, 0);
+            let n = loop {
+                match reader.read(&mut chunk[header_max_size..]) {
+                    Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                    r => break r?,
+                }
+            };
+            chunk.truncate(header_max_size + n);
+            n
+        } else {
+            reader
+                .take(max_payload_size as u64)
+                .read_to_end(&mut chunk)?
+        };
 
         // Then write the header
         let header_str = format!("{:x}\r\n", payload_size);
         let header = header_str.as_bytes();
-        assert!(header.len() <= CHUNK_HEADER_MAX_SIZE);
-        let start_index = CHUNK_HEADER_MAX_SIZE - header.len();
+        assert!(header.len() <= header_max_size);
+        let start_index = header_max_size - header.len();
         (&mut chunk[start_index..]).write_all(&header).unwrap();
 
         // And add the footer
@@ -132,6 +267,9 @@ fn copy_chunked<R: Read, W: Write>(reader: &mut R, writer: &mut W) -> IoResult<u
 
         // Finally Write the chunk
         writer.write_all(&chunk[start_index..])?;
+        if flush {
+            writer.flush()?;
+        }
         written += payload_size as u64;
 
         // On EOF, we wrote a 0 sized chunk. This is what the chunked encoding protocol requires.
@@ -148,7 +286,7 @@ fn test_copy_chunked() {
     source.extend_from_slice(b"hello world");
 
     let mut dest = Vec::<u8>::new();
-    copy_chunked(&mut &source[..], &mut dest).unwrap();
+    copy_chunked(&mut &source[..], &mut dest, CHUNK_MAX_PAYLOAD_SIZE, false).unwrap();
 
     let mut dest_expected = Vec::<u8>::new();
     dest_expected.extend_from_slice(format!("{:x}\r\n", CHUNK_MAX_PAYLOAD_SIZE).as_bytes());
@@ -161,16 +299,92 @@ fn test_copy_chunked() {
     assert_eq!(dest, dest_expected);
 }
 
+#[test]
+fn test_copy_chunked_large_chunks() {
+    let source = vec![33; 0x12345];
+
+    let mut dest = Vec::<u8>::new();
+    copy_chunked(&mut &source[..], &mut dest, 0x10000, false).unwrap();
+
+    let mut dest_expected = Vec::<u8>::new();
+    dest_expected.extend_from_slice(b"10000\r\n");
+    dest_expected.resize(dest_expected.len() + 0x10000, 33);
+    dest_expected.extend_from_slice(b"\r\n2345\r\n");
+    dest_expected.resize(dest_expected.len() + 0x2345, 33);
+    dest_expected.extend_from_slice(b"\r\n0\r\n\r\n");
+
+    assert_eq!(dest, dest_expected);
+}
+
+#[test]
+fn test_copy_chunked_flush() {
+    struct Flushes(Vec<u8>, Vec<usize>);
+    impl Write for Flushes {
+        fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+            self.0.write(buf)
+        }
+        fn flush(&mut self) -> IoResult<()> {
+            self.1.push(self.0.len());
+            Ok(())
+        }
+    }
+
+    // one chunk per read, however small.
+    let mut source = (&b"ab"[..]).chain(&b"cde"[..]);
+    let mut dest = Flushes(vec![], vec![]);
+    copy_chunked(&mut source, &mut dest, 0x100, true).unwrap();
+
+    assert_eq!(dest.0, b"2\r\nab\r\n3\r\ncde\r\n0\r\n\r\n");
+    assert_eq!(dest.1, vec![7, 15, 20]);
+}
+
+// io::copy(), with a buffer of the given size.
+fn copy_buffered<R: Read, W: Write>(
+    reader: &mut R,
+    writer: &mut W,
+    buffer_size: usize,
+) -> IoResult<u64> {
+    let mut buf = vec![0; buffer_size];
+    let mut written = 0;
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => return Ok(written),
+            Ok(n) => n,
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        writer.write_all(&buf[..n])?;
+        written += n as u64;
+    }
+}
+
 /// Helper to send a body, either as chunked or not.
+///
+/// Without a `buffer_size`, chunks are the size of a TLS fragment and other
+/// bodies are copied with the buffer of `io::copy()`.
 pub(crate) fn send_body(
     mut body: SizedReader,
     do_chunk: bool,
-    stream: &mut Stream,
+    stream: &mut DeadlineStream,
+    buffer_size: Option<usize>,
+    flush_each_chunk: bool,
 ) -> IoResult<()> {
-    if do_chunk {
-        copy_chunked(&mut body.reader, stream)?;
-    } else {
-        copy(&mut body.reader, stream)?;
+    let buffer_size = buffer_size.map(|size| size.max(1));
+    match (do_chunk, buffer_size) {
+        (true, size) => {
+            copy_chunked(
+                &mut body.reader,
+                stream,
+                size.unwrap_or(CHUNK_MAX_PAYLOAD_SIZE),
+                flush_each_chunk,
+            )?;
+        }
+        (false, Some(size)) => {
+            copy_buffered(&mut body.reader, stream, size)?;
+        }
+        (false, None) => {
+            copy(&mut body.reader, stream)?;
+        }
     };
 
     Ok(())
@@ -0,0 +1,57 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// How often a read or write blocked on the socket checks whether it was cancelled.
+pub(crate) const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+/// A handle to cancel requests from another thread.
+///
+/// Give a clone of it to [`set_cancel_handle()`](struct.Request.html#method.set_cancel_handle)
+/// before calling. Once [`cancel()`](#method.cancel) is called, every request using the
+/// handle fails with [`Error::Cancelled`](enum.Error.html#variant.Cancelled) on its next
+/// read or write, and so does reading the body of a response it already got. A read or
+/// write blocked waiting for the server notices within a fraction of a second.
+///
+/// A handle can't be reset, a request started with a cancelled handle fails right away.
+///
+/// ```
+/// use std::net::TcpListener;
+/// use std::thread;
+/// use std::time::Duration;
+///
+/// // a server that never answers.
+/// let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+/// let url = format!("http://{}/", listener.local_addr().unwrap());
+///
+/// let handle = ureq::CancelHandle::new();
+/// let canceller = handle.clone();
+/// thread::spawn(move || {
+///     thread::sleep(Duration::from_millis(100));
+///     canceller.cancel();
+/// });
+///
+/// let resp = ureq::get(&url).set_cancel_handle(handle).call();
+/// assert!(matches!(resp.synthetic_error(), Some(ureq::Error::Cancelled)));
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancelHandle {
+    /// A new handle, not cancelled.
+    pub fn new() -> Self {
+        CancelHandle::default()
+    }
+
+    /// Cancel the requests using this handle, and any made with it later.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Whether [`cancel()`](#method.cancel) has been called.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::HashSet;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use cookie::{Cookie, CookieJar};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use time::OffsetDateTime;
+
+/// Characters that would break a line of cookies.txt, or the `Cookie` header.
+const VALUE_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'%')
+    .add(b',')
+    .add(b';')
+    .add(b'\\');
+
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+/// Write the cookies of a jar in the Netscape cookies.txt format, as read by
+/// curl and wget. Expired cookies are left out. Session cookies are kept, with
+/// an expiry of 0. The cookies named in `host_only` don't include subdomains.
+pub(crate) fn save<W: Write>(
+    jar: &CookieJar,
+    host_only: &HashSet<String>,
+    mut writer: W,
+) -> io::Result<()> {
+    writeln!(writer, "# Netscape HTTP Cookie File")?;
+    let now = OffsetDateTime::now_utc();
+    for c in jar.iter() {
+        let expires = match c.expires() {
+            Some(t) if t <= now => continue,
+            Some(t) => t.unix_timestamp(),
+            None => 0,
+        };
+        // cookies without a domain are never sent, see unit::match_cookies.
+        let domain = match c.domain() {
+            Some(domain) => domain,
+            None => continue,
+        };
+        // like curl, a domain that includes subdomains starts with a dot.
+        let include_subdomains = !host_only.contains(c.name());
+        writeln!(
+            writer,
+            "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}",
+            if c.http_only().unwrap_or(false) {
+                HTTP_ONLY_PREFIX
+            } else {
+                ""
+            },
+            if include_subdomains { "." } else { "" },
+            domain,
+            bool_field(include_subdomains),
+            c.path().unwrap_or("/"),
+            bool_field(c.secure().unwrap_or(false)),
+            expires,
+            utf8_percent_encode(c.name(), VALUE_ENCODE_SET),
+            utf8_percent_encode(c.value(), VALUE_ENCODE_SET),
+        )?;
+    }
+    writer.flush()
+}
+
+/// Add the cookies of a Netscape cookies.txt file to a jar. Expired cookies are
+/// skipped. The names of the cookies that don't include subdomains are added to
+/// `host_only`. A line that isn't a cookie is an `InvalidData` error.
+pub(crate) fn load<R: Read>(
+    jar: &mut CookieJar,
+    host_only: &mut HashSet<String>,
+    reader: R,
+) -> io::Result<()> {
+    let now = OffsetDateTime::now_utc();
+    for (index, line) in BufReader::new(reader).lines().enumerate() {
+        let line = line?;
+        let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+            Some(rest) => (rest, true),
+            None => (&line[..], false),
+        };
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (cookie, include_subdomains) = parse_line(line, http_only).ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Bad cookie on line {}", index + 1),
+            )
+        })?;
+        if cookie.expires().map(|t| t <= now).unwrap_or(false) {
+            continue;
+        }
+        if include_subdomains {
+            host_only.remove(cookie.name());
+        } else {
+            host_only.insert(cookie.name().to_string());
+        }
+        jar.add(cookie);
+    }
+    Ok(())
+}
+
+/// The cookie of a line, and whether it includes subdomains.
+fn parse_line(line: &str, http_only: bool) -> Option<(Cookie<'static>, bool)> {
+    let fields: Vec<&str> = line.split('\t').collect();
+    if fields.len() != 7 {
+        return None;
+    }
+    let name = percent_decode_str(fields[5]).decode_utf8().ok()?;
+    let value = percent_decode_str(fields[6]).decode_utf8().ok()?;
+    let mut cookie = Cookie::new(name.into_owned(), value.into_owned());
+    cookie.set_domain(fields[0].trim_start_matches('.').to_string());
+    let include_subdomains = parse_bool_field(fields[1])?;
+    cookie.set_path(fields[2].to_string());
+    cookie.set_secure(parse_bool_field(fields[3])?);
+    cookie.set_http_only(http_only);
+    match fields[4].parse::<i64>().ok()? {
+        0 => (),
+        expires => cookie.set_expires(OffsetDateTime::from_unix_timestamp(expires)),
+    }
+    Some((cookie, include_subdomains))
+}
+
+fn bool_field(value: bool) -> &'static str {
+    if value {
+        "TRUE"
+    } else {
+        "FALSE"
+    }
+}
+
+fn parse_bool_field(field: &str) -> Option<bool> {
+    match field {
+        "TRUE" => Some(true),
+        "FALSE" => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(jar: &CookieJar) -> CookieJar {
+        round_trip_host_only(jar, &HashSet::new()).0
+    }
+
+    fn round_trip_host_only(
+        jar: &CookieJar,
+        host_only: &HashSet<String>,
+    ) -> (CookieJar, HashSet<String>) {
+        let mut saved = vec![];
+        save(jar, host_only, &mut saved).unwrap();
+        let mut loaded = CookieJar::new();
+        let mut loaded_host_only = HashSet::new();
+        load(&mut loaded, &mut loaded_host_only, &saved[..]).unwrap();
+        (loaded, loaded_host_only)
+    }
+
+    #[test]
+    fn attributes_round_trip() {
+        let mut jar = CookieJar::new();
+        let expires = OffsetDateTime::from_unix_timestamp(4_000_000_000);
+        jar.add(
+            Cookie::build("session", "a b;c")
+                .domain("example.com")
+                .path("/app")
+                .secure(true)
+                .http_only(true)
+                .expires(expires)
+                .finish(),
+        );
+        let loaded = round_trip(&jar);
+        let c = loaded.get("session").unwrap();
+        assert_eq!(c.value(), "a b;c");
+        assert_eq!(c.domain(), Some("example.com"));
+        assert_eq!(c.path(), Some("/app"));
+        assert_eq!(c.secure(), Some(true));
+        assert_eq!(c.http_only(), Some(true));
+        assert_eq!(c.expires(), Some(expires));
+    }
+
+    #[test]
+    fn session_cookie_round_trip() {
+        let mut jar = CookieJar::new();
+        jar.add(Cookie::build("id", "1").domain("example.com").finish());
+        let loaded = round_trip(&jar);
+        let c = loaded.get("id").unwrap();
+        assert_eq!(c.expires(), None);
+        assert_eq!(c.secure(), Some(false));
+        assert_eq!(c.path(), Some("/"));
+    }
+
+    #[test]
+    fn host_only_cookie_round_trip() {
+        let mut jar = CookieJar::new();
+        jar.add(Cookie::build("host", "1").domain("example.com").finish());
+        jar.add(Cookie::build("domain", "2").domain("example.com").finish());
+        let host_only = ["host".to_string()].iter().cloned().collect();
+        let mut saved = vec![];
+        save(&jar, &host_only, &mut saved).unwrap();
+        let saved = String::from_utf8(saved).unwrap();
+        assert!(saved.contains("\nexample.com\tFALSE\t/\tFALSE\t0\thost\t1\n"));
+        assert!(saved.contains("\n.example.com\tTRUE\t/\tFALSE\t0\tdomain\t2\n"));
+
+        let (loaded, loaded_host_only) = round_trip_host_only(&jar, &host_only);
+        assert_eq!(loaded.get("host").unwrap().domain(), Some("example.com"));
+        assert_eq!(loaded.get("domain").unwrap().domain(), Some("example.com"));
+        assert_eq!(loaded_host_only, host_only);
+    }
+
+    #[test]
+    fn expired_cookies_not_saved() {
+        let mut jar = CookieJar::new();
+        jar.add(
+            Cookie::build("old", "1")
+                .domain("example.com")
+                .expires(OffsetDateTime::from_unix_timestamp(1_000_000_000))
+                .finish(),
+        );
+        let mut saved = vec![];
+        save(&jar, &HashSet::new(), &mut saved).unwrap();
+        assert!(!String::from_utf8(saved).unwrap().contains("old"));
+    }
+
+    #[test]
+    fn load_curl_file() {
+        let file = "# Netscape HTTP Cookie File\n\
+                    \n\
+                    .example.com\tTRUE\t/\tFALSE\t0\ta\t1\n\
+                    #HttpOnly_example.com\tFALSE\t/\tTRUE\t0\tb\t2\n\
+                    example.com\tFALSE\t/\tFALSE\t1000000000\tc\t3\n";
+        let mut jar = CookieJar::new();
+        let mut host_only = HashSet::new();
+        load(&mut jar, &mut host_only, file.as_bytes()).unwrap();
+        assert_eq!(jar.get("a").unwrap().domain(), Some("example.com"));
+        assert_eq!(jar.get("b").unwrap().http_only(), Some(true));
+        assert!(jar.get("c").is_none());
+        assert!(!host_only.contains("a"));
+        assert!(host_only.contains("b"));
+    }
+
+    #[test]
+    fn load_bad_line() {
+        let mut jar = CookieJar::new();
+        let err = load(&mut jar, &mut HashSet::new(), "not a cookie\n".as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
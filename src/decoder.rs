@@ -0,0 +1,141 @@
+use std::io::{self, Read};
+
+#[cfg(feature = "brotli")]
+use brotli_decompressor::Decompressor as BrotliDecoder;
+#[cfg(feature = "gzip")]
+use flate2::read::{MultiGzDecoder, ZlibDecoder};
+
+/// The encodings we can decode, as sent in `Accept-Encoding`.
+#[cfg(all(feature = "gzip", not(feature = "brotli")))]
+pub(crate) const ACCEPT_ENCODING: &str = "gzip, deflate";
+#[cfg(all(feature = "brotli", not(feature = "gzip")))]
+pub(crate) const ACCEPT_ENCODING: &str = "br";
+#[cfg(all(feature = "gzip", feature = "brotli"))]
+pub(crate) const ACCEPT_ENCODING: &str = "gzip, deflate, br";
+
+// Same as the buffer size for chunks in the request body.
+#[cfg(feature = "brotli")]
+const BROTLI_BUFFER_SIZE: usize = 0x4000;
+
+type BodyReader<'a> = Box<dyn Read + Send + Sync + 'a>;
+
+/// Wraps a response body reader to decode its `Content-Encoding`.
+///
+/// `identity` is passed through as is, and encodings we can't decode give a reader
+/// that fails with an error rather than the undecoded bytes.
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+pub(crate) fn decode<'a>(encoding: &str, reader: BodyReader<'a>) -> BodyReader<'a> {
+    decode_one("Content-Encoding", encoding, reader)
+}
+
+// The registered transfer codings besides chunked. Others are ignored.
+const TRANSFER_CODINGS: &[&str] = &["gzip", "x-gzip", "deflate", "compress", "x-compress", "br"];
+
+/// Wraps a de-chunked response body reader to undo the other codings of its
+/// `Transfer-Encoding`, the last one listed first. A coding we can't decode, which
+/// is any without the `gzip` or `brotli` features, gives a reader that fails.
+pub(crate) fn decode_transfer<'a>(codings: &[String], reader: BodyReader<'a>) -> BodyReader<'a> {
+    codings
+        .iter()
+        .rev()
+        .filter(|c| TRANSFER_CODINGS.contains(&&c[..]))
+        .fold(reader, |reader, coding| {
+            decode_one("Transfer-Encoding", coding, reader)
+        })
+}
+
+fn decode_one<'a>(header: &'static str, encoding: &str, reader: BodyReader<'a>) -> BodyReader<'a> {
+    if encoding.trim().eq_ignore_ascii_case("identity") {
+        return reader;
+    }
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    {
+        if let Ok(decoder) = Decoder::new(encoding, reader) {
+            return Box::new(decoder);
+        }
+    }
+    Box::new(Unsupported(header, encoding.to_string()))
+}
+
+/// Whether a body in this `Content-Encoding` is decoded by [`decode()`].
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+pub(crate) fn can_decode(encoding: &str) -> bool {
+    Decoder::new(encoding, io::empty()).is_ok()
+}
+
+struct Unsupported(&'static str, String);
+
+impl Read for Unsupported {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported {}: {}", self.0, self.1),
+        ))
+    }
+}
+
+/// Decodes a response body according to its `Content-Encoding`.
+///
+/// *Internal API*
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+pub(crate) enum Decoder<R: Read> {
+    #[cfg(feature = "gzip")]
+    Gzip(MultiGzDecoder<R>),
+    #[cfg(feature = "gzip")]
+    Deflate(ZlibDecoder<R>),
+    #[cfg(feature = "brotli")]
+    // boxed since the brotli state is much larger than the others.
+    Brotli(Box<BrotliDecoder<R>>),
+}
+
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+impl<R: Read> Decoder<R> {
+    /// A decoder for the given `Content-Encoding`, or gives back the reader if
+    /// it's not an encoding we can decode.
+    pub fn new(encoding: &str, reader: R) -> Result<Self, R> {
+        let encoding = encoding.trim().to_ascii_lowercase();
+        match &encoding[..] {
+            #[cfg(feature = "gzip")]
+            "gzip" | "x-gzip" => Ok(Decoder::Gzip(MultiGzDecoder::new(reader))),
+            #[cfg(feature = "gzip")]
+            "deflate" => Ok(Decoder::Deflate(ZlibDecoder::new(reader))),
+            #[cfg(feature = "brotli")]
+            "br" => Ok(Decoder::Brotli(Box::new(BrotliDecoder::new(
+                reader,
+                BROTLI_BUFFER_SIZE,
+            )))),
+            _ => Err(reader),
+        }
+    }
+
+    fn inner(&mut self) -> &mut R {
+        match self {
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(d) => d.get_mut(),
+            #[cfg(feature = "gzip")]
+            Decoder::Deflate(d) => d.get_mut(),
+            #[cfg(feature = "brotli")]
+            Decoder::Brotli(d) => d.get_mut(),
+        }
+    }
+}
+
+#[cfg(any(feature = "gzip", feature = "brotli"))]
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let amount = match self {
+            #[cfg(feature = "gzip")]
+            Decoder::Gzip(d) => d.read(buf)?,
+            #[cfg(feature = "gzip")]
+            Decoder::Deflate(d) => d.read(buf)?,
+            #[cfg(feature = "brotli")]
+            Decoder::Brotli(d) => d.read(buf)?,
+        };
+        if amount == 0 && !buf.is_empty() {
+            // the compressed stream can end before the body it came in. read the
+            // body to the end so the connection can be reused.
+            io::copy(self.inner(), &mut io::sink())?;
+        }
+        Ok(amount)
+    }
+}
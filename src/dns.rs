@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+pub(crate) const DEFAULT_DNS_CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Addresses looked up by the system resolver, kept for reuse by later requests
+/// of the same agent.
+///
+/// std doesn't tell us the TTL of the DNS records, so entries are kept for a fixed
+/// time instead. An entry is dropped when none of its addresses can be connected
+/// to, so that a host that moved is looked up again.
+///
+/// *Internal API*
+#[derive(Debug)]
+pub(crate) struct DnsCache {
+    // "host:port" to the addresses it resolved to, and when.
+    entries: HashMap<String, (Vec<SocketAddr>, Instant)>,
+    // zero turns the cache off.
+    ttl: Duration,
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        DnsCache::new(DEFAULT_DNS_CACHE_TTL)
+    }
+}
+
+impl DnsCache {
+    pub fn new(ttl: Duration) -> Self {
+        DnsCache {
+            entries: HashMap::new(),
+            ttl,
+        }
+    }
+
+    /// The cached addresses of `addr`, unless they're too old.
+    pub fn get(&mut self, addr: &str) -> Option<Vec<SocketAddr>> {
+        let ttl = self.ttl;
+        match self.entries.get(addr) {
+            Some((addrs, resolved)) if resolved.elapsed() < ttl => Some(addrs.clone()),
+            Some(_) => {
+                self.entries.remove(addr);
+                None
+            }
+            None => None,
+        }
+    }
+
+    pub fn insert(&mut self, addr: &str, addrs: &[SocketAddr]) {
+        if self.ttl == Duration::from_secs(0) || addrs.is_empty() {
+            return;
+        }
+        // don't let entries for hosts no longer used pile up.
+        let ttl = self.ttl;
+        self.entries
+            .retain(|_, (_, resolved)| resolved.elapsed() < ttl);
+        self.entries
+            .insert(addr.to_string(), (addrs.to_vec(), Instant::now()));
+    }
+
+    pub fn remove(&mut self, addr: &str) {
+        self.entries.remove(addr);
+    }
+
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+#[test]
+fn dns_cache_expires_entries() {
+    let addrs: Vec<SocketAddr> = vec!["127.0.0.1:80".parse().unwrap()];
+    let mut cache = DnsCache::new(Duration::from_millis(50));
+    cache.insert("example.com:80", &addrs);
+    assert_eq!(cache.get("example.com:80"), Some(addrs));
+    std::thread::sleep(Duration::from_millis(60));
+    assert_eq!(cache.get("example.com:80"), None);
+    assert_eq!(cache.len(), 0);
+}
+
+#[test]
+fn dns_cache_zero_ttl() {
+    let addrs: Vec<SocketAddr> = vec!["127.0.0.1:80".parse().unwrap()];
+    let mut cache = DnsCache::new(Duration::from_secs(0));
+    cache.insert("example.com:80", &addrs);
+    assert_eq!(cache.get("example.com:80"), None);
+}
@@ -1,6 +1,8 @@
 use std::fmt;
 use std::io::Error as IoError;
 
+use crate::response::Response;
+
 /// Errors that are translated to ["synthetic" responses](struct.Response.html#method.synthetic).
 #[derive(Debug)]
 pub enum Error {
@@ -8,10 +10,16 @@ pub enum Error {
     BadUrl(String),
     /// The url scheme could not be understood. Synthetic error `400`.
     UnknownScheme(String),
-    /// DNS lookup failed. Synthetic error `400`.
+    /// DNS lookup failed, or found no address. Holds the host that didn't resolve
+    /// and why, like `example.invalid: failed to lookup address information`, so it can
+    /// be told apart from a refused connection. Synthetic error `400`.
     DnsFailed(String),
     /// Connection to server failed. Synthetic error `500`.
     ConnectionFailed(String),
+    /// The socket could not be bound to the [`local_address()`](struct.Agent.html#method.local_address)
+    /// or [`bind_device()`](struct.Agent.html#method.bind_device) of the agent, for instance
+    /// because the address isn't one of this host. Synthetic error `500`.
+    BindFailed(String),
     /// Too many redirects. Synthetic error `500`.
     TooManyRedirects,
     /// We fail to read the status line. This happens for pooled connections when
@@ -31,17 +39,86 @@ pub enum Error {
     ProxyConnect,
     /// Incorrect credentials for proxy
     InvalidProxyCreds,
+    /// The request body could not be sent again to follow a redirect that keeps the method.
+    /// This happens for bodies from a reader. Synthetic error `500`.
+    BodyNotReplayable,
+    /// A redirect was refused by the [`redirect_fn`](struct.Request.html#method.redirect_fn).
+    /// Synthetic error `500`.
+    RedirectDenied(String),
+    /// The connection was closed while reading the response headers. Like `BadStatusRead`,
+    /// this happens for pooled connections the server closed. Synthetic error `500`.
+    BadHeaderRead,
+    /// A value could not be serialized as JSON for [`send_json()`](struct.Request.html#method.send_json).
+    /// Synthetic error `500`.
+    Json(String),
+    /// A single read waited longer than [`timeout_read()`](struct.Request.html#method.timeout_read).
+    /// Synthetic error `500`.
+    ReadTimeout,
+    /// The overall [`timeout()`](struct.Request.html#method.timeout) passed before the
+    /// request completed. Synthetic error `500`.
+    DeadlineExceeded,
+    /// Connecting to the server took longer than [`timeout_connect()`](struct.Request.html#method.timeout_connect).
+    /// Synthetic error `500`.
+    ConnectTimeout,
+    /// A single write waited longer than [`timeout_write()`](struct.Request.html#method.timeout_write).
+    /// Synthetic error `500`.
+    WriteTimeout,
+    /// The proxy answered `407 Proxy Authentication Required`, because credentials were
+    /// missing or not accepted. Synthetic error `500`.
+    ProxyAuthRequired,
+    /// A client certificate, private key or root certificate could not be used.
+    /// Synthetic error `400`.
+    BadCertificate(String),
+    /// The TLS handshake failed, for example because the server rejected the client
+    /// certificate or its own certificate could not be verified. Synthetic error `500`.
+    TlsHandshake(String),
+    /// Copying the response body with [`copy_to()`](struct.Response.html#method.copy_to) failed
+    /// after writing the given number of bytes. Synthetic error `500`.
+    Download(u64, Box<Error>),
+    /// The server answered with a `4xx` or `5xx` status, or with one other than `101` to
+    /// [`into_upgraded()`](struct.Response.html#method.into_upgraded). The response is kept whole,
+    /// so its headers and body can still be read. Not a synthetic error, turning it
+    /// into a response gives back the one from the server.
+    Status(u16, Box<Response>),
+    /// The response body is larger than the [`max_response_size()`](struct.Agent.html#method.max_response_size)
+    /// of the agent, given in bytes. Synthetic error `500`.
+    ResponseTooLarge(u64),
+    /// A chunk of a chunked response body could not be understood, such as one with a
+    /// size that isn't hex. Synthetic error `500`.
+    BadChunk(String),
+    /// The request was cancelled with its [`CancelHandle`](struct.CancelHandle.html).
+    /// Synthetic error `500`.
+    Cancelled,
+    /// The public key of the server's certificate isn't one of the
+    /// [`pinned_public_keys()`](struct.Agent.html#method.pinned_public_keys). Synthetic error `500`.
+    PinMismatch,
+    /// A redirect went back to a url the request already visited. Synthetic error `500`.
+    RedirectLoop(String),
+    /// The TLS handshake failed because the server doesn't support a TLS version allowed by
+    /// [`min_tls_version()`](struct.Agent.html#method.min_tls_version) and
+    /// [`max_tls_version()`](struct.Agent.html#method.max_tls_version). Synthetic error `500`.
+    TlsVersion(String),
+    /// The connection closed before the whole response body, as long as its `Content-Length`,
+    /// was read. Holds the declared length and the bytes read. Synthetic error `500`.
+    BodyTruncated(u64, u64),
+    /// A line of [`into_json_lines()`](struct.Response.html#method.into_json_lines) could not be
+    /// deserialized. Holds the line number, counting from 1, and what serde said. The lines after
+    /// it are still read. Synthetic error `500`.
+    BadJsonLine(u64, String),
+    /// A response body could not be deserialized by [`into_json()`](struct.Response.html#method.into_json).
+    /// Synthetic error `500`.
+    BadJson(String),
     /// TLS Error
     #[cfg(feature = "native-tls")]
     TlsError(native_tls::Error),
 }
 
 impl Error {
-    // If the error is bad status read, which might happen if a TLS connections is
-    // closed and we only discover it when trying to read the status line from it.
-    pub(crate) fn is_bad_status_read(&self) -> bool {
+    // If the error is bad status or header read, which might happen if a connection is
+    // closed and we only discover it when trying to read the response from it.
+    pub(crate) fn is_closed_connection(&self) -> bool {
         match self {
-            Error::BadStatusRead => true,
+            Error::BadStatusRead | Error::BadHeaderRead => true,
             _ => false,
         }
     }
@@ -53,6 +130,7 @@ impl Error {
             Error::UnknownScheme(_) => 400,
             Error::DnsFailed(_) => 400,
             Error::ConnectionFailed(_) => 500,
+            Error::BindFailed(_) => 500,
             Error::TooManyRedirects => 500,
             Error::BadStatusRead => 500,
             Error::BadStatus => 500,
@@ -62,6 +140,28 @@ impl Error {
             Error::BadProxyCreds => 500,
             Error::ProxyConnect => 500,
             Error::InvalidProxyCreds => 500,
+            Error::BodyNotReplayable => 500,
+            Error::RedirectDenied(_) => 500,
+            Error::BadHeaderRead => 500,
+            Error::Json(_) => 500,
+            Error::ReadTimeout => 500,
+            Error::DeadlineExceeded => 500,
+            Error::ConnectTimeout => 500,
+            Error::WriteTimeout => 500,
+            Error::ProxyAuthRequired => 500,
+            Error::BadCertificate(_) => 400,
+            Error::TlsHandshake(_) => 500,
+            Error::Download(..) => 500,
+            Error::Status(status, _) => *status,
+            Error::ResponseTooLarge(_) => 500,
+            Error::BadChunk(_) => 500,
+            Error::Cancelled => 500,
+            Error::PinMismatch => 500,
+            Error::RedirectLoop(_) => 500,
+            Error::TlsVersion(_) => 500,
+            Error::BodyTruncated(..) => 500,
+            Error::BadJsonLine(..) => 500,
+            Error::BadJson(_) => 500,
             #[cfg(feature = "native-tls")]
             Error::TlsError(_) => 500,
         }
@@ -74,6 +174,7 @@ impl Error {
             Error::UnknownScheme(_) => "Unknown Scheme",
             Error::DnsFailed(_) => "Dns Failed",
             Error::ConnectionFailed(_) => "Connection Failed",
+            Error::BindFailed(_) => "Bind Failed",
             Error::TooManyRedirects => "Too Many Redirects",
             Error::BadStatusRead => "Failed to read status line",
             Error::BadStatus => "Bad Status",
@@ -83,6 +184,28 @@ impl Error {
             Error::BadProxyCreds => "Failed to parse proxy credentials",
             Error::ProxyConnect => "Proxy failed to connect",
             Error::InvalidProxyCreds => "Provided proxy credentials are incorrect",
+            Error::BodyNotReplayable => "Body Not Replayable",
+            Error::RedirectDenied(_) => "Redirect Denied",
+            Error::BadHeaderRead => "Failed to read headers",
+            Error::Json(_) => "JSON Error",
+            Error::ReadTimeout => "Read Timeout",
+            Error::DeadlineExceeded => "Deadline Exceeded",
+            Error::ConnectTimeout => "Connect Timeout",
+            Error::WriteTimeout => "Write Timeout",
+            Error::ProxyAuthRequired => "Proxy Authentication Required",
+            Error::BadCertificate(_) => "Bad Certificate",
+            Error::TlsHandshake(_) => "TLS Handshake Failed",
+            Error::Download(..) => "Download Failed",
+            Error::Status(_, resp) => resp.status_text(),
+            Error::ResponseTooLarge(_) => "Response Too Large",
+            Error::BadChunk(_) => "Bad Chunk",
+            Error::Cancelled => "Cancelled",
+            Error::PinMismatch => "Pin Mismatch",
+            Error::RedirectLoop(_) => "Redirect Loop",
+            Error::TlsVersion(_) => "TLS Version Not Supported",
+            Error::BodyTruncated(..) => "Body Truncated",
+            Error::BadJsonLine(..) => "Bad JSON Line",
+            Error::BadJson(_) => "Bad JSON",
             #[cfg(feature = "native-tls")]
             Error::TlsError(_) => "TLS Error",
         }
@@ -95,6 +218,7 @@ impl Error {
             Error::UnknownScheme(scheme) => format!("Unknown Scheme: {}", scheme),
             Error::DnsFailed(err) => format!("Dns Failed: {}", err),
             Error::ConnectionFailed(err) => format!("Connection Failed: {}", err),
+            Error::BindFailed(err) => format!("Bind Failed: {}", err),
             Error::TooManyRedirects => "Too Many Redirects".to_string(),
             Error::BadStatusRead => "Failed to read status line".to_string(),
             Error::BadStatus => "Bad Status".to_string(),
@@ -104,14 +228,90 @@ impl Error {
             Error::BadProxyCreds => "Failed to parse proxy credentials".to_string(),
             Error::ProxyConnect => "Proxy failed to connect".to_string(),
             Error::InvalidProxyCreds => "Provided proxy credentials are incorrect".to_string(),
+            Error::BodyNotReplayable => "Request body could not be resent for redirect".to_string(),
+            Error::RedirectDenied(url) => format!("Redirect Denied: {}", url),
+            Error::BadHeaderRead => "Failed to read headers".to_string(),
+            Error::Json(err) => format!("JSON Error: {}", err),
+            Error::ReadTimeout => "Timed out waiting to read from the server".to_string(),
+            Error::DeadlineExceeded => {
+                "The request did not complete before the deadline".to_string()
+            }
+            Error::ConnectTimeout => "Timed out connecting to the server".to_string(),
+            Error::WriteTimeout => "Timed out waiting to write to the server".to_string(),
+            Error::ProxyAuthRequired => "Proxy requires authentication".to_string(),
+            Error::BadCertificate(err) => format!("Bad Certificate: {}", err),
+            Error::TlsHandshake(err) => format!("TLS Handshake Failed: {}", err),
+            Error::Download(written, err) => {
+                format!("Download failed after {} bytes: {}", written, err)
+            }
+            Error::Status(status, resp) => format!("Status {} {}", status, resp.status_text()),
+            Error::ResponseTooLarge(max) => format!("Response body larger than {} bytes", max),
+            Error::BadChunk(err) => format!("Bad Chunk: {}", err),
+            Error::Cancelled => "The request was cancelled".to_string(),
+            Error::PinMismatch => "Server public key does not match a pinned key".to_string(),
+            Error::RedirectLoop(url) => format!("Redirect Loop: {}", url),
+            Error::TlsVersion(err) => format!("TLS Version Not Supported: {}", err),
+            Error::BodyTruncated(len, read) => {
+                format!("Response body truncated: {} of {} bytes read", read, len)
+            }
+            Error::BadJsonLine(line, err) => format!("Bad JSON on line {}: {}", line, err),
+            Error::BadJson(err) => format!("Bad JSON: {}", err),
             #[cfg(feature = "native-tls")]
             Error::TlsError(err) => format!("TLS Error: {}", err),
         }
     }
 }
 
+/// The kind of an [`Error`](enum.Error.html), for telling problems with the request,
+/// or with getting an answer, apart from an answer with an error status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    /// The request can't be made as it is, such as with a bad url or certificate.
+    InvalidRequest,
+    /// Talking to the server (or proxy) failed: DNS lookup, connecting, TLS, timeouts,
+    /// a closed connection or a response we don't understand.
+    Transport,
+    /// A redirect could not be followed.
+    Redirect,
+    /// The server answered with a `4xx` or `5xx` status.
+    Status,
+}
+
+impl Error {
+    /// The kind of error this is.
+    ///
+    /// ```
+    /// use ureq::{Error, ErrorKind};
+    ///
+    /// assert_eq!(Error::ConnectTimeout.kind(), ErrorKind::Transport);
+    /// assert_eq!(Error::BadUrl("?".into()).kind(), ErrorKind::InvalidRequest);
+    /// ```
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Error::BadUrl(_)
+            | Error::UnknownScheme(_)
+            | Error::BadProxy
+            | Error::BadProxyCreds
+            | Error::Json(_)
+            | Error::BadCertificate(_) => ErrorKind::InvalidRequest,
+            Error::TooManyRedirects
+            | Error::BodyNotReplayable
+            | Error::RedirectDenied(_)
+            | Error::RedirectLoop(_) => ErrorKind::Redirect,
+            Error::Download(_, err) => err.kind(),
+            Error::Status(..) => ErrorKind::Status,
+            _ => ErrorKind::Transport,
+        }
+    }
+}
+
 impl From<IoError> for Error {
     fn from(err: IoError) -> Error {
+        // DeadlineStream reports timeouts as one of our errors wrapped in an io::Error.
+        let is_ours = err.get_ref().map(|e| e.is::<Error>()).unwrap_or(false);
+        if is_ours {
+            return *err.into_inner().unwrap().downcast::<Error>().unwrap();
+        }
         Error::Io(err)
     }
 }
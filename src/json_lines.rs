@@ -0,0 +1,81 @@
+use std::io::{BufRead, BufReader, Read};
+use std::marker::PhantomData;
+
+use serde::de::DeserializeOwned;
+
+use crate::error::Error;
+
+/// The values of a newline delimited JSON body, made by
+/// [`into_json_lines()`](struct.Response.html#method.into_json_lines).
+///
+/// Each call to `next()` reads one more line of the body, however long it is, and
+/// deserializes it. Blank lines are skipped. A line that can't be deserialized gives an
+/// [`Error::BadJsonLine`](enum.Error.html#variant.BadJsonLine) and the iterator goes on
+/// with the next one, while failing to read the body ends it after that error.
+pub struct JsonLines<T> {
+    reader: BufReader<Box<dyn Read + Send + Sync>>,
+    line: u64,
+    done: bool,
+    _type: PhantomData<fn() -> T>,
+}
+
+impl<T> JsonLines<T> {
+    pub(crate) fn new(reader: Box<dyn Read + Send + Sync>) -> JsonLines<T> {
+        JsonLines {
+            reader: BufReader::new(reader),
+            line: 0,
+            done: false,
+            _type: PhantomData,
+        }
+    }
+}
+
+impl<T: DeserializeOwned> Iterator for JsonLines<T> {
+    type Item = Result<T, Error>;
+
+    fn next(&mut self) -> Option<Result<T, Error>> {
+        let mut buf = vec![];
+        while !self.done {
+            buf.clear();
+            match self.reader.read_until(b'\n', &mut buf) {
+                Ok(0) => self.done = true,
+                Ok(_) => {
+                    self.line += 1;
+                    if buf.iter().all(|b| b.is_ascii_whitespace()) {
+                        continue;
+                    }
+                    let value = serde_json::from_slice(&buf)
+                        .map_err(|e| Error::BadJsonLine(self.line, e.to_string()));
+                    return Some(value);
+                }
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e.into()));
+                }
+            }
+        }
+        None
+    }
+}
+
+#[test]
+fn json_lines() {
+    let body = "{\"n\": 1}\n\r\n  \n{\"n\": 2}\r\nnot json\n{\"n\": 3}";
+    let values: Vec<Result<serde_json::Value, Error>> =
+        JsonLines::new(Box::new(body.as_bytes())).collect();
+    assert_eq!(values.len(), 4);
+    assert_eq!(values[0].as_ref().unwrap()["n"], 1);
+    assert_eq!(values[1].as_ref().unwrap()["n"], 2);
+    assert!(matches!(values[2], Err(Error::BadJsonLine(5, _))));
+    assert_eq!(values[3].as_ref().unwrap()["n"], 3);
+}
+
+#[test]
+fn json_lines_longer_than_buffer() {
+    let long = "x".repeat(100_000);
+    let body = format!("[\"{}\"]\n[\"short\"]\n", long);
+    let values: Vec<Vec<String>> = JsonLines::new(Box::new(std::io::Cursor::new(body)))
+        .map(|v| v.unwrap())
+        .collect();
+    assert_eq!(values, vec![vec![long], vec!["short".to_string()]]);
+}
@@ -47,10 +47,12 @@
 //! which follows a build pattern. The builders are finished using:
 //!
 //! * [`.call()`](struct.Request.html#method.call) without a request body.
-//! * [`.send()`](struct.Request.html#method.send) with a request body as `Read` (chunked encoding).
+//! * [`.send()`](struct.Request.html#method.send) with a request body as `Read` (chunked encoding, or
+//!   `Content-Length` if the length is known).
 //! * [`.send_string()`](struct.Request.html#method.send_string) body as string.
 //! * [`.send_bytes()`](struct.Request.html#method.send_bytes) body as bytes.
 //! * [`.send_form()`](struct.Request.html#method.send_form) key-value pairs as application/x-www-form-urlencoded.
+//! * [`.send_multipart()`](struct.Request.html#method.send_multipart) fields and files as multipart/form-data.
 //!
 //! # JSON
 //!
@@ -58,7 +60,7 @@
 //! the library supports serde json.
 //!
 //! * [`request.send_json()`](struct.Request.html#method.send_json) send body as serde json.
-//! * [`response.into_json()`](struct.Response.html#method.into_json) transform response to json.
+//! * [`response.into_json()`](struct.Response.html#method.into_json) deserialize the response body from json.
 //!
 //! # Agents
 //!
@@ -103,6 +105,36 @@
 //! we first check if the user has set a `; charset=<whatwg charset>` and attempt
 //! to encode the request body using that.
 //!
+//! # Content-Encoding
+//!
+//! By enabling the `ureq = { version = "*", features = ["gzip"] }` feature,
+//! requests are sent with `Accept-Encoding: gzip, deflate` (unless the header is already set),
+//! and response bodies with `Content-Encoding: gzip` or `deflate` are decoded when read.
+//! The `brotli` feature does the same for `br`. The decoder is picked from the
+//! `Content-Encoding` of each response.
+//! This can be turned off with [`agent.compression(false)`](struct.Agent.html#method.compression).
+//! With `gzip`, request bodies can also be sent compressed with
+//! [`send_compressed()`](struct.Request.html#method.send_compressed).
+//!
+//! # Server-Sent Events
+//!
+//! By enabling the `ureq = { version = "*", features = ["sse"] }` feature, a
+//! `text/event-stream` body can be read an event at a time with
+//! [`response.into_events()`](struct.Response.html#method.into_events).
+//!
+//! # Unix sockets
+//!
+//! On unix, a `unix://` url connects to a Unix domain socket. The host is the
+//! percent encoded path of the socket, and the rest of the url is the request as usual:
+//!
+//! ```no_run
+//! # #[cfg(unix)] {
+//! let resp = ureq::get("unix://%2Fvar%2Frun%2Fdocker.sock/containers/json").call();
+//! # }
+//! ```
+//!
+//! Requests over a socket are sent with `Host: localhost`, unless the header is set.
+//!
 //! # Synthetic errors
 //!
 //! Rather than exposing a custom error type through results, this library has opted for
@@ -120,14 +152,28 @@
 
 mod agent;
 mod body;
+mod cancel;
+#[cfg(feature = "cookie")]
+mod cookies;
+mod decoder;
+mod dns;
 mod error;
 mod header;
+#[cfg(feature = "json")]
+mod json_lines;
+mod multipart;
 mod pool;
 mod proxy;
 mod request;
 mod response;
+mod spool;
+#[cfg(feature = "sse")]
+mod sse;
 mod stream;
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+mod tls;
 mod unit;
+mod upgrade;
 
 #[cfg(feature = "json")]
 mod serde_macros;
@@ -136,17 +182,28 @@ mod serde_macros;
 mod test;
 
 pub use crate::agent::Agent;
-pub use crate::error::Error;
+pub use crate::cancel::CancelHandle;
+pub use crate::error::{Error, ErrorKind};
 pub use crate::header::Header;
+#[cfg(feature = "json")]
+pub use crate::json_lines::JsonLines;
+pub use crate::multipart::Multipart;
 pub use crate::proxy::Proxy;
-pub use crate::request::Request;
-pub use crate::response::Response;
+pub use crate::request::{RedirectAction, Request};
+pub use crate::response::{RedirectResponse, Response, Timings, Trailers};
+pub use crate::spool::SeekableBody;
+#[cfg(feature = "sse")]
+pub use crate::sse::{Event, Events};
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+pub use crate::tls::TlsVersion;
+pub use crate::upgrade::Upgraded;
 
 // re-export
 #[cfg(feature = "cookie")]
 pub use cookie::Cookie;
 #[cfg(feature = "json")]
 pub use serde_json::{to_value as serde_to_value, Map as SerdeMap, Value as SerdeValue};
+pub use url::Url;
 
 /// Agents are used to keep state between requests.
 pub fn agent() -> Agent {
@@ -0,0 +1,336 @@
+use std::collections::hash_map::RandomState;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Cursor, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+use crate::body::LenReader;
+
+/// A `multipart/form-data` body of text fields and files, sent with
+/// [`request.send_multipart()`](struct.Request.html#method.send_multipart).
+///
+/// Files are streamed from their reader while the request is sent, so they are
+/// never held in memory as a whole. If the length of every file is known, the
+/// body is sent with a `Content-Length`, otherwise with chunked encoding.
+///
+/// ```no_run
+/// let form = ureq::Multipart::new()
+///     .text("title", "Holiday")
+///     .file_path("photo", "beach.jpg")
+///     .unwrap();
+///
+/// let resp = ureq::post("http://localhost/upload").send_multipart(form);
+/// ```
+pub struct Multipart {
+    boundary: String,
+    parts: Vec<Part>,
+    /// Why a part could not be added, which fails the request before the body is sent.
+    rejected: Option<String>,
+}
+
+struct Part {
+    headers: String,
+    body: Box<dyn Read + 'static>,
+    len: Option<u64>,
+}
+
+impl ::std::fmt::Debug for Multipart {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::result::Result<(), ::std::fmt::Error> {
+        write!(
+            f,
+            "Multipart[boundary={},parts={}]",
+            self.boundary,
+            self.parts.len()
+        )
+    }
+}
+
+impl Default for Multipart {
+    fn default() -> Self {
+        Multipart {
+            boundary: random_boundary(),
+            parts: vec![],
+            rejected: None,
+        }
+    }
+}
+
+impl Multipart {
+    /// An empty form with a random boundary.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add a text field.
+    ///
+    /// ```
+    /// let form = ureq::Multipart::new()
+    ///     .text("name", "martin")
+    ///     .text("rust", "true");
+    /// ```
+    pub fn text(mut self, name: &str, value: &str) -> Self {
+        let headers = format!(
+            "Content-Disposition: form-data; name=\"{}\"\r\n",
+            escape(name)
+        );
+        let value = value.as_bytes().to_vec();
+        let len = value.len() as u64;
+        self.push(headers, Box::new(Cursor::new(value)), Some(len));
+        self
+    }
+
+    /// Add a file read from `reader`, whose length isn't known up front. The
+    /// form is then sent with chunked encoding. A `content_type` with a line break
+    /// fails the request.
+    ///
+    /// ```
+    /// let csv = std::io::Cursor::new("a,b\n1,2\n");
+    /// let form = ureq::Multipart::new()
+    ///     .file("data", "data.csv", "text/csv", csv);
+    /// ```
+    pub fn file(
+        mut self,
+        name: &str,
+        filename: &str,
+        content_type: &str,
+        reader: impl Read + 'static,
+    ) -> Self {
+        if let Some(headers) = self.file_headers(name, filename, content_type) {
+            self.push(headers, Box::new(reader), None);
+        }
+        self
+    }
+
+    /// Add a file read from `reader`, which has exactly `len` bytes. If it ends
+    /// sooner the request fails, rather than leave the server waiting for the rest.
+    pub fn file_with_len(
+        mut self,
+        name: &str,
+        filename: &str,
+        content_type: &str,
+        reader: impl Read + 'static,
+        len: u64,
+    ) -> Self {
+        if let Some(headers) = self.file_headers(name, filename, content_type) {
+            let body = Box::new(LenReader::new(Box::new(reader), len));
+            self.push(headers, body, Some(len));
+        }
+        self
+    }
+
+    /// Add the file at `path`, as `application/octet-stream` and named after the
+    /// last component of the path.
+    pub fn file_path(self, name: &str, path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        let filename = path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(self.file_with_len(name, &filename, "application/octet-stream", file, len))
+    }
+
+    /// The boundary between the parts.
+    pub fn boundary(&self) -> &str {
+        &self.boundary
+    }
+
+    /// The `Content-Type` header for this form.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// The length of the whole body, if the length of every file is known.
+    pub(crate) fn len(&self) -> Option<u64> {
+        let closing = self.closing().len() as u64;
+        self.parts.iter().try_fold(closing, |total, part| {
+            let framing = (self.opening(part).len() + 2) as u64;
+            part.len.map(|len| total + framing + len)
+        })
+    }
+
+    /// A reader of the whole body, ending with the closing boundary.
+    pub(crate) fn into_reader(self) -> impl Read + 'static {
+        let mut readers: VecDeque<Box<dyn Read + 'static>> = VecDeque::new();
+        if let Some(reason) = self.rejected {
+            readers.push_back(Box::new(Rejected(reason)));
+            return ChainReader { readers };
+        }
+        let openings: Vec<String> = self.parts.iter().map(|p| self.opening(p)).collect();
+        let closing = self.closing();
+        for (part, opening) in self.parts.into_iter().zip(openings) {
+            readers.push_back(Box::new(Cursor::new(opening.into_bytes())));
+            readers.push_back(part.body);
+            readers.push_back(Box::new(Cursor::new(&b"\r\n"[..])));
+        }
+        readers.push_back(Box::new(Cursor::new(closing.into_bytes())));
+        ChainReader { readers }
+    }
+
+    /// The headers of a file part, or None if they can't be sent, which rejects the form.
+    fn file_headers(&mut self, name: &str, filename: &str, content_type: &str) -> Option<String> {
+        // the content type would end the header, and inject whatever follows.
+        if content_type.contains(['\r', '\n']) {
+            self.rejected = Some(format!("Bad content type of part {:?}", name));
+            return None;
+        }
+        Some(format!(
+            "Content-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: {}\r\n",
+            escape(name),
+            escape(filename),
+            content_type
+        ))
+    }
+
+    fn push(&mut self, headers: String, body: Box<dyn Read + 'static>, len: Option<u64>) {
+        self.parts.push(Part { headers, body, len });
+    }
+
+    fn opening(&self, part: &Part) -> String {
+        format!("--{}\r\n{}\r\n", self.boundary, part.headers)
+    }
+
+    fn closing(&self) -> String {
+        format!("--{}--\r\n", self.boundary)
+    }
+}
+
+// Names go in quoted strings, which the HTML standard escapes like this.
+fn escape(s: &str) -> String {
+    s.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn random_boundary() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let mut boundary = String::from("------------------------ureq");
+    for _ in 0..2 {
+        // RandomState is seeded randomly, hashing makes the counter unguessable.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+        if let Ok(elapsed) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u128(elapsed.as_nanos());
+        }
+        boundary.push_str(&format!("{:016x}", hasher.finish()));
+    }
+    boundary
+}
+
+/// Fails the body with the reason the form was rejected.
+struct Rejected(String);
+
+impl Read for Rejected {
+    fn read(&mut self, _buf: &mut [u8]) -> io::Result<usize> {
+        Err(io::Error::new(io::ErrorKind::InvalidInput, self.0.clone()))
+    }
+}
+
+/// Reads each reader to its end, in turn.
+struct ChainReader {
+    readers: VecDeque<Box<dyn Read + 'static>>,
+}
+
+impl Read for ChainReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while let Some(reader) = self.readers.front_mut() {
+            match reader.read(buf)? {
+                0 if !buf.is_empty() => {
+                    self.readers.pop_front();
+                }
+                n => return Ok(n),
+            }
+        }
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn body(form: Multipart) -> String {
+        let mut body = String::new();
+        form.into_reader().read_to_string(&mut body).unwrap();
+        body
+    }
+
+    #[test]
+    fn text_and_file_parts() {
+        let form = Multipart::new().text("title", "Holiday").file_with_len(
+            "notes",
+            "notes.txt",
+            "text/plain",
+            &b"hello"[..],
+            5,
+        );
+        let b = form.boundary().to_string();
+        let len = form.len();
+        let body = body(form);
+        assert_eq!(
+            body,
+            format!(
+                "--{b}\r\n\
+                 Content-Disposition: form-data; name=\"title\"\r\n\
+                 \r\n\
+                 Holiday\r\n\
+                 --{b}\r\n\
+                 Content-Disposition: form-data; name=\"notes\"; filename=\"notes.txt\"\r\n\
+                 Content-Type: text/plain\r\n\
+                 \r\n\
+                 hello\r\n\
+                 --{b}--\r\n",
+                b = b
+            )
+        );
+        assert_eq!(len, Some(body.len() as u64));
+    }
+
+    #[test]
+    fn unknown_file_len() {
+        let form = Multipart::new().text("a", "1").file(
+            "f",
+            "f.bin",
+            "application/octet-stream",
+            &b"xyz"[..],
+        );
+        assert_eq!(form.len(), None);
+        assert!(body(form).contains("\r\n\r\nxyz\r\n"));
+    }
+
+    #[test]
+    fn empty_form() {
+        let form = Multipart::new();
+        let closing = format!("--{}--\r\n", form.boundary());
+        assert_eq!(form.len(), Some(closing.len() as u64));
+        assert_eq!(body(form), closing);
+    }
+
+    #[test]
+    fn names_escaped() {
+        let form = Multipart::new().file("a\"b", "x\r\ny.txt", "text/plain", &b""[..]);
+        let body = body(form);
+        assert!(body.contains("name=\"a%22b\"; filename=\"x%0D%0Ay.txt\""));
+    }
+
+    #[test]
+    fn content_type_line_break_rejected() {
+        let form = Multipart::new().text("a", "1").file(
+            "f",
+            "f.txt",
+            "text/plain\r\nX-Injected: 1",
+            &b"hello"[..],
+        );
+        let err = form.into_reader().read(&mut [0; 64]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn boundaries_differ() {
+        assert_ne!(Multipart::new().boundary(), Multipart::new().boundary());
+    }
+}
@@ -1,5 +1,6 @@
 use std::collections::{HashMap, VecDeque};
 use std::io::{Read, Result as IoResult};
+use std::time::{Duration, Instant};
 
 use crate::stream::Stream;
 use crate::unit::Unit;
@@ -8,87 +9,161 @@ use crate::Proxy;
 use url::Url;
 
 pub const DEFAULT_HOST: &str = "localhost";
-const MAX_IDLE_CONNECTIONS: usize = 100;
+pub(crate) const DEFAULT_MAX_IDLE_CONNECTIONS: usize = 100;
+pub(crate) const DEFAULT_MAX_IDLE_CONNECTIONS_PER_HOST: usize = 1;
+pub(crate) const DEFAULT_MAX_IDLE_AGE: Duration = Duration::from_secs(15);
 
 /// Holder of recycled connections.
 ///
-/// Invariant: The length of recycle and lru are the same.
+/// Invariant: The total number of streams in recycle and the length of lru are the same.
 /// Invariant: Each PoolKey exists as a key in recycle, and vice versa.
-/// Invariant: Each PoolKey exists in recycle at most once and lru at most once.
+/// Invariant: Each PoolKey occurs in lru as many times as it has streams in recycle.
 ///
 /// *Internal API*
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub(crate) struct ConnectionPool {
-    // the actual pooled connection. however only one per hostname:port.
-    recycle: HashMap<PoolKey, Stream>,
+    // the actual pooled connections, oldest first, at most
+    // max_idle_connections_per_host per hostname:port. Each
+    // stream is kept with the time it was returned to the pool.
+    recycle: HashMap<PoolKey, VecDeque<(Stream, Instant)>>,
     // This is used to keep track of which streams to expire when the
-    // pool reaches MAX_IDLE_CONNECTIONS. The corresponding PoolKeys for
+    // pool reaches max_idle_connections. The corresponding PoolKeys for
     // recently used Streams are added to the back of the queue;
     // old streams are removed from the front.
     lru: VecDeque<PoolKey>,
+    max_idle_connections: usize,
+    max_idle_connections_per_host: usize,
+    // streams idle for longer than this are closed rather than reused.
+    max_idle_age: Duration,
+}
+
+impl Default for ConnectionPool {
+    fn default() -> Self {
+        ConnectionPool::new(
+            DEFAULT_MAX_IDLE_CONNECTIONS,
+            DEFAULT_MAX_IDLE_CONNECTIONS_PER_HOST,
+            DEFAULT_MAX_IDLE_AGE,
+        )
+    }
 }
 
 impl ConnectionPool {
-    pub fn new() -> Self {
+    pub fn new(
+        max_idle_connections: usize,
+        max_idle_connections_per_host: usize,
+        max_idle_age: Duration,
+    ) -> Self {
         ConnectionPool {
-            ..Default::default()
+            recycle: HashMap::new(),
+            lru: VecDeque::new(),
+            max_idle_connections,
+            max_idle_connections_per_host,
+            max_idle_age,
         }
     }
 
     /// How the unit::connect tries to get a pooled connection.
-    pub fn try_get_connection(&mut self, url: &Url, proxy: &Option<Proxy>) -> Option<Stream> {
-        let key = PoolKey::new(url, proxy);
-        self.remove(&key)
+    pub fn try_get_connection(&mut self, key: &PoolKey) -> Option<Stream> {
+        self.remove_expired();
+        self.remove(key)
+    }
+
+    // Drop the streams that have been idle for longer than max_idle_age.
+    // Streams are added to the back of lru, so the expired ones are all
+    // at the front, and each is the oldest stream for its key.
+    fn remove_expired(&mut self) {
+        let now = Instant::now();
+        while let Some(key) = self.lru.front() {
+            let (_, since) = self.recycle[key].front().unwrap();
+            if now.duration_since(*since) <= self.max_idle_age {
+                break;
+            }
+            self.remove_at(0);
+        }
     }
 
+    // Remove the most recently returned stream for the key, which is
+    // the one most likely to still be open.
     fn remove(&mut self, key: &PoolKey) -> Option<Stream> {
-        if !self.recycle.contains_key(&key) {
-            return None;
+        let streams = self.recycle.get_mut(key)?;
+        let stream = streams.pop_back().map(|(stream, _)| stream);
+        if streams.is_empty() {
+            self.recycle.remove(key);
         }
-        let index = self.lru.iter().position(|k| k == key);
+        let index = self.lru.iter().rposition(|k| k == key);
         assert!(
             index.is_some(),
             "invariant failed: key existed in recycle but not lru"
         );
         self.lru.remove(index.unwrap());
-        self.recycle.remove(&key)
+        stream
     }
 
     fn add(&mut self, key: PoolKey, stream: Stream) {
-        // If an entry with the same key already exists, remove it.
-        // The more recently used stream is likely to live longer.
-        self.remove(&key);
-        if self.recycle.len() + 1 > MAX_IDLE_CONNECTIONS {
-            self.remove_oldest();
+        if self.max_idle_connections == 0 || self.max_idle_connections_per_host == 0 {
+            return;
+        }
+        // If the host is at its limit, make room by evicting its oldest
+        // stream. The more recently used stream is likely to live longer.
+        if self.host_len(&key) >= self.max_idle_connections_per_host {
+            let index = self.lru.iter().position(|k| k == &key).unwrap();
+            self.remove_at(index);
+        }
+        if self.lru.len() >= self.max_idle_connections {
+            self.remove_at(0);
         }
         self.lru.push_back(key.clone());
-        self.recycle.insert(key, stream);
+        self.recycle
+            .entry(key)
+            .or_default()
+            .push_back((stream, Instant::now()));
+    }
+
+    fn host_len(&self, key: &PoolKey) -> usize {
+        self.recycle.get(key).map(|s| s.len()).unwrap_or(0)
     }
 
-    fn remove_oldest(&mut self) {
-        if let Some(key) = self.lru.pop_front() {
-            let removed = self.recycle.remove(&key);
-            assert!(
-                removed.is_some(),
-                "invariant failed: key existed in lru but not in recycle"
-            );
-        } else {
-            panic!("tried to remove oldest but no entries found!");
+    // Remove the entry at the index in lru, along with the oldest stream
+    // for its key.
+    fn remove_at(&mut self, index: usize) {
+        let key = self.lru.remove(index).expect("no entry in lru at index");
+        let streams = self.recycle.get_mut(&key);
+        assert!(
+            streams.is_some(),
+            "invariant failed: key existed in lru but not in recycle"
+        );
+        let streams = streams.unwrap();
+        streams.pop_front();
+        if streams.is_empty() {
+            self.recycle.remove(&key);
         }
     }
 
     #[cfg(test)]
     pub fn len(&self) -> usize {
-        self.recycle.len()
+        self.lru.len()
     }
 }
 
+/// What a pooled connection was made for. It's only handed out for the same
+/// destination, through the same proxy, with the same TLS settings.
 #[derive(PartialEq, Clone, Eq, Hash)]
-struct PoolKey {
+pub(crate) struct PoolKey {
     scheme: String,
     hostname: String,
     port: Option<u16>,
     proxy: Option<Proxy>,
+    tls: TlsKey,
+}
+
+/// The TLS settings a connection was set up with. The config is compared by
+/// identity, since requests of one agent share the one it built.
+#[derive(PartialEq, Clone, Eq, Hash, Default)]
+struct TlsKey {
+    config: Option<usize>,
+    server_name: Option<String>,
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pinned_keys: Vec<[u8; 32]>,
 }
 
 use std::fmt;
@@ -105,15 +180,51 @@ impl fmt::Debug for PoolKey {
 }
 
 impl PoolKey {
-    fn new(url: &Url, proxy: &Option<Proxy>) -> Self {
+    pub(crate) fn new(url: &Url, proxy: &Option<Proxy>) -> Self {
         let port = url.port_or_known_default();
         PoolKey {
             scheme: url.scheme().to_string(),
             hostname: url.host_str().unwrap_or("").to_string(),
             port,
             proxy: proxy.clone(),
+            tls: TlsKey::default(),
         }
     }
+
+    /// The key of the connections a unit can use.
+    pub(crate) fn for_unit(unit: &Unit) -> Self {
+        let mut key = PoolKey::new(&unit.url, &unit.proxy);
+        if unit.url.scheme() == "https" {
+            key.tls = TlsKey::for_unit(unit);
+        }
+        key
+    }
+}
+
+impl TlsKey {
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    fn for_unit(unit: &Unit) -> Self {
+        #[cfg(feature = "tls")]
+        let config = unit
+            .tls_config
+            .as_ref()
+            .map(|c| std::sync::Arc::as_ptr(&c.0) as usize);
+        #[cfg(all(feature = "native-tls", not(feature = "tls")))]
+        let config = unit
+            .tls_connector
+            .as_ref()
+            .map(crate::request::TLSConnector::id);
+        TlsKey {
+            config,
+            server_name: unit.tls_server_name.clone(),
+            pinned_keys: unit.pinned_keys.clone(),
+        }
+    }
+
+    #[cfg(not(any(feature = "tls", feature = "native-tls")))]
+    fn for_unit(_unit: &Unit) -> Self {
+        TlsKey::default()
+    }
 }
 
 #[test]
@@ -124,14 +235,15 @@ fn poolkey_new() {
 
 #[test]
 fn pool_size_limit() {
-    assert_eq!(MAX_IDLE_CONNECTIONS, 100);
-    let mut pool = ConnectionPool::new();
+    assert_eq!(DEFAULT_MAX_IDLE_CONNECTIONS, 100);
+    let mut pool = ConnectionPool::default();
     let hostnames = (0..200).map(|i| format!("{}.example", i));
     let poolkeys = hostnames.map(|hostname| PoolKey {
         scheme: "https".to_string(),
         hostname,
         port: Some(999),
         proxy: None,
+        tls: TlsKey::default(),
     });
     for key in poolkeys.clone() {
         pool.add(key, Stream::Cursor(std::io::Cursor::new(vec![])));
@@ -149,14 +261,15 @@ fn pool_duplicates_limit() {
     // Test inserting duplicates into the pool, and subsequently
     // filling and draining it. The duplicates should evict earlier
     // entries with the same key.
-    assert_eq!(MAX_IDLE_CONNECTIONS, 100);
-    let mut pool = ConnectionPool::new();
+    assert_eq!(DEFAULT_MAX_IDLE_CONNECTIONS, 100);
+    let mut pool = ConnectionPool::default();
     let hostnames = (0..100).map(|i| format!("{}.example", i));
     let poolkeys = hostnames.map(|hostname| PoolKey {
         scheme: "https".to_string(),
         hostname,
         port: Some(999),
         proxy: None,
+        tls: TlsKey::default(),
     });
     for key in poolkeys.clone() {
         pool.add(key.clone(), Stream::Cursor(std::io::Cursor::new(vec![])));
@@ -170,11 +283,43 @@ fn pool_duplicates_limit() {
     }
 }
 
+#[cfg(feature = "tls")]
+#[test]
+fn pool_checks_tls_settings() {
+    let agent = crate::agent().build();
+    let body = crate::body::Payload::Empty.into_read();
+    let key = |req: &crate::Request| {
+        let url = req.to_url().unwrap();
+        PoolKey::for_unit(&Unit::new(req, &url, false, &body, false))
+    };
+    let plain = key(&agent.get("https://example.com/"));
+    assert_eq!(plain, key(&agent.get("https://example.com/other")));
+
+    let named = key(agent
+        .get("https://example.com/")
+        .tls_server_name("cdn.example.com"));
+    assert_ne!(plain, named);
+
+    let config = std::sync::Arc::new(rustls::ClientConfig::new());
+    let custom = key(agent
+        .get("https://example.com/")
+        .set_tls_config(config.clone()));
+    assert_ne!(plain, custom);
+    let same = key(agent.get("https://example.com/").set_tls_config(config));
+    assert_eq!(custom, same);
+
+    // plain http has no TLS settings to keep apart.
+    let http = key(agent
+        .get("http://example.com/")
+        .tls_server_name("cdn.example.com"));
+    assert_eq!(http, key(&agent.get("http://example.com/")));
+}
+
 #[test]
 fn pool_checks_proxy() {
     // Test inserting different poolkeys with same address but different proxies.
     // Each insertion should result in an additional entry in the pool.
-    let mut pool = ConnectionPool::new();
+    let mut pool = ConnectionPool::default();
     let url = Url::parse("zzz:///example.com").unwrap();
 
     pool.add(
@@ -199,6 +344,96 @@ fn pool_checks_proxy() {
     assert_eq!(pool.len(), 3);
 }
 
+#[cfg(test)]
+fn cursor_with(data: &[u8]) -> Stream {
+    Stream::Cursor(std::io::Cursor::new(data.to_vec()))
+}
+
+#[cfg(test)]
+fn cursor_data(stream: Stream) -> Vec<u8> {
+    match stream {
+        Stream::Cursor(cursor) => cursor.into_inner(),
+        _ => panic!("expected a cursor stream"),
+    }
+}
+
+#[test]
+fn pool_per_host_limit() {
+    // Keep up to two streams per host, evicting the oldest, and hand
+    // out the most recently returned one first.
+    let mut pool = ConnectionPool::new(100, 2, DEFAULT_MAX_IDLE_AGE);
+    let url = Url::parse("https://example.com/").unwrap();
+    let key = PoolKey::new(&url, &None);
+    pool.add(key.clone(), cursor_with(b"1"));
+    pool.add(key.clone(), cursor_with(b"2"));
+    pool.add(key.clone(), cursor_with(b"3"));
+    assert_eq!(pool.len(), 2);
+
+    let other = Url::parse("https://other.example/").unwrap();
+    pool.add(PoolKey::new(&other, &None), cursor_with(b"other"));
+    assert_eq!(pool.len(), 3);
+
+    let first = pool.try_get_connection(&PoolKey::new(&url, &None)).unwrap();
+    assert_eq!(cursor_data(first), b"3");
+    let second = pool.try_get_connection(&PoolKey::new(&url, &None)).unwrap();
+    assert_eq!(cursor_data(second), b"2");
+    assert!(pool
+        .try_get_connection(&PoolKey::new(&url, &None))
+        .is_none());
+    assert_eq!(pool.len(), 1);
+}
+
+#[test]
+fn pool_total_limit_evicts_oldest() {
+    let mut pool = ConnectionPool::new(3, 2, DEFAULT_MAX_IDLE_AGE);
+    let a = Url::parse("https://a.example/").unwrap();
+    let b = Url::parse("https://b.example/").unwrap();
+    pool.add(PoolKey::new(&a, &None), cursor_with(b"a1"));
+    pool.add(PoolKey::new(&b, &None), cursor_with(b"b1"));
+    pool.add(PoolKey::new(&a, &None), cursor_with(b"a2"));
+    // full, so the oldest (a1) goes.
+    pool.add(PoolKey::new(&b, &None), cursor_with(b"b2"));
+    assert_eq!(pool.len(), 3);
+
+    let got = pool.try_get_connection(&PoolKey::new(&a, &None)).unwrap();
+    assert_eq!(cursor_data(got), b"a2");
+    assert!(pool.try_get_connection(&PoolKey::new(&a, &None)).is_none());
+    assert_eq!(pool.len(), 2);
+}
+
+#[test]
+fn pool_disabled() {
+    let mut pool = ConnectionPool::new(0, 1, DEFAULT_MAX_IDLE_AGE);
+    let url = Url::parse("https://example.com/").unwrap();
+    pool.add(PoolKey::new(&url, &None), cursor_with(b""));
+    assert_eq!(pool.len(), 0);
+}
+
+#[test]
+fn pool_evicts_idle_connections() {
+    let mut pool = ConnectionPool::new(100, 2, Duration::from_millis(50));
+    let a = Url::parse("https://a.example/").unwrap();
+    let b = Url::parse("https://b.example/").unwrap();
+    pool.add(PoolKey::new(&a, &None), cursor_with(b"old"));
+    std::thread::sleep(Duration::from_millis(100));
+    pool.add(PoolKey::new(&a, &None), cursor_with(b"new"));
+    pool.add(PoolKey::new(&b, &None), cursor_with(b"b"));
+
+    // the expired stream is dropped, the fresh ones are kept.
+    let got = pool.try_get_connection(&PoolKey::new(&b, &None)).unwrap();
+    assert_eq!(cursor_data(got), b"b");
+    assert_eq!(pool.len(), 1);
+    let got = pool.try_get_connection(&PoolKey::new(&a, &None)).unwrap();
+    assert_eq!(cursor_data(got), b"new");
+    assert!(pool.try_get_connection(&PoolKey::new(&a, &None)).is_none());
+
+    // once the only candidate expires, there is nothing to hand out.
+    pool.add(PoolKey::new(&a, &None), cursor_with(b"again"));
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(pool.try_get_connection(&PoolKey::new(&a, &None)).is_none());
+    assert_eq!(pool.len(), 0);
+}
+
 /// Read wrapper that returns the stream to the pool once the
 /// read is exhausted (reached a 0).
 ///
@@ -230,7 +465,7 @@ impl<R: Read + Sized + Into<Stream>> PoolReturnRead<R> {
                     return;
                 }
                 // insert back into pool
-                let key = PoolKey::new(&unit.url, &unit.proxy);
+                let key = PoolKey::for_unit(&unit);
                 agent.pool().add(key, stream);
             }
         }
@@ -246,7 +481,14 @@ impl<R: Read + Sized + Into<Stream>> PoolReturnRead<R> {
 
 impl<R: Read + Sized + Into<Stream>> Read for PoolReturnRead<R> {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
-        let amount = self.do_read(buf)?;
+        let amount = match self.do_read(buf) {
+            Ok(amount) => amount,
+            Err(e) => {
+                // the body wasn't read to its end, so the connection can't be reused.
+                self.unit.take();
+                return Err(e);
+            }
+        };
         // only if the underlying reader is exhausted can we send a new
         // request to the same socket. hence, we only return it now.
         if amount == 0 {
@@ -1,23 +1,43 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
 use std::io::Read;
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
 use std::time;
 
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
 use qstring::QString;
 use url::{form_urlencoded, Url};
 
-#[cfg(feature = "tls")]
+#[cfg(any(feature = "tls", feature = "native-tls"))]
 use std::fmt;
 
-use crate::agent::{self, Agent, AgentState};
-use crate::body::{Payload, SizedReader};
+use crate::agent::{self, Agent, AgentState, Resolver};
+use crate::body::{Payload, PullReader, SizedReader};
+use crate::cancel::CancelHandle;
 use crate::error::Error;
 use crate::header::{self, Header};
+use crate::multipart::Multipart;
 use crate::pool;
 use crate::unit::{self, Unit};
 use crate::Response;
 
 #[cfg(feature = "json")]
-use super::SerdeValue;
+use serde::Serialize;
+
+/// What to do with a redirect, as decided by a [`redirect_fn`](struct.Request.html#method.redirect_fn).
+#[derive(Debug, Clone, PartialEq)]
+pub enum RedirectAction {
+    /// Follow the redirect to the given url.
+    Follow(Url),
+    /// Don't follow, and return the 3xx response as is.
+    Stop,
+    /// Don't follow, and produce a synthetic error response instead.
+    Error,
+}
+
+pub(crate) type RedirectFn = dyn Fn(&Url, u16, &Url) -> RedirectAction + Send + Sync;
 
 /// Request instances are builders that creates a request.
 ///
@@ -38,15 +58,43 @@ pub struct Request {
 
     // from request itself
     pub(crate) headers: Vec<Header>,
+    pub(crate) default_headers: Vec<Header>,
     pub(crate) query: QString,
+    pub(crate) header_order: Vec<String>,
     pub(crate) timeout_connect: u64,
     pub(crate) timeout_read: u64,
     pub(crate) timeout_write: u64,
     pub(crate) timeout: Option<time::Duration>,
+    pub(crate) expect_continue: Option<time::Duration>,
+    pub(crate) flush_each_chunk: bool,
     pub(crate) redirects: u32,
+    pub(crate) redirect_fn: Option<Arc<RedirectFn>>,
+    pub(crate) keep_redirect_responses: Option<usize>,
+    pub(crate) retries: u32,
+    pub(crate) max_retry_wait: time::Duration,
+    pub(crate) pool_retries: u32,
+    pub(crate) max_bytes_per_sec: Option<u64>,
+    pub(crate) max_response_size: Option<u64>,
+    pub(crate) buffer_size: Option<usize>,
+    pub(crate) keepalive: bool,
+    pub(crate) no_cookies: bool,
+    pub(crate) tcp_nodelay: bool,
+    pub(crate) tcp_keepalive: Option<time::Duration>,
+    pub(crate) local_address: Option<IpAddr>,
+    pub(crate) bind_device: Option<String>,
+    pub(crate) resolver: Option<Resolver>,
+    pub(crate) cancel: Option<CancelHandle>,
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    pub(crate) compression: bool,
     pub(crate) proxy: Option<crate::proxy::Proxy>,
     #[cfg(feature = "tls")]
     pub(crate) tls_config: Option<TLSClientConfig>,
+    #[cfg(feature = "native-tls")]
+    pub(crate) tls_connector: Option<TLSConnector>,
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub(crate) tls_server_name: Option<String>,
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub(crate) pinned_keys: Vec<[u8; 32]>,
 }
 
 impl ::std::fmt::Debug for Request {
@@ -73,7 +121,29 @@ impl Request {
             method,
             url,
             headers: agent.headers.clone(),
-            redirects: 5,
+            default_headers: agent.default_headers.clone(),
+            max_bytes_per_sec: agent.max_bytes_per_sec,
+            max_response_size: agent.max_response_size,
+            buffer_size: agent.buffer_size,
+            keepalive: agent.keepalive,
+            tcp_nodelay: agent.tcp_nodelay,
+            tcp_keepalive: agent.tcp_keepalive,
+            local_address: agent.local_address,
+            bind_device: agent.bind_device.clone(),
+            resolver: agent.resolver.clone(),
+            #[cfg(any(feature = "gzip", feature = "brotli"))]
+            compression: agent.compression,
+            #[cfg(feature = "tls")]
+            tls_config: agent.tls_config.clone(),
+            #[cfg(feature = "native-tls")]
+            tls_connector: agent.tls_connector.clone(),
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            pinned_keys: agent.tls.pinned_keys.clone(),
+            timeout: agent.timeout,
+            redirects: agent.redirects,
+            proxy: agent.proxy.clone(),
+            pool_retries: 1,
+            max_retry_wait: DEFAULT_MAX_RETRY_WAIT,
             ..Default::default()
         }
     }
@@ -110,16 +180,33 @@ impl Request {
         self.to_url()
             .and_then(|url| {
                 let reader = payload.into_read();
-                let unit = Unit::new(&self, &url, true, &reader);
-                unit::connect(&self, unit, true, 0, reader, false)
+                let unit = Unit::new(&self, &url, true, &reader, false);
+                unit::connect(&self, unit, true, 0, reader)
             })
             .unwrap_or_else(|e| e.into())
     }
 
+    /// Like [`call()`](#method.call), but a failed request, or a response with a `4xx`
+    /// or `5xx` status, is an `Err`. See [`Response::into_result()`](struct.Response.html#method.into_result).
+    ///
+    /// ```no_run
+    /// fn fetch() -> Result<String, ureq::Error> {
+    ///     let resp = ureq::get("http://example.com/my_page").try_call()?;
+    ///     Ok(resp.into_string()?)
+    /// }
+    /// ```
+    pub fn try_call(&mut self) -> Result<Response, Error> {
+        self.call().into_result()
+    }
+
     /// Send data a json value.
     ///
     /// Requires feature `ureq = { version = "*", features = ["json"] }`
     ///
+    /// The data can be a `serde_json::Value` or anything else implementing the (serde)
+    /// Serialize trait. If serializing fails, the response is a synthetic 500 error.
+    ///
+    /// The `Content-Type` header is set to `application/json` unless it's already set.
     /// The `Content-Length` header is implicitly set to the length of the serialized value.
     ///
     /// ```
@@ -132,17 +219,34 @@ impl Request {
     /// println!("{:?}", r);
     /// }
     /// ```
+    ///
+    /// ```
+    /// # use serde::Serialize;
+    /// #[derive(Serialize)]
+    /// struct Hello {
+    ///     hello: String,
+    /// }
+    ///
+    /// let hello = Hello { hello: "world".into() };
+    /// let r = ureq::post("/my_page").send_json(&hello);
+    /// println!("{:?}", r);
+    /// ```
     #[cfg(feature = "json")]
-    pub fn send_json(&mut self, data: SerdeValue) -> Response {
+    pub fn send_json(&mut self, data: impl Serialize) -> Response {
         if self.header("Content-Type").is_none() {
             self.set("Content-Type", "application/json");
         }
-        self.do_call(Payload::JSON(data))
+        match serde_json::to_value(data) {
+            Ok(value) => self.do_call(Payload::JSON(value)),
+            Err(e) => Error::Json(e.to_string()).into(),
+        }
     }
 
     /// Send data as bytes.
     ///
-    /// The `Content-Length` header is implicitly set to the length of the serialized value.
+    /// The `Content-Length` header is implicitly set to the length of the serialized value,
+    /// also when it's `0`. The body is kept in memory, so a request with an idempotent
+    /// method can be retried.
     ///
     /// ```
     /// let body = b"Hello world!";
@@ -184,8 +288,11 @@ impl Request {
 
     /// Send a sequence of (key, value) pairs as form-urlencoded data.
     ///
+    /// The pairs are encoded as HTML forms do, with `+` for spaces rather than the
+    /// `%20` of [`query()`](#method.query).
     /// The `Content-Type` header is implicitly set to application/x-www-form-urlencoded.
-    /// The `Content-Length` header is implicitly set to the length of the serialized value.
+    /// The `Content-Length` header is implicitly set to the length of the serialized value,
+    /// `0` for an empty form.
     ///
     /// ```
     /// #[macro_use]
@@ -209,7 +316,11 @@ impl Request {
 
     /// Send data from a reader.
     ///
-    /// This uses [chunked transfer encoding](https://tools.ietf.org/html/rfc7230#section-4.1).
+    /// If the length of the data is known, pass it as `len` and the body is sent with
+    /// a `Content-Length` header. Exactly `len` bytes are read, and the request fails
+    /// if the reader ends early rather than sending a truncated body.
+    ///
+    /// Without a length, this uses [chunked transfer encoding](https://tools.ietf.org/html/rfc7230#section-4.1).
     /// The caller is responsible for setting the Transfer-Encoding: chunked header.
     ///
     /// The input from the reader is buffered into chunks of size 16,384, the max size of a TLS fragment.
@@ -222,14 +333,117 @@ impl Request {
     /// let resp = ureq::post("http://localhost/example-upload")
     ///     .set("Content-Type", "text/plain")
     ///     .set("Transfer-Encoding", "chunked")
-    ///     .send(read);
+    ///     .send(read, None);
+    ///
+    /// let read = Cursor::new(vec![0x20; 100_000]);
+    ///
+    /// let resp = ureq::put("http://localhost/example-upload")
+    ///     .send(read, Some(100_000));
+    /// ```
+    pub fn send(&mut self, reader: impl Read + 'static, len: Option<u64>) -> Response {
+        self.do_call(Payload::Reader(Box::new(reader), len))
+    }
+
+    /// Send a body produced by a closure, for bodies generated on the fly without a
+    /// `Read` to give [`send()`](#method.send).
+    ///
+    /// The closure is called with an empty buffer to append the next part of the body
+    /// to, and returns `Ok(true)` while there is more to come, or `Ok(false)` with the
+    /// last part. An error from it fails the request. The body goes with chunked
+    /// transfer encoding, unless `Content-Length` or `Transfer-Encoding` are set.
+    ///
+    /// ```
+    /// let mut rows = vec!["a,1\n", "b,2\n"].into_iter();
+    ///
+    /// let resp = ureq::post("http://localhost/upload.csv")
+    ///     .set("Content-Type", "text/csv")
+    ///     .send_with(move |buf: &mut Vec<u8>| {
+    ///         match rows.next() {
+    ///             Some(row) => {
+    ///                 buf.extend_from_slice(row.as_bytes());
+    ///                 Ok(true)
+    ///             }
+    ///             None => Ok(false),
+    ///         }
+    ///     });
     /// ```
-    pub fn send(&mut self, reader: impl Read + 'static) -> Response {
-        self.do_call(Payload::Reader(Box::new(reader)))
+    pub fn send_with<F>(&mut self, produce: F) -> Response
+    where
+        F: FnMut(&mut Vec<u8>) -> std::io::Result<bool> + 'static,
+    {
+        if !self.has("Content-Length") && !self.has("Transfer-Encoding") {
+            self.set("Transfer-Encoding", "chunked");
+        }
+        let reader = PullReader::new(produce);
+        self.do_call(Payload::Reader(Box::new(reader), None))
+    }
+
+    /// Send data from a reader compressed with gzip, as `Content-Encoding: gzip`.
+    ///
+    /// Requires feature `ureq = { version = "*", features = ["gzip"] }`
+    ///
+    /// The body is compressed while it's sent. Its compressed length isn't known
+    /// ahead, so it goes with chunked transfer encoding, unless `Content-Length` or
+    /// `Transfer-Encoding` are set. Like any body from a reader, it can't be sent
+    /// again for a retry or a 307/308 redirect.
+    ///
+    /// Whether the server accepts gzip bodies is up to it.
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    ///
+    /// let read = Cursor::new(r#"{"large": "json"}"#);
+    ///
+    /// let resp = ureq::post("http://localhost/example-upload")
+    ///     .set("Content-Type", "application/json")
+    ///     .send_compressed(read);
+    /// ```
+    #[cfg(feature = "gzip")]
+    pub fn send_compressed(&mut self, reader: impl Read + 'static) -> Response {
+        use flate2::read::GzEncoder;
+        use flate2::Compression;
+
+        if !self.has("Content-Encoding") {
+            self.set("Content-Encoding", "gzip");
+        }
+        if !self.has("Content-Length") && !self.has("Transfer-Encoding") {
+            self.set("Transfer-Encoding", "chunked");
+        }
+        let encoder = GzEncoder::new(reader, Compression::default());
+        self.do_call(Payload::Reader(Box::new(encoder), None))
+    }
+
+    /// Send a `multipart/form-data` body of text fields and files.
+    ///
+    /// The `Content-Type` header is set to `multipart/form-data` with the form's
+    /// boundary, unless it's already set. If the length of every file is known,
+    /// the `Content-Length` header is set, otherwise the body is sent with
+    /// chunked transfer encoding.
+    ///
+    /// ```
+    /// let form = ureq::Multipart::new()
+    ///     .text("title", "Holiday")
+    ///     .file("notes", "notes.txt", "text/plain", std::io::Cursor::new("Sunny"));
+    ///
+    /// let r = ureq::post("/my_page").send_multipart(form);
+    /// println!("{:?}", r);
+    /// ```
+    pub fn send_multipart(&mut self, form: Multipart) -> Response {
+        if self.header("Content-Type").is_none() {
+            self.set("Content-Type", &form.content_type());
+        }
+        if form.len().is_none() && !self.has("Content-Length") && !self.has("Transfer-Encoding") {
+            self.set("Transfer-Encoding", "chunked");
+        }
+        self.do_call(Payload::Multipart(form))
     }
 
     /// Set a header field.
     ///
+    /// Setting `User-Agent` replaces the default `ureq` one. Set it to `""` to
+    /// send no `User-Agent` at all. The same goes for `Host`, which otherwise is
+    /// the host and port of the url, e.g. to test how a server routes virtual hosts.
+    ///
     /// ```
     /// let r = ureq::get("/my_page")
     ///     .set("X-API-Key", "foobar")
@@ -247,6 +461,146 @@ impl Request {
         self
     }
 
+    /// A key to cache the response to this request under, made of the method, the url
+    /// and the values of the request headers named in the `Vary` header of the response.
+    /// The headers are the ones that would be sent, including the agent's defaults, its
+    /// cookies and the `Accept-Encoding` ureq adds. The key is the same for requests that
+    /// would get the same response, and `None` if the url is bad or for `Vary: *`.
+    ///
+    /// ```
+    /// let mut req = ureq::get("http://example.com/page?q=1");
+    /// req.set("Accept-Encoding", "gzip");
+    ///
+    /// let key = req.cache_key(Some("Accept-Encoding")).unwrap();
+    /// assert_eq!(key, "GET http://example.com/page?q=1\naccept-encoding: gzip");
+    /// assert_eq!(req.cache_key(Some("*")), None);
+    /// ```
+    pub fn cache_key(&self, vary: Option<&str>) -> Option<String> {
+        let url = self.to_url().ok()?;
+        let body = Payload::Empty.into_read();
+        Unit::new(self, &url, true, &body, false).cache_key(vary.unwrap_or(""))
+    }
+
+    /// Send the headers in this order, given by name in any case. The ones ureq adds,
+    /// such as `Host`, `User-Agent`, `Accept` and `Content-Length`, are placed like any
+    /// other. Headers not in the list come after the listed ones, in the order they
+    /// would be sent without it.
+    ///
+    /// Without an order, the headers ureq adds come first and the ones set on the
+    /// request follow in the order they were first set. The name is sent spelled as
+    /// in the last [`set()`](#method.set).
+    ///
+    /// ```
+    /// let r = ureq::get("/my_page")
+    ///     .set("accept", "text/html")
+    ///     .set("accept-language", "en")
+    ///     .header_order(&["host", "user-agent", "accept", "accept-language"])
+    ///     .call();
+    /// ```
+    pub fn header_order(&mut self, names: &[&str]) -> &mut Request {
+        self.header_order = names.iter().map(|n| n.to_ascii_lowercase()).collect();
+        self
+    }
+
+    /// Ask for a part of the resource with the `Range` header, from byte `start` up to
+    /// and including byte `end`, or to the end of the resource when `end` is `None`.
+    ///
+    /// A server that honors the range answers `206 Partial Content`, which counts as
+    /// [`ok()`](struct.Response.html#method.ok), and the body read from the response is
+    /// only the requested slice. See [`content_range()`](struct.Response.html#method.content_range)
+    /// for the range it sent. A server may also ignore the header and send the whole
+    /// resource with a `200`.
+    ///
+    /// ```
+    /// // resume a download after the first 1000 bytes.
+    /// let r = ureq::get("/my_file")
+    ///     .set_range(1000, None)
+    ///     .call();
+    ///
+    /// if r.status() == 206 {
+    ///     println!("got {:?}", r.content_range());
+    /// }
+    /// ```
+    pub fn set_range(&mut self, start: u64, end: Option<u64>) -> &mut Request {
+        let range = match end {
+            Some(end) => format!("bytes={}-{}", start, end),
+            None => format!("bytes={}-", start),
+        };
+        self.set("Range", &range)
+    }
+
+    /// Set an `Idempotency-Key` header with a random UUID, so a server that supports
+    /// it can tell a retry from a new request. Does nothing if the header is set.
+    ///
+    /// The key is set on the request once, so every attempt sends the same one:
+    /// [`retries()`](#method.retries), and sending again on a new connection when a
+    /// pooled one turns out closed. It also makes requests with methods that aren't
+    /// idempotent, like `POST`, be retried.
+    ///
+    /// ```
+    /// let r = ureq::post("/payments")
+    ///     .with_idempotency_key()
+    ///     .retries(2)
+    ///     .send_string("amount=10");
+    /// println!("{:?}", r);
+    /// ```
+    pub fn with_idempotency_key(&mut self) -> &mut Request {
+        if !self.has("Idempotency-Key") {
+            self.set("Idempotency-Key", &random_uuid());
+        }
+        self
+    }
+
+    /// Set the `If-Modified-Since` header, so the server answers `304 Not Modified`
+    /// without a body if the resource hasn't changed since the given time.
+    ///
+    /// The time is sent as an HTTP-date, such as `Sun, 06 Nov 1994 08:49:37 GMT`,
+    /// with the sub-second part dropped.
+    ///
+    /// ```
+    /// use std::time::{Duration, SystemTime};
+    ///
+    /// let fetched = SystemTime::now() - Duration::from_secs(3600);
+    /// let r = ureq::get("/my_page")
+    ///     .set_if_modified_since(fetched)
+    ///     .call();
+    ///
+    /// if r.not_modified() {
+    ///     println!("still fresh");
+    /// }
+    /// ```
+    pub fn set_if_modified_since(&mut self, time: time::SystemTime) -> &mut Request {
+        self.set("If-Modified-Since", &header::http_date(time))
+    }
+
+    /// Set the `If-None-Match` header, so the server answers `304 Not Modified`
+    /// without a body if the resource still has the given entity tag.
+    ///
+    /// The etag is quoted if it isn't already, and weak (`W/"..."`) tags and `*` are
+    /// sent as they are. The value of a previous response's `ETag` header can be
+    /// given directly.
+    ///
+    /// ```
+    /// let r = ureq::get("/my_page")
+    ///     .set_if_none_match("33a64df5")
+    ///     .call();
+    ///
+    /// if r.not_modified() {
+    ///     println!("still fresh");
+    /// }
+    /// ```
+    pub fn set_if_none_match(&mut self, etag: &str) -> &mut Request {
+        let etag = etag.trim();
+        let quoted = etag == "*"
+            || (etag.len() >= 2 && etag.starts_with('"') && etag.ends_with('"'))
+            || (etag.len() >= 4 && etag.starts_with("W/\"") && etag.ends_with('"'));
+        if quoted {
+            self.set("If-None-Match", etag)
+        } else {
+            self.set("If-None-Match", &format!("\"{}\"", etag))
+        }
+    }
+
     /// Returns the value for a set header.
     ///
     /// ```
@@ -303,9 +657,11 @@ impl Request {
         header::get_all_headers(&self.headers, name)
     }
 
-    /// Set a query parameter.
+    /// Set a query parameter. The name and value are percent encoded, and
+    /// appended to any query already in the url. Setting the same name again
+    /// adds another pair rather than replacing it.
     ///
-    /// For example, to set `?format=json&dest=/login`
+    /// For example, to set `?format=json&dest=%2Flogin`
     ///
     /// ```
     /// let r = ureq::get("/my_page")
@@ -316,7 +672,32 @@ impl Request {
     /// println!("{:?}", r);
     /// ```
     pub fn query(&mut self, param: &str, value: &str) -> &mut Request {
-        self.query.add_pair((param, value));
+        // QString only escapes some characters, encoding here first makes sure
+        // `%`, `+` and `#` arrive as they are.
+        self.query
+            .add_pair((encode_query(param), encode_query(value)));
+        self
+    }
+
+    /// Set several query parameters, encoded the same as
+    /// [`query()`](#method.query).
+    ///
+    /// ```
+    /// let filters = vec![("tag", "rust"), ("tag", "http")];
+    /// let req = ureq::get("https://cool.server/search")
+    ///     .query_pairs(filters)
+    ///     .build();
+    /// assert_eq!(req.get_query().unwrap(), "?tag=rust&tag=http");
+    /// ```
+    pub fn query_pairs<I, K, V>(&mut self, pairs: I) -> &mut Request
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        for (param, value) in pairs {
+            self.query(param.as_ref(), value.as_ref());
+        }
         self
     }
 
@@ -335,9 +716,13 @@ impl Request {
         self
     }
 
-    /// Timeout for the socket connection to be successful.
-    /// If both this and .timeout() are both set, .timeout_connect()
-    /// takes precedence.
+    /// Timeout for the socket connection to be successful. This covers the
+    /// DNS lookup, TCP connect, any proxy handshake and the TLS handshake, but not
+    /// reading or writing the request afterwards.
+    /// If both this and .timeout() are set, connecting must finish before
+    /// whichever runs out first. Running out of this one fails with
+    /// [`Error::ConnectTimeout`](enum.Error.html#variant.ConnectTimeout),
+    /// which is retried like other connection errors.
     ///
     /// The default is `0`, which means a request can block forever.
     ///
@@ -353,8 +738,11 @@ impl Request {
     }
 
     /// Timeout for the individual reads of the socket.
-    /// If both this and .timeout() are both set, .timeout()
-    /// takes precedence.
+    /// If both this and .timeout() are set, both apply, and whichever
+    /// runs out first ends the request. A read that waits too long fails
+    /// with [`Error::ReadTimeout`](enum.Error.html#variant.ReadTimeout),
+    /// while passing the overall deadline gives
+    /// [`Error::DeadlineExceeded`](enum.Error.html#variant.DeadlineExceeded).
     ///
     /// The default is `0`, which means it can block forever.
     ///
@@ -369,9 +757,11 @@ impl Request {
         self
     }
 
-    /// Timeout for the individual writes to the socket.
-    /// If both this and .timeout() are both set, .timeout()
-    /// takes precedence.
+    /// Timeout for the individual writes to the socket, such as when
+    /// uploading a body to a server that stopped reading it.
+    /// If both this and .timeout() are set, both apply, and whichever
+    /// runs out first ends the request. A write that waits too long fails
+    /// with [`Error::WriteTimeout`](enum.Error.html#variant.WriteTimeout).
     ///
     /// The default is `0`, which means it can block forever.
     ///
@@ -386,10 +776,13 @@ impl Request {
         self
     }
 
-    /// Timeout for the overall request, including DNS resolution, connection
-    /// time, redirects, and reading the response body. Slow DNS resolution
-    /// may cause a request to exceed the timeout, because the DNS request
-    /// cannot be interrupted with the available APIs.
+    /// Timeout for the overall request, including DNS resolution, connecting,
+    /// the TLS handshake, redirects, sending the body and reading the response
+    /// body. The time starts when the request is made, and running out anywhere
+    /// fails with [`Error::DeadlineExceeded`](enum.Error.html#variant.DeadlineExceeded).
+    ///
+    /// A lookup that's still going when the time runs out is left to finish in the
+    /// background, since DNS requests can't be interrupted with the available APIs.
     ///
     /// This takes precedence over .timeout_read() and .timeout_write(), but
     /// not .timeout_connect().
@@ -406,7 +799,59 @@ impl Request {
         self
     }
 
-    /// Basic auth.
+    /// Send `Expect: 100-continue` and wait for the server to answer
+    /// `100 Continue` before sending the body, for at most `wait_ms` milliseconds.
+    ///
+    /// A server that would reject the request, say with a 401 or 413, can then do so
+    /// before a large body is uploaded. The final response is returned as is, without
+    /// sending the body. If there is no answer in time, the body is sent anyway.
+    ///
+    /// Setting the `Expect: 100-continue` header yourself does the same, waiting for
+    /// one second. Requests without a body are sent as usual.
+    ///
+    /// ```
+    /// let upload = std::io::Cursor::new(vec![0; 1_000_000]);
+    /// let r = ureq::put("/my_page")
+    ///     .set("Transfer-Encoding", "chunked")
+    ///     .expect_continue(2_000)
+    ///     .send(upload, None);
+    /// println!("{:?}", r);
+    /// ```
+    pub fn expect_continue(&mut self, wait_ms: u64) -> &mut Request {
+        self.expect_continue = Some(time::Duration::from_millis(wait_ms));
+        self
+    }
+
+    /// Send each chunk of a chunked body as soon as the reader gives it, rather than
+    /// waiting to fill a chunk of the [`buffer_size()`](struct.Agent.html#method.buffer_size),
+    /// for uploads the server answers as they go. Each chunk is flushed, with Nagle's
+    /// algorithm turned off while the body is sent.
+    ///
+    /// Defaults to `false`, since many small chunks cost more to send.
+    ///
+    /// ```
+    /// let mut lines = vec!["first\n", "second\n"].into_iter();
+    /// let r = ureq::post("/my_page")
+    ///     .flush_each_chunk(true)
+    ///     .send_with(move |buf| match lines.next() {
+    ///         Some(line) => {
+    ///             buf.extend_from_slice(line.as_bytes());
+    ///             Ok(true)
+    ///         }
+    ///         None => Ok(false),
+    ///     });
+    /// println!("{:?}", r);
+    /// ```
+    pub fn flush_each_chunk(&mut self, enabled: bool) -> &mut Request {
+        self.flush_each_chunk = enabled;
+        self
+    }
+
+    /// Basic auth. The user and password are UTF-8 encoded before base64,
+    /// the same as credentials in the url.
+    ///
+    /// Like any `Authorization` header, it is not sent on when a redirect goes
+    /// to another host.
     ///
     /// These are the same
     ///
@@ -440,11 +885,23 @@ impl Request {
 
     /// How many redirects to follow.
     ///
-    /// Defaults to `5`. Set to `0` to avoid redirects and instead
-    /// get a response object with the 3xx status code.
+    /// Defaults to `5`, or what the agent's [`redirects()`](struct.Agent.html#method.redirects)
+    /// set. Set to `0` to avoid redirects and instead get a response object with the
+    /// 3xx status code, including the `Location` header.
     ///
-    /// If the redirect count hits this limit (and it's > 0), a synthetic 500 error
-    /// response is produced.
+    /// Each redirect followed counts towards the limit, i.e. `.redirects(2)` lets
+    /// a request go through two redirects before the final response. If the server
+    /// redirects once more than that (and the limit is > 0), a synthetic 500 error
+    /// response is produced, while a 3xx that wouldn't be followed anyway, such as a
+    /// `304` or one without `Location`, is the response. A redirect that would make
+    /// the same request again, with the same method to an url already visited, fails
+    /// right away with [`Error::RedirectLoop`](enum.Error.html#variant.RedirectLoop).
+    ///
+    /// For 303 the redirected request is a `GET` (or `HEAD`) without body. 301 and 302
+    /// do the same for `POST`, like browsers do, but keep any other method. Otherwise
+    /// the method is kept and the body sent again, which only works for bodies
+    /// held in memory (strings, bytes, JSON). A body from [`send()`](#method.send)
+    /// can't be resent and gives a synthetic 500 error response.
     ///
     /// ```
     /// let r = ureq::get("/my_page")
@@ -457,9 +914,174 @@ impl Request {
         self
     }
 
-    // pub fn retry(&self, times: u16) -> Request {
-    //     unimplemented!()
-    // }
+    /// The longest to wait before a retry. Defaults to 60 seconds.
+    ///
+    /// A response whose `Retry-After` asks for a longer wait isn't retried, but given
+    /// back as it is, so a server can't leave us sleeping for days. The doubling wait
+    /// between retries without `Retry-After` stops growing at this.
+    ///
+    /// ```
+    /// let r = ureq::get("/my_page")
+    ///     .retries(5)
+    ///     .max_retry_wait(std::time::Duration::from_secs(10))
+    ///     .call();
+    /// println!("{:?}", r);
+    /// ```
+    pub fn max_retry_wait(&mut self, max: time::Duration) -> &mut Request {
+        self.max_retry_wait = max;
+        self
+    }
+
+    /// Decide for each redirect whether to follow it.
+    ///
+    /// The function is called with the url that was requested, the 3xx status code and
+    /// the url from the `Location` header (already resolved against the requested url).
+    /// It can follow the redirect, possibly to a rewritten url, stop and give back the
+    /// 3xx response, or give a synthetic error response. The default is to always follow.
+    ///
+    /// The limit set by [`redirects()`](#method.redirects) still applies, to the redirects
+    /// the function follows. It is asked about a redirect past the limit too, and
+    /// stopping there gives back that 3xx response rather than an error.
+    ///
+    /// ```
+    /// use ureq::RedirectAction;
+    ///
+    /// let r = ureq::get("/my_page")
+    ///     .redirect_fn(|_from, _status, to| {
+    ///         if to.scheme() == "http" {
+    ///             // upgrade to https
+    ///             let mut to = to.clone();
+    ///             to.set_scheme("https").unwrap();
+    ///             RedirectAction::Follow(to)
+    ///         } else if to.host_str() == Some("localhost") {
+    ///             RedirectAction::Error
+    ///         } else {
+    ///             RedirectAction::Follow(to.clone())
+    ///         }
+    ///     })
+    ///     .call();
+    /// println!("{:?}", r);
+    /// ```
+    pub fn redirect_fn<F>(&mut self, f: F) -> &mut Request
+    where
+        F: Fn(&Url, u16, &Url) -> RedirectAction + Send + Sync + 'static,
+    {
+        self.redirect_fn = Some(Arc::new(f));
+        self
+    }
+
+    /// Keep the 3xx responses of the redirects followed, with up to `max_body` bytes
+    /// of each body, to see in [`Response::redirect_responses()`](struct.Response.html#method.redirect_responses).
+    /// Meant for debugging redirects that go wrong. Off by default, when the bodies
+    /// of redirects are never read.
+    ///
+    /// ```
+    /// let r = ureq::get("/my_page")
+    ///     .keep_redirect_responses(4096)
+    ///     .call();
+    /// println!("{:?}", r.redirect_responses());
+    /// ```
+    pub fn keep_redirect_responses(&mut self, max_body: usize) -> &mut Request {
+        self.keep_redirect_responses = Some(max_body);
+        self
+    }
+
+    /// How many times to retry a request that failed with 429, 502, 503, 504 or
+    /// a connection error.
+    ///
+    /// Defaults to `0`. Only idempotent requests (`GET`, `HEAD`, `PUT`, `DELETE`,
+    /// `OPTIONS`, `TRACE`) are retried, and never ones with a body from a reader.
+    /// Other methods are retried if the request has an `Idempotency-Key`, see
+    /// [`with_idempotency_key()`](#method.with_idempotency_key).
+    ///
+    /// Retries wait 100ms before the first one, doubling for each following retry.
+    /// If the response has a `Retry-After` header, in seconds or as an HTTP-date, that
+    /// wait is used instead. A retry is not done if the wait would go past the
+    /// [`timeout()`](#method.timeout), or if `Retry-After` asks for longer than
+    /// [`max_retry_wait()`](#method.max_retry_wait). Then, or when the retries run out,
+    /// the last response is given back, with its body.
+    ///
+    /// ```
+    /// let r = ureq::get("/my_page")
+    ///     .retries(3)
+    ///     .call();
+    /// println!("{:?}", r);
+    /// ```
+    pub fn retries(&mut self, n: u32) -> &mut Request {
+        self.retries = n;
+        self
+    }
+
+    /// How many times to reconnect when a connection from the pool turns out to
+    /// be closed by the server before the response status line could be read.
+    ///
+    /// Defaults to `1`. This only applies to idempotent requests. The last reconnect
+    /// always opens a new connection, earlier ones may pick another pooled connection.
+    /// No reconnect is done after the [`timeout()`](#method.timeout) has passed.
+    ///
+    /// ```
+    /// let r = ureq::get("/my_page")
+    ///     .pool_retries(3)
+    ///     .call();
+    /// println!("{:?}", r);
+    /// ```
+    pub fn pool_retries(&mut self, n: u32) -> &mut Request {
+        self.pool_retries = n;
+        self
+    }
+
+    /// Don't keep the connection open after this request. The request is sent with
+    /// `Connection: close` on a new connection, which isn't returned to the pool.
+    ///
+    /// ```
+    /// let r = ureq::get("/my_page")
+    ///     .no_keepalive()
+    ///     .call();
+    /// println!("{:?}", r);
+    /// ```
+    pub fn no_keepalive(&mut self) -> &mut Request {
+        self.keepalive = false;
+        self
+    }
+
+    /// Leave the agent's cookie jar out of this request, and the redirects it follows:
+    /// none of its cookies are sent, and the ones the server sets aren't saved. The jar
+    /// stays as it is for other requests. A `Cookie` header set on the request is still
+    /// sent.
+    ///
+    /// ```
+    /// let agent = ureq::agent().build();
+    /// let r = agent
+    ///     .get("http://example.com/public/logo.png")
+    ///     .no_cookies()
+    ///     .call();
+    /// println!("{:?}", r);
+    /// ```
+    #[cfg(feature = "cookie")]
+    pub fn no_cookies(&mut self) -> &mut Request {
+        self.no_cookies = true;
+        self
+    }
+
+    /// Make the request cancellable from another thread with the given handle.
+    /// Cancelling it fails the request, or the reading of its response body, with
+    /// [`Error::Cancelled`](enum.Error.html#variant.Cancelled).
+    ///
+    /// The same handle can be given to several requests to cancel them all at once.
+    /// See [`CancelHandle`](struct.CancelHandle.html).
+    ///
+    /// ```
+    /// let handle = ureq::CancelHandle::new();
+    /// let req = ureq::get("/my_page")
+    ///     .set_cancel_handle(handle.clone())
+    ///     .build();
+    /// // later, from another thread
+    /// handle.cancel();
+    /// ```
+    pub fn set_cancel_handle(&mut self, handle: CancelHandle) -> &mut Request {
+        self.cancel = Some(handle);
+        self
+    }
     // pub fn sortQuery(&self) -> Request {
     //     unimplemented!()
     // }
@@ -565,12 +1187,17 @@ impl Request {
         self.to_url().map(|u| u.path().to_string())
     }
 
-    fn to_url(&self) -> Result<Url, Error> {
+    pub(crate) fn to_url(&self) -> Result<Url, Error> {
         Url::parse(&self.url).map_err(|e| Error::BadUrl(format!("{}", e)))
     }
 
     /// Set the proxy server to use for the connection.
     ///
+    /// Hosts listed in the `NO_PROXY` (or `no_proxy`) environment variable are
+    /// connected to directly. The list is comma separated, and takes `*`, hostnames
+    /// (which also match subdomains, like `localhost,.internal.example.com`),
+    /// IP addresses and CIDR ranges such as `10.0.0.0/8`.
+    ///
     /// Example:
     /// ```
     /// let proxy = ureq::Proxy::new("user:password@cool.proxy:9090").unwrap();
@@ -599,6 +1226,24 @@ impl Request {
         self
     }
 
+    /// Set the hostname used for TLS, instead of the host of the url.
+    ///
+    /// The connection is still made to the host in the url, but the name is sent
+    /// as SNI and the server's certificate is checked against it. Useful to reach
+    /// a server by its IP address.
+    ///
+    /// Example:
+    /// ```
+    /// let req = ureq::get("https://10.0.0.1/")
+    ///     .tls_server_name("api.example.com")
+    ///     .build();
+    /// ```
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub fn tls_server_name(&mut self, name: &str) -> &mut Request {
+        self.tls_server_name = Some(name.to_string());
+        self
+    }
+
     // Returns true if this request, with the provided body, is retryable.
     pub(crate) fn is_retryable(&self, body: &SizedReader) -> bool {
         // Per https://tools.ietf.org/html/rfc7231#section-8.1.3
@@ -607,13 +1252,53 @@ impl Request {
             "DELETE" | "GET" | "HEAD" | "OPTIONS" | "PUT" | "TRACE" => true,
             _ => false,
         };
-        // Unsized bodies aren't retryable because we can't rewind the reader.
-        // Sized bodies are retryable only if they are zero-length because of
-        // coincidences of the current implementation - the function responsible
-        // for retries doesn't have a way to replay a Payload.
-        let no_body = body.size.is_none() || body.size.unwrap() > 0;
-        idempotent && no_body
+        // a server that dedupes by the key makes any method safe to send again.
+        let idempotent = idempotent || self.has("Idempotency-Key");
+        // Bodies from a reader aren't retryable because we can't rewind the
+        // reader. Bodies held in memory, including no body at all, can be sent again.
+        let replayable = body.try_clone().is_some();
+        idempotent && replayable
+    }
+}
+
+/// A random (version 4) UUID, such as `0b9e6c4a-3f1d-4c2e-9a7b-5d8f1e2c3b4a`.
+fn random_uuid() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let mut bytes = [0u8; 16];
+    for half in bytes.chunks_mut(8) {
+        // RandomState is seeded randomly, hashing makes the counter unguessable.
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_usize(COUNTER.fetch_add(1, Ordering::Relaxed));
+        if let Ok(elapsed) = time::SystemTime::now().duration_since(time::UNIX_EPOCH) {
+            hasher.write_u128(elapsed.as_nanos());
+        }
+        half.copy_from_slice(&hasher.finish().to_be_bytes());
     }
+    bytes[6] = (bytes[6] & 0x0f) | 0x40;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+/// The longest wait before a retry, unless changed with `max_retry_wait()`.
+const DEFAULT_MAX_RETRY_WAIT: time::Duration = time::Duration::from_secs(60);
+
+/// Everything but the unreserved characters of RFC 3986.
+const QUERY_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+fn encode_query(s: &str) -> String {
+    utf8_percent_encode(s, QUERY_ENCODE_SET).to_string()
 }
 
 #[cfg(feature = "tls")]
@@ -626,3 +1311,44 @@ impl fmt::Debug for TLSClientConfig {
         f.debug_struct("TLSClientConfig").finish()
     }
 }
+
+/// The native-tls connector of an agent, or the options it could not be built from.
+#[cfg(feature = "native-tls")]
+#[derive(Clone)]
+pub(crate) enum TLSConnector {
+    Built(Arc<native_tls::TlsConnector>),
+    Failed(Arc<crate::tls::TlsOptions>),
+}
+
+#[cfg(feature = "native-tls")]
+impl TLSConnector {
+    /// The connector, or an `Error::TlsError` with why it could not be built.
+    pub(crate) fn connector(&self) -> Result<Arc<native_tls::TlsConnector>, Error> {
+        match self {
+            TLSConnector::Built(connector) => Ok(Arc::clone(connector)),
+            // building again gives the error, native-tls errors can't be cloned.
+            TLSConnector::Failed(options) => options
+                .build_native_connector()
+                .map(Arc::new)
+                .map_err(Error::TlsError),
+        }
+    }
+
+    /// Tells connectors apart, for the connection pool.
+    pub(crate) fn id(&self) -> usize {
+        match self {
+            TLSConnector::Built(connector) => Arc::as_ptr(connector) as usize,
+            TLSConnector::Failed(options) => Arc::as_ptr(options) as usize,
+        }
+    }
+}
+
+#[cfg(feature = "native-tls")]
+impl fmt::Debug for TLSConnector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TLSConnector").finish()
+    }
+}
+
+/// How many redirects are followed, unless changed with `redirects()`.
+pub(crate) const DEFAULT_REDIRECTS: u32 = 5;
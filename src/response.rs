@@ -1,14 +1,20 @@
-use std::io::{Cursor, Error as IoError, ErrorKind, Read, Result as IoResult};
+use std::io::{Cursor, Error as IoError, ErrorKind, Read, Result as IoResult, Write};
 use std::str::FromStr;
-use std::time::Instant;
-
-use chunked_transfer::Decoder as ChunkDecoder;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use crate::decoder;
 use crate::error::Error;
-use crate::header::Header;
+use crate::header::{self, Header};
+#[cfg(feature = "json")]
+use crate::json_lines::JsonLines;
 use crate::pool::PoolReturnRead;
+use crate::spool::{self, SeekableBody, DEFAULT_SPOOL_MEMORY_LIMIT};
+#[cfg(feature = "sse")]
+use crate::sse::Events;
 use crate::stream::{DeadlineStream, Stream};
 use crate::unit::Unit;
+use crate::upgrade::Upgraded;
 
 #[cfg(feature = "json")]
 use serde::de::DeserializeOwned;
@@ -48,14 +54,78 @@ pub const DEFAULT_CHARACTER_SET: &str = "utf-8";
 /// ```
 pub struct Response {
     url: Option<String>,
+    history: Vec<(u16, String)>,
+    redirect_responses: Vec<RedirectResponse>,
     error: Option<Error>,
     status_line: String,
     index: ResponseStatusIndex,
     status: u16,
     headers: Vec<Header>,
+    raw_headers: Vec<u8>,
     unit: Option<Unit>,
     stream: Option<Stream>,
     deadline: Option<Instant>,
+    trailers: Trailers,
+    peeked: Option<Peeked>,
+    encoded: Option<Encoded>,
+    peer_certificate: Option<Vec<u8>>,
+    negotiated_protocol: Option<String>,
+    connection_reused: bool,
+    timings: Timings,
+}
+
+/// How the body is encoded on the wire, when it's decoded for the reader.
+#[derive(Debug)]
+struct Encoded {
+    // the Content-Encoding to decode.
+    #[cfg_attr(not(any(feature = "gzip", feature = "brotli")), allow(dead_code))]
+    encoding: String,
+    // the Content-Length of the encoded body.
+    length: Option<u64>,
+}
+
+type BodyReader = Box<dyn Read + Send + Sync>;
+
+/// How long the phases of a request took, from [`Response::timings()`](struct.Response.html#method.timings).
+///
+/// The phases are those of the connection that gave the final response, after any
+/// redirects and retries. Phases that didn't happen are zero: all of the connecting
+/// for a connection reused from the pool, `dns` for an IP address, `tls` for http.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Timings {
+    /// Looking up the address of the host (or proxy).
+    pub dns: Duration,
+    /// Opening the TCP connection, including a tunnel through a proxy.
+    pub connect: Duration,
+    /// The TLS handshake.
+    pub tls: Duration,
+    /// Waiting for the status line and headers after sending the request.
+    pub first_byte: Duration,
+    /// From making the call until the headers were read, across all redirects
+    /// and retries. Reading the body isn't included.
+    pub total: Duration,
+}
+
+/// A 3xx response of a redirect that was followed, kept for
+/// [`Response::redirect_responses()`](struct.Response.html#method.redirect_responses)
+/// when asked for with [`Request::keep_redirect_responses()`](struct.Request.html#method.keep_redirect_responses).
+#[derive(Debug, Clone)]
+pub struct RedirectResponse {
+    /// The status of the redirect.
+    pub status: u16,
+    /// The url that answered with the redirect.
+    pub url: String,
+    /// The headers of the redirect, `Location` among them.
+    pub headers: Vec<Header>,
+    /// The start of the body, up to the size asked for.
+    pub body: Vec<u8>,
+}
+
+/// The start of the body read by [`Response::peek()`](struct.Response.html#method.peek),
+/// and the reader of the rest of it.
+struct Peeked {
+    bytes: Vec<u8>,
+    reader: BodyReader,
 }
 
 /// index into status_line where we split: HTTP/1.1 200 OK
@@ -101,6 +171,53 @@ impl Response {
         self.url.as_ref().map(|s| &s[..]).unwrap_or("")
     }
 
+    /// The redirects followed to get this response, in order. Each is the status
+    /// of the redirect and the URL it sent us to.
+    ///
+    /// ```no_run
+    /// let resp = ureq::get("http://example.com/old").call();
+    ///
+    /// for (status, url) in resp.history() {
+    ///     println!("{} -> {}", status, url);
+    /// }
+    /// ```
+    pub fn history(&self) -> &[(u16, String)] {
+        &self.history
+    }
+
+    /// The 3xx responses of the redirects followed to get this response, in order,
+    /// with their headers and the start of their bodies. Only kept if the request
+    /// asked for them with [`keep_redirect_responses()`](struct.Request.html#method.keep_redirect_responses),
+    /// otherwise empty.
+    ///
+    /// ```no_run
+    /// let resp = ureq::get("http://example.com/old")
+    ///     .keep_redirect_responses(1024)
+    ///     .call();
+    ///
+    /// for redirect in resp.redirect_responses() {
+    ///     println!("{} {}: {}", redirect.status, redirect.url, String::from_utf8_lossy(&redirect.body));
+    /// }
+    /// ```
+    pub fn redirect_responses(&self) -> &[RedirectResponse] {
+        &self.redirect_responses
+    }
+
+    /// Take the headers and up to `limit` bytes of the body of a redirect.
+    pub(crate) fn into_redirect_response(self, url: &str, limit: usize) -> RedirectResponse {
+        let status = self.status;
+        let headers = self.headers.clone();
+        let mut body = vec![];
+        // only to help debugging, so whatever could be read is enough.
+        let _ = self.into_reader().take(limit as u64).read_to_end(&mut body);
+        RedirectResponse {
+            status,
+            url: url.to_string(),
+            headers,
+            body,
+        }
+    }
+
     /// The entire status line like: `HTTP/1.1 200 OK`
     pub fn status_line(&self) -> &str {
         self.status_line.as_str()
@@ -117,8 +234,13 @@ impl Response {
     }
 
     /// The status text: `OK`
+    ///
+    /// Empty if the server sent none, as in `HTTP/1.1 200`.
     pub fn status_text(&self) -> &str {
-        &self.status_line.as_str()[self.index.response_code + 1..].trim()
+        self.status_line
+            .get(self.index.response_code + 1..)
+            .unwrap_or("")
+            .trim()
     }
 
     /// The header corresponding header value for the give name, if any.
@@ -129,6 +251,23 @@ impl Response {
             .map(|h| h.value())
     }
 
+    /// The header lines of the response as they were received, each ending with `\r\n`,
+    /// without the status line and the blank line after the headers. Unlike
+    /// [`header()`](#method.header) and the like, nothing is left out or changed, such as
+    /// lines that don't parse or the `Content-Encoding` of a body ureq decodes. Meant for
+    /// passing a response on as it came.
+    ///
+    /// ```
+    /// let resp = "HTTP/1.1 200 OK\r\nX-Custom: A\r\nx-custom: b\r\n\r\nhi"
+    ///     .parse::<ureq::Response>()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(resp.raw_headers(), b"X-Custom: A\r\nx-custom: b\r\n");
+    /// ```
+    pub fn raw_headers(&self) -> &[u8] {
+        &self.raw_headers
+    }
+
     /// A list of the header names in this response.
     /// Lowercased to be uniform.
     pub fn headers_names(&self) -> Vec<String> {
@@ -152,15 +291,179 @@ impl Response {
             .collect()
     }
 
+    /// All values of the named header, in the order they were received. The same
+    /// as [`all()`](#method.all), named to go with [`header()`](#method.header),
+    /// which only returns the first.
+    ///
+    /// ```
+    /// let resp = "HTTP/1.1 200 OK\r\n\
+    ///     Link: <https://example.com/?page=2>; rel=\"next\"\r\n\
+    ///     link: <https://example.com/?page=9>; rel=\"last\"\r\n\
+    ///     \r\n"
+    ///     .parse::<ureq::Response>()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(resp.header_all("Link").len(), 2);
+    /// ```
+    pub fn header_all(&self, name: &str) -> Vec<&str> {
+        self.all(name)
+    }
+
+    /// The trailer headers of a chunked response, which the server sends after the
+    /// body. Since they come last, they can only be read once the body is, so this
+    /// is a handle to keep while the response is turned into a reader.
+    ///
+    /// ```
+    /// use std::io::Read;
+    ///
+    /// let resp = "HTTP/1.1 200 OK\r\n\
+    ///     Transfer-Encoding: chunked\r\n\
+    ///     Trailer: X-Checksum\r\n\
+    ///     \r\n\
+    ///     5\r\nhello\r\n0\r\nX-Checksum: 5d41402a\r\n\r\n"
+    ///     .parse::<ureq::Response>()
+    ///     .unwrap();
+    ///
+    /// let trailers = resp.trailers();
+    /// let mut body = String::new();
+    /// resp.into_reader().read_to_string(&mut body).unwrap();
+    ///
+    /// assert_eq!(body, "hello");
+    /// assert_eq!(trailers.get("x-checksum").as_deref(), Some("5d41402a"));
+    /// ```
+    pub fn trailers(&self) -> Trailers {
+        self.trailers.clone()
+    }
+
+    /// Whether the response came on a connection reused from the pool, rather than
+    /// a new one. After redirects and retries, this is about the connection of the
+    /// final response. Always `false` for responses that aren't from a server.
+    ///
+    /// ```
+    /// let resp = "HTTP/1.1 200 OK\r\n\r\n".parse::<ureq::Response>().unwrap();
+    ///
+    /// assert!(!resp.connection_reused());
+    /// ```
+    pub fn connection_reused(&self) -> bool {
+        self.connection_reused
+    }
+
+    /// How long connecting, sending the request and waiting for the response took.
+    /// See [`Timings`](struct.Timings.html). All zero for responses that aren't from
+    /// a server.
+    ///
+    /// ```no_run
+    /// let resp = ureq::get("http://example.com/").call();
+    /// let timings = resp.timings();
+    /// println!("dns {:?}, connect {:?}, waiting {:?}", timings.dns, timings.connect, timings.first_byte);
+    /// ```
+    pub fn timings(&self) -> Timings {
+        self.timings
+    }
+
+    /// The certificate the server presented for an https request, DER encoded. This
+    /// is the server's own (leaf) certificate, not the rest of its chain. `None` for
+    /// plain http, and for responses that aren't from a server.
+    ///
+    /// The headers have been read, but not the body, when the response is handed out.
+    /// So a certificate that doesn't match a pinned one can be rejected by dropping
+    /// the response before reading on.
+    ///
+    /// ```
+    /// let resp = "HTTP/1.1 200 OK\r\n\r\n".parse::<ureq::Response>().unwrap();
+    ///
+    /// assert!(resp.peer_certificate().is_none());
+    /// ```
+    pub fn peer_certificate(&self) -> Option<&[u8]> {
+        self.peer_certificate.as_deref()
+    }
+
+    /// The protocol the server chose with ALPN during the TLS handshake, such as
+    /// `"h2"` or `"http/1.1"`. `None` for plain http, and when no protocols were
+    /// offered, which needs a custom TLS config with ALPN protocols set.
+    ///
+    /// ```
+    /// let resp = "HTTP/1.1 200 OK\r\n\r\n".parse::<ureq::Response>().unwrap();
+    ///
+    /// assert!(resp.negotiated_protocol().is_none());
+    /// ```
+    pub fn negotiated_protocol(&self) -> Option<String> {
+        self.negotiated_protocol.clone()
+    }
+
+    /// The length of the body declared by the `Content-Length` header. `None` if the
+    /// body is chunked, or the header is missing or not a number.
+    ///
+    /// A compressed body that's decoded has no known length: its `Content-Length`
+    /// and `Content-Encoding` headers are removed, since neither describes the
+    /// body read from the response.
+    ///
+    /// ```
+    /// let resp = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+    ///     .parse::<ureq::Response>()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(resp.content_length(), Some(5));
+    /// ```
+    pub fn content_length(&self) -> Option<u64> {
+        if self.has("transfer-encoding") {
+            return None;
+        }
+        self.header("content-length")
+            .and_then(|l| l.trim().parse::<u64>().ok())
+    }
+
+    /// The range of the resource carried by a `206 Partial Content` response, parsed
+    /// from the `Content-Range` header as `(start, end, total)`. Both ends are inclusive,
+    /// and the total is `None` if the server doesn't know it (`*`).
+    ///
+    /// `None` if the header is missing, isn't a byte range or can't be understood.
+    /// A `416 Range Not Satisfiable` answer only gives the total (`bytes */1234`),
+    /// and is also `None`.
+    ///
+    /// ```
+    /// let resp = "HTTP/1.1 206 Partial Content\r\nContent-Range: bytes 2-4/12\r\n\r\nllo"
+    ///     .parse::<ureq::Response>()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(resp.content_range(), Some((2, 4, Some(12))));
+    /// ```
+    pub fn content_range(&self) -> Option<(u64, u64, Option<u64>)> {
+        let value = self.header("content-range")?.trim();
+        let mut parts = value.splitn(2, ' ');
+        if !parts.next()?.eq_ignore_ascii_case("bytes") {
+            return None;
+        }
+        let mut parts = parts.next()?.trim().splitn(2, '/');
+        let mut range = parts.next()?.splitn(2, '-');
+        let start = range.next()?.trim().parse::<u64>().ok()?;
+        let end = range.next()?.trim().parse::<u64>().ok()?;
+        let total = match parts.next()?.trim() {
+            "*" => None,
+            total => Some(total.parse::<u64>().ok()?),
+        };
+        if end < start || total.map(|t| end >= t).unwrap_or(false) {
+            return None;
+        }
+        Some((start, end, total))
+    }
+
     /// Whether the response status is: 200 <= status <= 299
     pub fn ok(&self) -> bool {
         self.status >= 200 && self.status <= 299
     }
 
+    /// Whether the response status is: 300 <= status <= 399
     pub fn redirect(&self) -> bool {
         self.status >= 300 && self.status <= 399
     }
 
+    /// Whether the response status is `304 Not Modified`, the answer to a conditional
+    /// request when the resource hasn't changed. Such a response has no body.
+    pub fn not_modified(&self) -> bool {
+        self.status == 304
+    }
+
     /// Whether the response status is: 400 <= status <= 499
     pub fn client_error(&self) -> bool {
         self.status >= 400 && self.status <= 499
@@ -176,6 +479,39 @@ impl Response {
         self.client_error() || self.server_error()
     }
 
+    /// Whether the response status is: 300 <= status <= 399. Same as
+    /// [`redirect()`](#method.redirect).
+    ///
+    /// ```
+    /// let resp = ureq::Response::new(302, "Found", "");
+    /// assert!(resp.is_redirect());
+    /// ```
+    pub fn is_redirect(&self) -> bool {
+        self.redirect()
+    }
+
+    /// Whether the response status is: 400 <= status <= 499. Same as
+    /// [`client_error()`](#method.client_error).
+    ///
+    /// ```
+    /// let resp = ureq::Response::new(404, "Not Found", "");
+    /// assert!(resp.is_client_error());
+    /// ```
+    pub fn is_client_error(&self) -> bool {
+        self.client_error()
+    }
+
+    /// Whether the response status is: 500 <= status <= 599. Same as
+    /// [`server_error()`](#method.server_error).
+    ///
+    /// ```
+    /// let resp = ureq::Response::new(503, "Service Unavailable", "");
+    /// assert!(resp.is_server_error());
+    /// ```
+    pub fn is_server_error(&self) -> bool {
+        self.server_error()
+    }
+
     /// Tells if this response is "synthetic".
     ///
     /// The [methods](struct.Request.html#method.call) [firing](struct.Request.html#method.send)
@@ -220,6 +556,34 @@ impl Response {
         &self.error
     }
 
+    /// Turn this response into a `Result`: `Err` with the
+    /// [synthetic error](#method.synthetic_error) if there was one, or
+    /// [`Error::Status`](enum.Error.html#variant.Status) with this response for a
+    /// `4xx` or `5xx` status, otherwise `Ok` with this response.
+    ///
+    /// ```
+    /// use ureq::Error;
+    ///
+    /// let resp = ureq::Response::new(404, "Not Found", "no such page");
+    ///
+    /// match resp.into_result() {
+    ///     Ok(resp) => println!("got {}", resp.into_string().unwrap()),
+    ///     Err(Error::Status(404, resp)) => {
+    ///         assert_eq!(resp.into_string().unwrap(), "no such page\n");
+    ///     }
+    ///     Err(err) => println!("failed: {}", err),
+    /// }
+    /// ```
+    pub fn into_result(mut self) -> Result<Response, Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        if self.error() {
+            return Err(Error::Status(self.status, Box::new(self)));
+        }
+        Ok(self)
+    }
+
     /// The content type part of the "Content-Type" header without
     /// the charset.
     ///
@@ -260,11 +624,16 @@ impl Response {
 
     /// Turn this response into a `impl Read` of the body.
     ///
-    /// 1. If `Transfer-Encoding: chunked`, the returned reader will unchunk it
+    /// 1. The response to a `HEAD` request, and a `204` or `304` response, has no
+    ///    body, whatever the headers say.
+    /// 2. If `Transfer-Encoding: chunked`, the returned reader will unchunk it
     ///    and any `Content-Length` header is ignored.
-    /// 2. If `Content-Length` is set, the returned reader is limited to this byte
+    /// 3. If `Content-Length` is set, the returned reader is limited to this byte
     ///    length regardless of how many bytes the server sends.
-    /// 3. If no length header, the reader is until server stream end.
+    /// 4. If no length header, the reader is until server stream end.
+    /// 5. With the `gzip` feature, if `Content-Encoding: gzip` or `deflate` is set (or `br`
+    ///    with the `brotli` feature), the returned reader decodes the body (unless turned off on the agent). Other
+    ///    encodings, apart from `identity`, make the reader fail with an error.
     ///
     /// Example:
     ///
@@ -287,7 +656,113 @@ impl Response {
     /// assert_eq!(bytes.len(), len);
     /// # }
     /// ```
-    pub fn into_reader(self) -> impl Read {
+    pub fn into_reader(mut self) -> impl Read {
+        self.take_reader()
+    }
+
+    /// The events of a `text/event-stream` body, read as they arrive. See
+    /// [`Events`](struct.Events.html).
+    ///
+    /// ```
+    /// # #[cfg(feature = "sse")] {
+    /// let resp = "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n\
+    ///     event: greeting\n\
+    ///     data: hello\n\
+    ///     data: world\n\
+    ///     \n"
+    ///     .parse::<ureq::Response>()
+    ///     .unwrap();
+    ///
+    /// let event = resp.into_events().next().unwrap().unwrap();
+    /// assert_eq!(event.event, "greeting");
+    /// assert_eq!(event.data, "hello\nworld");
+    /// # }
+    /// ```
+    #[cfg(feature = "sse")]
+    pub fn into_events(mut self) -> Events {
+        Events::new(self.take_reader())
+    }
+
+    // the body reader, with any bytes peek() read in front.
+    fn take_reader(&mut self) -> BodyReader {
+        match self.peeked.take() {
+            Some(peeked) => Box::new(Cursor::new(peeked.bytes).chain(peeked.reader)),
+            None => self.body_reader(),
+        }
+    }
+
+    /// Take over the connection once the server switched protocols, for instance for a
+    /// WebSocket handshake. The request asks for it with the `Connection: Upgrade` and
+    /// `Upgrade` headers, this response's status line and headers are already read, and
+    /// nothing after them is.
+    ///
+    /// A status other than `101 Switching Protocols` is an
+    /// [`Error::Status`](enum.Error.html#variant.Status) that holds this response, and a
+    /// synthetic error is returned as is. Call this before [`peek()`](#method.peek).
+    ///
+    /// ```
+    /// use std::io::Read;
+    ///
+    /// let resp = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: echo\r\n\r\nhello"
+    ///     .parse::<ureq::Response>()
+    ///     .unwrap();
+    ///
+    /// let mut conn = resp.into_upgraded().unwrap();
+    /// let mut s = String::new();
+    /// conn.read_to_string(&mut s).unwrap();
+    ///
+    /// assert_eq!(s, "hello");
+    /// ```
+    pub fn into_upgraded(mut self) -> Result<Upgraded, Error> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+        if self.status != 101 {
+            return Err(Error::Status(self.status, Box::new(self)));
+        }
+        let stream = self
+            .stream
+            .take()
+            .ok_or_else(|| IoError::other("connection already in use by peek()"))?;
+        Ok(Upgraded::new(stream)?)
+    }
+
+    /// Read the first `n` bytes of the body, without using them up: they're still
+    /// the start of the body read by [`into_reader()`](#method.into_reader) and the
+    /// like. Useful to sniff the type of the content before deciding what to do.
+    ///
+    /// If the body is shorter, all of it is returned. Peeking again gives the same
+    /// bytes, reading more of the body if `n` is larger this time.
+    ///
+    /// ```
+    /// let mut resp = "HTTP/1.1 200 OK\r\n\r\n%PDF-1.4 and then some"
+    ///     .parse::<ureq::Response>()
+    ///     .unwrap();
+    ///
+    /// assert_eq!(resp.peek(5).unwrap(), b"%PDF-");
+    /// assert_eq!(resp.into_string().unwrap(), "%PDF-1.4 and then some");
+    /// ```
+    pub fn peek(&mut self, n: usize) -> Result<Vec<u8>, Error> {
+        if self.peeked.is_none() {
+            let reader = self.body_reader();
+            self.peeked = Some(Peeked {
+                bytes: vec![],
+                reader,
+            });
+        }
+        let peeked = self.peeked.as_mut().unwrap();
+        if peeked.bytes.len() < n {
+            let more = (n - peeked.bytes.len()) as u64;
+            (&mut peeked.reader)
+                .take(more)
+                .read_to_end(&mut peeked.bytes)?;
+        }
+        Ok(peeked.bytes[..n.min(peeked.bytes.len())].to_vec())
+    }
+
+    // The reader of the body, as described for into_reader(). The stream is
+    // taken out of the response.
+    fn body_reader(&mut self) -> BodyReader {
         //
         let is_http10 = self.http_version().eq_ignore_ascii_case("HTTP/1.0");
         let is_close = self
@@ -309,30 +784,154 @@ impl Response {
 
         let use_chunked = !is_http10 && !has_no_body && is_chunked;
 
-        let limit_bytes = if is_http10 || is_close {
-            None
-        } else if has_no_body {
+        // the codings are listed in the order they were applied, chunked last, so
+        // the others are undone after it, from the end of the list.
+        let transfer_codings: Vec<String> = if use_chunked {
+            self.all("transfer-encoding")
+                .into_iter()
+                .flat_map(|v| v.split(','))
+                .map(|c| c.trim().to_ascii_lowercase())
+                .filter(|c| !c.is_empty() && c != "chunked")
+                .collect()
+        } else {
+            vec![]
+        };
+
+        // without a Content-Length, the body ends when the server closes the
+        // connection, as legacy HTTP/1.0 servers do.
+        let limit_bytes = if has_no_body {
             // head requests never have a body
             Some(0)
         } else {
-            self.header("content-length")
-                .and_then(|l| l.parse::<usize>().ok())
+            match &self.encoded {
+                Some(encoded) => encoded.length,
+                None => self.content_length(),
+            }
+            .map(|l| l as usize)
         };
 
-        let stream = self.stream.expect("No reader in response?!");
-        let unit = self.unit;
-        let deadline = unit.as_ref().and_then(|u| u.deadline);
-        let stream = DeadlineStream::new(stream, deadline);
+        // only the trailers announced up front are kept, if any are.
+        let announced: Option<Vec<String>> = if self.has("trailer") {
+            let names = self.all("trailer").into_iter().flat_map(|v| v.split(','));
+            Some(names.map(|n| n.trim().to_string()).collect())
+        } else {
+            None
+        };
+
+        // HTTP/1.0 connections are closed after the response unless asked
+        // otherwise, which we don't.
+        let reusable = !is_http10 && !is_close;
+
+        #[cfg(any(feature = "gzip", feature = "brotli"))]
+        let content_encoding = match &self.encoded {
+            Some(encoded) if !has_no_body => Some(encoded.encoding.clone()),
+            _ => None,
+        };
 
-        match (use_chunked, limit_bytes) {
+        let stream = self.stream.take().expect("No reader in response?!");
+        let unit = self.unit.take();
+        let max_size = unit.as_ref().and_then(|u| u.max_response_size);
+        let deadline = unit.as_ref().and_then(|u| u.deadline);
+        let timeout_read = unit.as_ref().and_then(|u| u.read_timeout());
+        let timeout_write = unit.as_ref().and_then(|u| u.write_timeout());
+        let mut stream = DeadlineStream::new(stream, deadline, timeout_read, timeout_write);
+        stream.set_max_bytes_per_sec(unit.as_ref().and_then(|u| u.max_bytes_per_sec));
+        stream.set_cancel_handle(unit.as_ref().and_then(|u| u.cancel.clone()));
+        // the server may still expect the body we didn't send, so the
+        // connection isn't returned to the pool.
+        let unit = unit.filter(|u| !u.body_unsent && u.keepalive && reusable);
+
+        let reader = match (use_chunked, limit_bytes) {
             (true, _) => {
-                Box::new(PoolReturnRead::new(unit, ChunkDecoder::new(stream))) as Box<dyn Read>
+                let decoder = ChunkedRead::new(stream, self.trailers.clone(), announced);
+                Box::new(PoolReturnRead::new(unit, decoder)) as BodyReader
             }
             (false, Some(len)) => {
                 Box::new(PoolReturnRead::new(unit, LimitedRead::new(stream, len)))
             }
             (false, None) => Box::new(stream),
+        };
+
+        let reader = if transfer_codings.is_empty() {
+            reader
+        } else {
+            decoder::decode_transfer(&transfer_codings, reader)
+        };
+
+        #[cfg(any(feature = "gzip", feature = "brotli"))]
+        let reader = match content_encoding {
+            Some(enc) => decoder::decode(&enc, reader),
+            None => reader,
+        };
+
+        match max_size {
+            Some(max) => Box::new(SizeLimitRead::new(reader, max, limit_bytes)),
+            None => reader,
+        }
+    }
+
+    /// Copy the response body into `writer`, returning the number of bytes written.
+    ///
+    /// The body is read like [`into_reader()`](#method.into_reader) does, so the
+    /// timeouts of the request apply and a stalled download fails. The error is
+    /// then [`Error::Download`](enum.Error.html#variant.Download) with the number
+    /// of bytes written before it.
+    ///
+    /// ```no_run
+    /// let resp = ureq::get("http://example.com/large.bin")
+    ///     .timeout_read(10_000)
+    ///     .call();
+    ///
+    /// let mut file = std::fs::File::create("large.bin").unwrap();
+    /// let written = resp.copy_to(&mut file).unwrap();
+    /// ```
+    pub fn copy_to<W: Write>(self, writer: &mut W) -> Result<u64, Error> {
+        self.copy_to_with_progress(writer, |_| ())
+    }
+
+    /// Like [`copy_to()`](#method.copy_to), and calls `progress` with the number
+    /// of bytes written so far after each write.
+    ///
+    /// ```no_run
+    /// let resp = ureq::get("http://example.com/large.bin").call();
+    /// let total = resp
+    ///     .header("content-length")
+    ///     .and_then(|l| l.parse::<u64>().ok());
+    ///
+    /// let mut file = std::fs::File::create("large.bin").unwrap();
+    /// resp.copy_to_with_progress(&mut file, |written| {
+    ///     if let Some(total) = total {
+    ///         println!("{}%", written * 100 / total.max(1));
+    ///     }
+    /// })
+    /// .unwrap();
+    /// ```
+    pub fn copy_to_with_progress<W, F>(self, writer: &mut W, mut progress: F) -> Result<u64, Error>
+    where
+        W: Write,
+        F: FnMut(u64),
+    {
+        let buffer_size = self.unit.as_ref().and_then(|u| u.buffer_size);
+        let mut reader = self.into_reader();
+        let mut buf = vec![0; buffer_size.unwrap_or(8 * 1024).max(1)];
+        let mut written = 0;
+        loop {
+            let n = match reader.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(Error::Download(written, Box::new(e.into()))),
+            };
+            writer
+                .write_all(&buf[..n])
+                .map_err(|e| Error::Download(written, Box::new(e.into())))?;
+            written += n as u64;
+            progress(written);
         }
+        writer
+            .flush()
+            .map_err(|e| Error::Download(written, Box::new(e.into())))?;
+        Ok(written)
     }
 
     /// Turn this response into a String of the response body. By default uses `utf-8`,
@@ -361,7 +960,8 @@ impl Response {
     /// Requires feature `ureq = { version = "*", features = ["charset"] }`
     ///
     /// Attempts to respect the character encoding of the `Content-Type` header and
-    /// falls back to `utf-8`.
+    /// falls back to `utf-8`. This is also the case if the charset isn't known, and
+    /// bytes that aren't valid `utf-8` are replaced with `U+FFFD`.
     ///
     /// I.e. `Content-Length: text/plain; charset=iso-8859-1` would be decoded in latin-1.
     ///
@@ -371,41 +971,99 @@ impl Response {
             let encoding = encoding_from_whatwg_label(self.charset())
                 .or_else(|| encoding_from_whatwg_label(DEFAULT_CHARACTER_SET))
                 .unwrap();
-            let mut buf: Vec<u8> = vec![];
+            let mut buf: Vec<u8> = Vec::with_capacity(self.capacity_hint());
             self.into_reader().read_to_end(&mut buf)?;
             Ok(encoding.decode(&buf, DecoderTrap::Replace).unwrap())
         }
         #[cfg(not(feature = "charset"))]
         {
-            let mut buf: Vec<u8> = vec![];
+            let mut buf: Vec<u8> = Vec::with_capacity(self.capacity_hint());
             self.into_reader().read_to_end(&mut buf)?;
             Ok(String::from_utf8_lossy(&buf).to_string())
         }
     }
 
-    /// Turn this response into a (serde) JSON value of the response body.
+    /// Read the whole body, and give it back as a reader that can
+    /// [`Seek`](https://doc.rust-lang.org/std/io/trait.Seek.html), for formats that
+    /// need to jump around such as ZIP archives.
+    ///
+    /// Bodies up to 1 MiB are kept in memory, larger ones are written to a temporary
+    /// file, which is removed when the [`SeekableBody`](struct.SeekableBody.html) is
+    /// dropped. See [`into_seekable_with_limit()`](#method.into_seekable_with_limit)
+    /// to change where the line is.
+    ///
+    /// ```
+    /// use std::io::{Read, Seek, SeekFrom};
+    ///
+    /// let resp = "HTTP/1.1 200 OK\r\nContent-Length: 11\r\n\r\nhello world"
+    ///     .parse::<ureq::Response>()
+    ///     .unwrap();
+    ///
+    /// let mut body = resp.into_seekable().unwrap();
+    /// body.seek(SeekFrom::Start(6)).unwrap();
+    /// let mut end = String::new();
+    /// body.read_to_string(&mut end).unwrap();
+    ///
+    /// assert_eq!(end, "world");
+    /// ```
+    pub fn into_seekable(self) -> IoResult<SeekableBody> {
+        self.into_seekable_with_limit(DEFAULT_SPOOL_MEMORY_LIMIT)
+    }
+
+    /// Like [`into_seekable()`](#method.into_seekable), keeping bodies of up to
+    /// `memory_limit` bytes in memory. Larger ones go to a temporary file, so `0`
+    /// always uses a file and `usize::MAX` never does.
+    pub fn into_seekable_with_limit(self, memory_limit: usize) -> IoResult<SeekableBody> {
+        spool::spool(self.into_reader(), memory_limit)
+    }
+
+    // How much to allocate up front for reading the whole body. The server
+    // decides the Content-Length, so we don't trust it with more.
+    fn capacity_hint(&self) -> usize {
+        const MAX_CAPACITY_HINT: u64 = 1024 * 1024;
+        self.content_length()
+            .map(|len| len.min(MAX_CAPACITY_HINT) as usize)
+            .unwrap_or(0)
+    }
+
+    /// Deserialize the JSON body of this response into a type implementing the (serde)
+    /// Deserialize trait, or a `serde_json::Value` for any JSON.
     ///
     /// Requires feature `ureq = { version = "*", features = ["json"] }`
     ///
+    /// The body is deserialized as it's read from [`into_reader()`](#method.into_reader),
+    /// without first reading it all into a `String`, so the timeouts of the request apply
+    /// and failing to read gives the same errors. A body that isn't valid JSON for `T` is
+    /// an [`Error::BadJson`](enum.Error.html#variant.BadJson).
+    ///
     /// Example:
     ///
     /// ```
+    /// # use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Hello {
+    ///     hello: String,
+    /// }
+    ///
     /// let resp =
     ///     ureq::get("https://ureq.s3.eu-central-1.amazonaws.com/hello_world.json")
     ///         .call();
     ///
-    /// let json = resp.into_json().unwrap();
+    /// let json = resp.into_json::<Hello>().unwrap();
     ///
-    /// assert_eq!(json["hello"], "world");
+    /// assert_eq!(json.hello, "world");
     /// ```
     #[cfg(feature = "json")]
-    pub fn into_json(self) -> IoResult<serde_json::Value> {
+    pub fn into_json<T: DeserializeOwned>(self) -> Result<T, Error> {
         let reader = self.into_reader();
         serde_json::from_reader(reader).map_err(|e| {
-            IoError::new(
-                ErrorKind::InvalidData,
-                format!("Failed to read JSON: {}", e),
-            )
+            if e.is_io() {
+                // the read error as it was, so a timeout is still a timeout.
+                IoError::from(e).into()
+            } else {
+                Error::BadJson(e.to_string())
+            }
         })
     }
 
@@ -413,6 +1071,9 @@ impl Response {
     ///
     /// Requires feature `ureq = { version = "*", features = ["json"] }`
     ///
+    /// Like [`into_json()`](#method.into_json), with any failure as an `io::Error`. Other
+    /// errors than reading the body are wrapped in one of kind `InvalidData`.
+    ///
     /// Example:
     ///
     /// ```
@@ -432,16 +1093,43 @@ impl Response {
     /// assert_eq!(json.hello, "world");
     /// ```
     #[cfg(feature = "json")]
+    #[deprecated(note = "use into_json(), which returns a ureq::Error")]
     pub fn into_json_deserialize<T: DeserializeOwned>(self) -> IoResult<T> {
-        let reader = self.into_reader();
-        serde_json::from_reader(reader).map_err(|e| {
-            IoError::new(
-                ErrorKind::InvalidData,
-                format!("Failed to read JSON: {}", e),
-            )
+        self.into_json().map_err(|e| match e {
+            Error::Io(e) => e,
+            e => IoError::new(ErrorKind::InvalidData, e),
         })
     }
 
+    /// Deserialize each line of a newline delimited JSON body, as it arrives. See
+    /// [`JsonLines`](struct.JsonLines.html).
+    ///
+    /// Requires feature `ureq = { version = "*", features = ["json"] }`
+    ///
+    /// ```
+    /// # use serde::Deserialize;
+    ///
+    /// #[derive(Deserialize)]
+    /// struct Count {
+    ///     n: u32,
+    /// }
+    ///
+    /// let resp = "HTTP/1.1 200 OK\r\n\r\n{\"n\": 1}\n{\"n\": 2}\n"
+    ///     .parse::<ureq::Response>()
+    ///     .unwrap();
+    ///
+    /// let total: u32 = resp
+    ///     .into_json_lines::<Count>()
+    ///     .map(|count| count.unwrap().n)
+    ///     .sum();
+    ///
+    /// assert_eq!(total, 3);
+    /// ```
+    #[cfg(feature = "json")]
+    pub fn into_json_lines<T: DeserializeOwned>(mut self) -> JsonLines<T> {
+        JsonLines::new(self.take_reader())
+    }
+
     /// Create a response from a Read trait impl.
     ///
     /// This is hopefully useful for unit tests.
@@ -464,34 +1152,47 @@ impl Response {
     fn do_from_read(mut reader: impl Read) -> Result<Response, Error> {
         //
         // HTTP/1.1 200 OK\r\n
-        let status_line = read_next_line(&mut reader).map_err(|e| match e.kind() {
-            ErrorKind::ConnectionAborted => Error::BadStatusRead,
-            _ => Error::BadStatus,
-        })?;
+        let status_line = read_next_line(&mut reader)
+            .map_err(|e| read_error(e, Error::BadStatusRead, Error::BadStatus))?;
 
         let (index, status) = parse_status_line(status_line.as_str())?;
 
         let mut headers: Vec<Header> = Vec::new();
+        let mut raw_headers: Vec<u8> = Vec::new();
         loop {
-            let line = read_next_line(&mut reader).map_err(|_| Error::BadHeader)?;
+            let line = read_next_line(&mut reader)
+                .map_err(|e| read_error(e, Error::BadHeaderRead, Error::BadHeader))?;
             if line.is_empty() {
                 break;
             }
+            raw_headers.extend(line.as_bytes());
+            raw_headers.extend(b"\r\n");
             if let Ok(header) = line.as_str().parse::<Header>() {
                 headers.push(header);
             }
         }
+        frame_content_length(&mut headers)?;
 
         Ok(Response {
             url: None,
+            history: vec![],
+            redirect_responses: vec![],
             error: None,
             status_line,
             index,
             status,
             headers,
+            raw_headers,
             unit: None,
             stream: None,
             deadline: None,
+            trailers: Trailers::default(),
+            peeked: None,
+            encoded: None,
+            peer_certificate: None,
+            negotiated_protocol: None,
+            connection_reused: false,
+            timings: Timings::default(),
         })
     }
 
@@ -501,6 +1202,30 @@ impl Response {
     }
 }
 
+/// Leave at most one `Content-Length` header, so the body can't be framed in two
+/// ways (request smuggling). With `Transfer-Encoding` it is ignored (RFC 7230,
+/// section 3.3.3) and removed. Several that agree are merged, and ones that
+/// disagree are an `Error::BadHeader`.
+fn frame_content_length(headers: &mut Vec<Header>) -> Result<(), Error> {
+    if header::has_header(headers, "transfer-encoding") {
+        headers.retain(|h| !h.is_name("content-length"));
+        return Ok(());
+    }
+    let lengths: Vec<String> = header::get_all_headers(headers, "content-length")
+        .into_iter()
+        .flat_map(|v| v.split(','))
+        .map(|l| l.trim().to_string())
+        .collect();
+    if lengths.len() > 1 {
+        if lengths.iter().any(|l| *l != lengths[0]) {
+            return Err(Error::BadHeader);
+        }
+        headers.retain(|h| !h.is_name("content-length"));
+        headers.push(Header::new("Content-Length", &lengths[0]));
+    }
+    Ok(())
+}
+
 /// parse a line like: HTTP/1.1 200 OK\r\n
 fn parse_status_line(line: &str) -> Result<(ResponseStatusIndex, u16), Error> {
     //
@@ -557,6 +1282,9 @@ impl FromStr for Response {
 
 impl Into<Response> for Error {
     fn into(self) -> Response {
+        if let Error::Status(_, resp) = self {
+            return *resp;
+        }
         let status = self.status();
         let status_text = self.status_text().to_string();
         let body_text = self.body_text();
@@ -569,15 +1297,66 @@ impl Into<Response> for Error {
 /// "Give away" Unit and Stream to the response.
 ///
 /// *Internal API*
-pub(crate) fn set_stream(resp: &mut Response, url: String, unit: Option<Unit>, stream: Stream) {
+pub(crate) fn set_stream(resp: &mut Response, url: String, mut unit: Option<Unit>, stream: Stream) {
     resp.url = Some(url);
-    if let Some(unit) = &unit {
+    if let Some(unit) = &mut unit {
         resp.deadline = unit.deadline;
+        resp.history = std::mem::take(&mut unit.history);
+        resp.redirect_responses = std::mem::take(&mut unit.redirect_responses);
+        resp.connection_reused = unit.connection_reused;
+        resp.timings = *unit.timings.lock().unwrap();
+    }
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    {
+        let compression = unit.as_ref().map(|u| u.compression).unwrap_or(true);
+        let encoding = resp.header("content-encoding").filter(|_| compression);
+        if let Some(encoding) = encoding.map(|e| e.to_string()) {
+            let length = resp.content_length();
+            // once decoded, the body is neither in this encoding nor of this length.
+            if decoder::can_decode(&encoding) {
+                resp.headers
+                    .retain(|h| !h.is_name("content-encoding") && !h.is_name("content-length"));
+            }
+            resp.encoded = Some(Encoded { encoding, length });
+        }
     }
+    resp.peer_certificate = stream.peer_certificate();
+    resp.negotiated_protocol = stream.alpn_protocol();
     resp.unit = unit;
     resp.stream = Some(stream);
 }
 
+// Whether the error means the server closed (or reset) the connection.
+fn is_closed(err: &IoError) -> bool {
+    matches!(
+        err.kind(),
+        ErrorKind::ConnectionAborted | ErrorKind::ConnectionReset | ErrorKind::BrokenPipe
+    )
+}
+
+// Map an error reading the status line or headers. Timeouts keep their own
+// error, a closed connection becomes `closed`, and anything else `other`.
+fn read_error(err: IoError, closed: Error, other: Error) -> Error {
+    if is_closed(&err) {
+        return closed;
+    }
+    // With TLS 1.3 the server checks our client certificate after we consider
+    // the handshake done, so a rejection arrives as an alert on the first read.
+    #[cfg(all(feature = "tls", not(feature = "native-tls")))]
+    {
+        if let Some(tls_err) = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<rustls::TLSError>())
+        {
+            return Error::TlsHandshake(tls_err.to_string());
+        }
+    }
+    match Error::from(err) {
+        Error::Io(_) => other,
+        e => e,
+    }
+}
+
 fn read_next_line<R: Read>(reader: &mut R) -> IoResult<String> {
     let mut buf = Vec::new();
     let mut prev_byte_was_cr = false;
@@ -602,6 +1381,140 @@ fn read_next_line<R: Read>(reader: &mut R) -> IoResult<String> {
     }
 }
 
+/// Trailer headers of a chunked response, sent after the last chunk of the body.
+///
+/// Got from [`Response::trailers()`](struct.Response.html#method.trailers) before
+/// reading the body. Until all of the body is read, there are none.
+#[derive(Clone, Debug, Default)]
+pub struct Trailers(Arc<Mutex<Option<Vec<Header>>>>);
+
+impl Trailers {
+    /// The value of the named trailer, if the server sent it. `None` until the
+    /// end of the body is read.
+    pub fn get(&self, name: &str) -> Option<String> {
+        let trailers = self.0.lock().unwrap();
+        trailers
+            .as_ref()?
+            .iter()
+            .find(|h| h.is_name(name))
+            .map(|h| h.value().to_string())
+    }
+
+    /// Whether the body was read to the end, so that the trailers are known.
+    pub fn is_complete(&self) -> bool {
+        self.0.lock().unwrap().is_some()
+    }
+}
+
+fn bad_chunk(msg: String) -> IoError {
+    IoError::new(ErrorKind::InvalidData, Error::BadChunk(msg))
+}
+
+/// Decodes a chunked body, keeping the trailers that follow the last chunk.
+struct ChunkedRead<R> {
+    reader: R,
+    // what's left of the current chunk, None between chunks.
+    remaining: Option<usize>,
+    done: bool,
+    trailers: Trailers,
+    announced: Option<Vec<String>>,
+}
+
+impl<R: Read> ChunkedRead<R> {
+    fn new(reader: R, trailers: Trailers, announced: Option<Vec<String>>) -> Self {
+        ChunkedRead {
+            reader,
+            remaining: None,
+            done: false,
+            trailers,
+            announced,
+        }
+    }
+
+    // chunk-size [ BWS ";" chunk-ext ] CRLF, where the extensions, which we
+    // have no use for, are skipped.
+    fn read_chunk_size(&mut self) -> IoResult<usize> {
+        let line = read_next_line(&mut self.reader)?;
+        let size = line
+            .split(';')
+            .next()
+            .unwrap_or("")
+            .trim_matches(|c| c == ' ' || c == '\t');
+        if size.is_empty() || !size.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(bad_chunk(format!("chunk size is not hex: {:?}", size)));
+        }
+        usize::from_str_radix(size, 16)
+            .map_err(|_| bad_chunk(format!("chunk size too large: {}", size)))
+    }
+
+    fn read_crlf(&mut self) -> IoResult<()> {
+        let mut crlf = [0; 2];
+        self.reader.read_exact(&mut crlf)?;
+        if &crlf != b"\r\n" {
+            return Err(bad_chunk("chunk is longer than its size".to_string()));
+        }
+        Ok(())
+    }
+
+    // The trailer section ends with an empty line. They're only made known when
+    // all of it is read, which means the body was complete.
+    fn read_trailers(&mut self) -> IoResult<()> {
+        let mut headers = vec![];
+        loop {
+            let line = read_next_line(&mut self.reader)?;
+            if line.is_empty() {
+                break;
+            }
+            if let Ok(header) = line.as_str().parse::<Header>() {
+                let wanted = match &self.announced {
+                    Some(names) => names.iter().any(|n| header.is_name(n)),
+                    None => true,
+                };
+                if wanted {
+                    headers.push(header);
+                }
+            }
+        }
+        *self.trailers.0.lock().unwrap() = Some(headers);
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for ChunkedRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                let size = self.read_chunk_size()?;
+                if size == 0 {
+                    self.read_trailers()?;
+                    self.done = true;
+                    return Ok(0);
+                }
+                size
+            }
+        };
+        let max = remaining.min(buf.len());
+        let amount = self.reader.read(&mut buf[..max])?;
+        if amount == 0 {
+            return Err(IoError::new(
+                ErrorKind::InvalidData,
+                "response body closed before all chunks were read",
+            ));
+        }
+        if amount == remaining {
+            self.read_crlf()?;
+            self.remaining = None;
+        } else {
+            self.remaining = Some(remaining - amount);
+        }
+        Ok(amount)
+    }
+}
+
 /// Limits a `Read` to a content size (as set by a "Content-Length" header).
 struct LimitedRead<R> {
     reader: R,
@@ -637,8 +1550,8 @@ impl<R: Read> Read for LimitedRead<R> {
             // received, the recipient MUST consider the message to be
             // incomplete and close the connection.
             Ok(0) => Err(IoError::new(
-                ErrorKind::InvalidData,
-                "response body closed before all bytes were read",
+                ErrorKind::UnexpectedEof,
+                Error::BodyTruncated(self.limit as u64, self.position as u64),
             )),
             Ok(amount) => {
                 self.position += amount;
@@ -649,13 +1562,67 @@ impl<R: Read> Read for LimitedRead<R> {
     }
 }
 
+/// Fails reading once more than `max` bytes were read, or at once if the
+/// `Content-Length` says there's more than that.
+struct SizeLimitRead<R> {
+    reader: R,
+    max: u64,
+    declared: Option<usize>,
+    position: u64,
+}
+
+impl<R: Read> SizeLimitRead<R> {
+    fn new(reader: R, max: u64, declared: Option<usize>) -> Self {
+        SizeLimitRead {
+            reader,
+            max,
+            declared,
+            position: 0,
+        }
+    }
+
+    fn too_large(&self) -> IoError {
+        IoError::new(ErrorKind::InvalidData, Error::ResponseTooLarge(self.max))
+    }
+}
+
+impl<R: Read> Read for SizeLimitRead<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+        if self.declared.map(|len| len as u64 > self.max) == Some(true) {
+            return Err(self.too_large());
+        }
+        // ask for one byte past the limit, to know if the body goes on.
+        let left = (self.max - self.position).saturating_add(1);
+        let amount = if (buf.len() as u64) > left {
+            self.reader.read(&mut buf[..left as usize])?
+        } else {
+            self.reader.read(buf)?
+        };
+        self.position += amount as u64;
+        if self.position > self.max {
+            return Err(self.too_large());
+        }
+        Ok(amount)
+    }
+}
+
 #[test]
 fn short_read() {
     use std::io::Cursor;
     let mut lr = LimitedRead::new(Cursor::new(vec![b'a'; 3]), 10);
     let mut buf = vec![0; 1000];
-    let result = lr.read_to_end(&mut buf);
-    assert!(result.is_err());
+    let err = lr.read_to_end(&mut buf).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::UnexpectedEof);
+    assert!(matches!(Error::from(err), Error::BodyTruncated(10, 3)));
+}
+
+impl<R: Read> From<ChunkedRead<R>> for Stream
+where
+    Stream: From<R>,
+{
+    fn from(chunked_read: ChunkedRead<R>) -> Stream {
+        chunked_read.reader.into()
+    }
 }
 
 impl<R: Read> From<LimitedRead<R>> for Stream
@@ -671,14 +1638,22 @@ where
 ///
 /// "Content-Type: text/plain; charset=iso8859-1" -> "iso8859-1"
 ///
+/// The parameter name is case insensitive, the value may be quoted and there
+/// can be other parameters before or after it.
+///
 /// *Internal API*
 pub(crate) fn charset_from_content_type(header: Option<&str>) -> &str {
     header
         .and_then(|header| {
-            header.find(';').and_then(|semi| {
-                (&header[semi + 1..])
-                    .find('=')
-                    .map(|equal| (&header[semi + equal + 2..]).trim())
+            header.split(';').skip(1).find_map(|param| {
+                let mut parts = param.splitn(2, '=');
+                let name = parts.next()?.trim();
+                let value = parts.next()?.trim().trim_matches('"').trim();
+                if name.eq_ignore_ascii_case("charset") && !value.is_empty() {
+                    Some(value)
+                } else {
+                    None
+                }
             })
         })
         .unwrap_or(DEFAULT_CHARACTER_SET)
@@ -725,6 +1700,76 @@ mod tests {
         assert_eq!("iso-8859-4", resp.charset());
     }
 
+    #[test]
+    fn charset_params() {
+        let cases = [
+            ("text/plain; charset=iso-8859-1", "iso-8859-1"),
+            ("text/plain;charset=iso-8859-1", "iso-8859-1"),
+            ("text/plain; CharSet=Shift_JIS", "Shift_JIS"),
+            ("text/plain; charset=\"iso-8859-1\"", "iso-8859-1"),
+            ("text/plain; a=b; charset=iso-8859-1", "iso-8859-1"),
+            ("text/plain; charset=iso-8859-1; a=b", "iso-8859-1"),
+            ("text/plain; charset = iso-8859-1 ", "iso-8859-1"),
+            ("text/plain; format=flowed", "utf-8"),
+            ("text/plain; charset=", "utf-8"),
+            ("text/plain", "utf-8"),
+        ];
+        for (header, charset) in cases.iter() {
+            assert_eq!(
+                charset_from_content_type(Some(header)),
+                *charset,
+                "{}",
+                header
+            );
+        }
+        assert_eq!(charset_from_content_type(None), "utf-8");
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Response {
+        let mut cursor = Cursor::new(bytes.to_vec());
+        let mut resp = Response::do_from_read(&mut cursor).unwrap();
+        set_stream(&mut resp, "".into(), None, Stream::Cursor(cursor));
+        resp
+    }
+
+    #[test]
+    #[cfg(feature = "charset")]
+    fn into_string_latin1() {
+        let mut bytes = b"HTTP/1.1 200 OK\r\n\
+                      Content-Type: text/plain; charset=ISO-8859-1\r\n\
+                      \r\n"
+            .to_vec();
+        bytes.extend(b"H\xe4llo W\xf6rld");
+        let resp = from_bytes(&bytes);
+        assert_eq!(resp.into_string().unwrap(), "Hällo Wörld");
+    }
+
+    #[test]
+    #[cfg(feature = "charset")]
+    fn into_string_shift_jis() {
+        let mut bytes = b"HTTP/1.1 200 OK\r\n\
+                      Content-Type: text/html; charset=\"Shift_JIS\"\r\n\
+                      \r\n"
+            .to_vec();
+        // "日本" in Shift_JIS
+        bytes.extend(b"\x93\xfa\x96\x7b");
+        let resp = from_bytes(&bytes);
+        assert_eq!(resp.into_string().unwrap(), "日本");
+    }
+
+    #[test]
+    fn into_string_unknown_charset() {
+        let mut bytes = b"HTTP/1.1 200 OK\r\n\
+                      Content-Type: text/plain; charset=bogus\r\n\
+                      \r\n"
+            .to_vec();
+        bytes.extend(b"hello \xff");
+        let resp = from_bytes(&bytes);
+        assert_eq!(resp.charset(), "bogus");
+        // falls back to lossy utf-8.
+        assert_eq!(resp.into_string().unwrap(), "hello \u{fffd}");
+    }
+
     #[test]
     fn charset_default() {
         let s = "HTTP/1.1 200 OK\r\n\
@@ -757,7 +1802,7 @@ mod tests {
              \r\n\
              {\"hello\":\"world\"}";
         let resp = s.parse::<Response>().unwrap();
-        let v = resp.into_json().unwrap();
+        let v = resp.into_json::<serde_json::Value>().unwrap();
         let compare = "{\"hello\":\"world\"}"
             .parse::<serde_json::Value>()
             .unwrap();
@@ -766,6 +1811,7 @@ mod tests {
 
     #[test]
     #[cfg(feature = "json")]
+    #[allow(deprecated)]
     fn parse_deserialize_json() {
         use serde::Deserialize;
 
@@ -793,4 +1839,194 @@ mod tests {
         let v = resp.into_string().unwrap();
         assert_eq!(v, "Bad Status\n");
     }
+
+    #[test]
+    fn status_error_keeps_response() {
+        let resp = "HTTP/1.1 503 Service Unavailable\r\n\r\ntry later"
+            .parse::<Response>()
+            .unwrap();
+        let err = Error::Status(503, Box::new(resp));
+        assert_eq!(err.kind(), crate::ErrorKind::Status);
+        assert_eq!(err.status_text(), "Service Unavailable");
+        assert_eq!(err.to_string(), "Status 503 Service Unavailable");
+        let resp: Response = err.into();
+        assert!(!resp.synthetic());
+        assert_eq!(resp.into_string().unwrap(), "try later");
+    }
+
+    #[test]
+    fn into_result() {
+        assert!(Response::new(204, "No Content", "").into_result().is_ok());
+        assert!(Response::new(302, "Found", "").into_result().is_ok());
+        match Response::new(500, "Oops", "broken").into_result() {
+            Err(Error::Status(500, resp)) => assert_eq!(resp.into_string().unwrap(), "broken\n"),
+            r => panic!("expected a status error, got {:?}", r),
+        }
+        let resp: Response = Error::DnsFailed("nope".into()).into();
+        match resp.into_result() {
+            Err(err) => assert!(matches!(err, Error::DnsFailed(_))),
+            r => panic!("expected a transport error, got {:?}", r),
+        }
+    }
+
+    #[test]
+    fn status_helpers() {
+        let resp = Response::new(204, "No Content", "");
+        assert!(resp.ok() && !resp.is_redirect() && !resp.error());
+        let resp = Response::new(301, "Moved Permanently", "");
+        assert!(resp.is_redirect() && !resp.ok());
+        let resp = Response::new(418, "I'm a teapot", "");
+        assert!(resp.is_client_error() && !resp.is_server_error());
+        assert_eq!(resp.status_text(), "I'm a teapot");
+        let resp = Response::new(599, "Odd", "");
+        assert!(resp.is_server_error() && !resp.is_client_error());
+    }
+
+    #[test]
+    fn no_status_text() {
+        let resp = "HTTP/1.1 204\r\n\r\n".parse::<Response>().unwrap();
+        assert_eq!(resp.status(), 204);
+        assert_eq!(resp.status_text(), "");
+    }
+
+    #[test]
+    fn content_length() {
+        let len = |headers: &str| {
+            let s = format!("HTTP/1.1 200 OK\r\n{}\r\n", headers);
+            s.parse::<Response>().unwrap().content_length()
+        };
+        assert_eq!(len("Content-Length: 1234\r\n"), Some(1234));
+        assert_eq!(len(""), None);
+        assert_eq!(len("Content-Length: -1\r\n"), None);
+        assert_eq!(len("Content-Length: lots\r\n"), None);
+        assert_eq!(
+            len("Content-Length: 10\r\nTransfer-Encoding: chunked\r\n"),
+            None
+        );
+    }
+
+    #[test]
+    fn duplicate_content_length() {
+        let parse = |headers: &str| {
+            let s = format!("HTTP/1.1 200 OK\r\n{}\r\n", headers);
+            s.parse::<Response>()
+        };
+        let same = parse("Content-Length: 5\r\nContent-Length: 5\r\n").unwrap();
+        assert_eq!(same.all("content-length"), vec!["5"]);
+        let list = parse("Content-Length: 5, 5\r\n").unwrap();
+        assert_eq!(list.content_length(), Some(5));
+        let differ = parse("Content-Length: 5\r\nContent-Length: 50\r\n");
+        assert!(matches!(differ, Err(Error::BadHeader)));
+        let chunked = parse("Content-Length: 5\r\nTransfer-Encoding: chunked\r\n").unwrap();
+        assert!(!chunked.has("content-length"));
+    }
+
+    #[test]
+    fn raw_headers_kept_verbatim() {
+        let s = "HTTP/1.1 200 OK\r\n\
+                 Content-Length: 2\r\n\
+                 content-length: 2\r\n\
+                 no colon\r\n\
+                 X-Spaced:   odd  \r\n\
+                 \r\n\
+                 hi";
+        let resp = s.parse::<Response>().unwrap();
+        // the parsed headers are merged and cleaned up, the raw ones aren't.
+        assert_eq!(resp.all("content-length"), vec!["2"]);
+        assert_eq!(resp.header("x-spaced"), Some("odd"));
+        assert_eq!(
+            resp.raw_headers(),
+            &b"Content-Length: 2\r\ncontent-length: 2\r\nno colon\r\nX-Spaced:   odd  \r\n"[..]
+        );
+        assert_eq!(resp.into_string().unwrap(), "hi");
+    }
+
+    #[test]
+    fn copy_to_writer() {
+        let s = "HTTP/1.1 200 OK\r\n\
+                 Content-Length: 11\r\n\
+                 \r\n\
+                 hello world";
+        let resp = s.parse::<Response>().unwrap();
+        let mut out = vec![];
+        let mut seen = vec![];
+        let written = resp
+            .copy_to_with_progress(&mut out, |n| seen.push(n))
+            .unwrap();
+        assert_eq!(written, 11);
+        assert_eq!(out, b"hello world");
+        assert_eq!(seen.last(), Some(&11));
+    }
+
+    #[test]
+    fn repeated_headers() {
+        let s = "HTTP/1.1 200 OK\r\n\
+                 Set-Cookie: a=1\r\n\
+                 X-Other: x\r\n\
+                 set-cookie: b=2\r\n\
+                 \r\n";
+        let resp = s.parse::<Response>().unwrap();
+        assert_eq!(resp.header("SET-COOKIE"), Some("a=1"));
+        assert_eq!(resp.header_all("Set-Cookie"), vec!["a=1", "b=2"]);
+        assert!(resp.header_all("Link").is_empty());
+        assert_eq!(
+            resp.headers_names(),
+            vec!["set-cookie", "x-other", "set-cookie"]
+        );
+    }
+
+    // A reader that gives some bytes and then fails as if the connection was reset.
+    struct ResetAfter(Cursor<Vec<u8>>);
+
+    impl Read for ResetAfter {
+        fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
+            match self.0.read(buf)? {
+                0 => Err(IoError::new(ErrorKind::ConnectionReset, "reset")),
+                n => Ok(n),
+            }
+        }
+    }
+
+    #[test]
+    fn reset_during_status_line() {
+        let reader = ResetAfter(Cursor::new(b"HTTP/1.1 2".to_vec()));
+        let resp = Response::from_read(reader);
+        assert!(matches!(resp.synthetic_error(), Some(Error::BadStatusRead)));
+    }
+
+    #[test]
+    fn reset_during_headers() {
+        let reader = ResetAfter(Cursor::new(b"HTTP/1.1 200 OK\r\nContent-Le".to_vec()));
+        let resp = Response::from_read(reader);
+        assert!(matches!(resp.synthetic_error(), Some(Error::BadHeaderRead)));
+        assert_eq!(resp.status_text(), "Failed to read headers");
+    }
+
+    #[test]
+    fn peek_then_read() {
+        let mut resp = "HTTP/1.1 200 OK\r\n\
+             Transfer-Encoding: chunked\r\n\
+             \r\n\
+             3\r\nhel\r\nb\r\nlo world!!!\r\n0\r\n\r\n"
+            .parse::<Response>()
+            .unwrap();
+        // across a chunk boundary
+        assert_eq!(resp.peek(5).unwrap(), b"hello");
+        assert_eq!(resp.peek(2).unwrap(), b"he");
+        assert_eq!(resp.peek(8).unwrap(), b"hello wo");
+        assert_eq!(resp.into_string().unwrap(), "hello world!!!");
+    }
+
+    #[test]
+    fn peek_past_end() {
+        let mut resp = "HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello"
+            .parse::<Response>()
+            .unwrap();
+        assert_eq!(resp.peek(100).unwrap(), b"hello");
+        let mut reader = resp.into_reader();
+        let mut body = vec![];
+        reader.read_to_end(&mut body).unwrap();
+        assert_eq!(body, b"hello");
+        assert_eq!(reader.read(&mut [0; 8]).unwrap(), 0);
+    }
 }
@@ -0,0 +1,193 @@
+use std::env;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Bodies up to this size are kept in memory by `Response::into_seekable()`.
+pub(crate) const DEFAULT_SPOOL_MEMORY_LIMIT: usize = 1024 * 1024;
+
+/// A response body that can be read more than once, made by
+/// [`into_seekable()`](struct.Response.html#method.into_seekable).
+///
+/// The whole body is read before this is handed out. A small one is kept in memory,
+/// a larger one in a temporary file, which is removed when this is dropped.
+pub struct SeekableBody {
+    spool: Spool,
+    len: u64,
+}
+
+enum Spool {
+    Memory(Cursor<Vec<u8>>),
+    File(TempFile),
+}
+
+impl SeekableBody {
+    /// The length of the body in bytes.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Whether the body is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether the body was too large for memory and is kept in a temporary file.
+    pub fn is_file(&self) -> bool {
+        matches!(self.spool, Spool::File(_))
+    }
+}
+
+impl fmt::Debug for SeekableBody {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kind = if self.is_file() { "file" } else { "memory" };
+        write!(f, "SeekableBody({} bytes in {})", self.len, kind)
+    }
+}
+
+impl Read for SeekableBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.spool {
+            Spool::Memory(cursor) => cursor.read(buf),
+            Spool::File(temp) => temp.file().read(buf),
+        }
+    }
+}
+
+impl Seek for SeekableBody {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match &mut self.spool {
+            Spool::Memory(cursor) => cursor.seek(pos),
+            Spool::File(temp) => temp.file().seek(pos),
+        }
+    }
+}
+
+/// Read all of `reader`, into memory if it's at most `memory_limit` bytes,
+/// otherwise into a temporary file.
+pub(crate) fn spool(mut reader: impl Read, memory_limit: usize) -> io::Result<SeekableBody> {
+    let mut buf = vec![];
+    (&mut reader)
+        .take((memory_limit as u64).saturating_add(1))
+        .read_to_end(&mut buf)?;
+    if buf.len() <= memory_limit {
+        let len = buf.len() as u64;
+        return Ok(SeekableBody {
+            spool: Spool::Memory(Cursor::new(buf)),
+            len,
+        });
+    }
+    let mut temp = TempFile::create()?;
+    let file = temp.file();
+    file.write_all(&buf)?;
+    let len = buf.len() as u64 + io::copy(&mut reader, file)?;
+    file.flush()?;
+    file.seek(SeekFrom::Start(0))?;
+    Ok(SeekableBody {
+        spool: Spool::File(temp),
+        len,
+    })
+}
+
+// A file in the temp dir that is removed on drop.
+struct TempFile {
+    // only None while dropping, so it can be closed before removing.
+    file: Option<File>,
+    path: PathBuf,
+}
+
+impl TempFile {
+    fn create() -> io::Result<TempFile> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.subsec_nanos())
+            .unwrap_or(0);
+        loop {
+            let name = format!(
+                "ureq-{}-{}-{}.body",
+                process::id(),
+                nanos,
+                COUNT.fetch_add(1, Ordering::SeqCst)
+            );
+            let path = env::temp_dir().join(name);
+            let mut options = OpenOptions::new();
+            options.read(true).write(true).create_new(true);
+            // the temp dir is shared, other users must not read the body.
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            match options.open(&path) {
+                Ok(file) => {
+                    return Ok(TempFile {
+                        file: Some(file),
+                        path,
+                    })
+                }
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    fn file(&mut self) -> &mut File {
+        self.file.as_mut().expect("temp file is open")
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        // windows can't remove a file that is open.
+        self.file.take();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+#[test]
+fn spool_small_body_in_memory() {
+    let mut body = spool(&b"hello world"[..], 64).unwrap();
+    assert!(!body.is_file());
+    assert_eq!(body.len(), 11);
+    body.seek(SeekFrom::Start(6)).unwrap();
+    let mut s = String::new();
+    body.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "world");
+}
+
+#[test]
+fn spool_large_body_to_file() {
+    let data: Vec<u8> = (0..10_000u32).map(|i| i as u8).collect();
+    let mut body = spool(&data[..], 100).unwrap();
+    assert!(body.is_file());
+    assert_eq!(body.len(), 10_000);
+    let path = match &body.spool {
+        Spool::File(temp) => temp.path.clone(),
+        Spool::Memory(_) => unreachable!(),
+    };
+    let mut all = vec![];
+    body.read_to_end(&mut all).unwrap();
+    assert_eq!(all, data);
+    body.seek(SeekFrom::End(-3)).unwrap();
+    let mut end = vec![];
+    body.read_to_end(&mut end).unwrap();
+    assert_eq!(end, &data[9997..]);
+    assert!(path.exists());
+    drop(body);
+    assert!(!path.exists());
+}
+
+#[cfg(unix)]
+#[test]
+fn spool_file_only_readable_by_owner() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp = TempFile::create().unwrap();
+    let mode = fs::metadata(&temp.path).unwrap().permissions().mode();
+    assert_eq!(mode & 0o777, 0o600);
+}
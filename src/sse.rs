@@ -0,0 +1,151 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::time::Duration;
+
+/// One event of a `text/event-stream`, yielded by [`Events`](struct.Events.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Event {
+    /// The `event:` field, `message` when the server didn't send one.
+    pub event: String,
+    /// The `data:` fields, joined with `\n` when there are several.
+    pub data: String,
+    /// The last `id:` the server sent, in this event or an earlier one.
+    pub id: Option<String>,
+    /// The last `retry:` the server sent, how long to wait before reconnecting.
+    pub retry: Option<Duration>,
+}
+
+/// The events of a Server-Sent Events response, made by
+/// [`into_events()`](struct.Response.html#method.into_events).
+///
+/// Each call to `next()` reads the body until the blank line that ends an event, so it
+/// blocks while the server is quiet and fails when the timeouts or deadline of the
+/// request run out. Comment lines and unknown fields are skipped. The iterator ends
+/// when the server closes the connection, an event it didn't finish is dropped.
+pub struct Events {
+    reader: BufReader<Box<dyn Read + Send + Sync>>,
+    id: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Events {
+    pub(crate) fn new(reader: Box<dyn Read + Send + Sync>) -> Events {
+        Events {
+            reader: BufReader::new(reader),
+            id: None,
+            retry: None,
+        }
+    }
+
+    // the next line without its line ending, None at the end of the body.
+    fn read_line(&mut self) -> io::Result<Option<String>> {
+        let mut buf = vec![];
+        if self.reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(None);
+        }
+        if buf.last() == Some(&b'\n') {
+            buf.pop();
+            if buf.last() == Some(&b'\r') {
+                buf.pop();
+            }
+        }
+        Ok(Some(String::from_utf8_lossy(&buf).into_owned()))
+    }
+}
+
+impl Iterator for Events {
+    type Item = io::Result<Event>;
+
+    fn next(&mut self) -> Option<io::Result<Event>> {
+        let mut event = None;
+        let mut data: Option<String> = None;
+        loop {
+            let line = match self.read_line() {
+                Ok(Some(line)) => line,
+                Ok(None) => return None,
+                Err(e) => return Some(Err(e)),
+            };
+            if line.is_empty() {
+                // an event without data is not dispatched, though its id and retry count.
+                if let Some(data) = data.take() {
+                    return Some(Ok(Event {
+                        event: event.unwrap_or_else(|| "message".to_string()),
+                        data,
+                        id: self.id.clone(),
+                        retry: self.retry,
+                    }));
+                }
+                event = None;
+                continue;
+            }
+            let (field, value) = match line.find(':') {
+                Some(0) => continue, // comment
+                Some(i) => {
+                    let value = &line[i + 1..];
+                    (&line[..i], value.strip_prefix(' ').unwrap_or(value))
+                }
+                None => (&line[..], ""),
+            };
+            match field {
+                "event" => event = Some(value.to_string()),
+                "data" => match &mut data {
+                    Some(data) => {
+                        data.push('\n');
+                        data.push_str(value);
+                    }
+                    None => data = Some(value.to_string()),
+                },
+                "id" if !value.contains('\0') => self.id = Some(value.to_string()),
+                "retry" => {
+                    if let Ok(millis) = value.parse::<u64>() {
+                        self.retry = Some(Duration::from_millis(millis));
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+fn events(stream: &'static str) -> Vec<Event> {
+    Events::new(Box::new(stream.as_bytes()))
+        .map(|e| e.unwrap())
+        .collect()
+}
+
+#[test]
+fn parse_events() {
+    let events = events(
+        ": a comment\n\
+         data: first\n\
+         \n\
+         event: update\r\n\
+         data: line one\r\n\
+         data:line two\r\n\
+         id: 7\r\n\
+         \r\n\
+         retry: 2500\n\
+         data\n\
+         \n",
+    );
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0].event, "message");
+    assert_eq!(events[0].data, "first");
+    assert_eq!(events[0].id, None);
+    assert_eq!(events[1].event, "update");
+    assert_eq!(events[1].data, "line one\nline two");
+    assert_eq!(events[1].id.as_deref(), Some("7"));
+    assert_eq!(events[2].data, "");
+    assert_eq!(events[2].id.as_deref(), Some("7"));
+    assert_eq!(events[2].retry, Some(Duration::from_millis(2500)));
+}
+
+#[test]
+fn events_without_data_are_skipped() {
+    let events = events("event: ping\nid: 1\n\nretry: x\ndata: ok\n\ndata: unfinished\n");
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].event, "message");
+    assert_eq!(events[0].data, "ok");
+    assert_eq!(events[0].id.as_deref(), Some("1"));
+    assert_eq!(events[0].retry, None);
+}
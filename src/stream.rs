@@ -1,11 +1,17 @@
 use std::io::{Cursor, Error as IoError, ErrorKind, Read, Result as IoResult, Write};
+use std::net::IpAddr;
 use std::net::SocketAddr;
 use std::net::TcpStream;
 use std::net::ToSocketAddrs;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::thread;
 use std::time::Duration;
 use std::time::Instant;
 
 use chunked_transfer::Decoder as ChunkDecoder;
+use socket2::{Domain, Protocol, SockRef, Socket, TcpKeepalive, Type};
 
 #[cfg(feature = "tls")]
 use rustls::ClientSession;
@@ -20,6 +26,7 @@ use native_tls::{HandshakeError, TlsConnector, TlsStream};
 use crate::proxy::Proto;
 use crate::proxy::Proxy;
 
+use crate::cancel::{CancelHandle, CANCEL_POLL};
 use crate::error::Error;
 use crate::unit::Unit;
 
@@ -31,23 +38,141 @@ pub enum Stream {
     #[cfg(all(feature = "native-tls", not(feature = "tls")))]
     Https(TlsStream<TcpStream>),
     Cursor(Cursor<Vec<u8>>),
+    #[cfg(unix)]
+    Unix(UnixStream),
     #[cfg(test)]
-    Test(Box<dyn Read + Send>, Vec<u8>),
+    Test(Box<dyn Read + Send + Sync>, Vec<u8>),
 }
 
-// DeadlineStream wraps a stream such that read() will return an error
-// after the provided deadline, and sets timeouts on the underlying
-// TcpStream to ensure read() doesn't block beyond the deadline.
+// DeadlineStream wraps a stream such that read() and write() will return
+// an error after the provided deadline, or when a single call waits longer
+// than timeout_read or timeout_write. It sets timeouts on the underlying
+// TcpStream to ensure calls don't block beyond either of them.
 // When the From trait is used to turn a DeadlineStream back into a
 // Stream (by PoolReturningRead), the timeouts are removed.
+// It can also hold reads and writes to a maximum number of bytes per second,
+// with the waiting counting against the deadline.
+// With a CancelHandle, calls fail once it is cancelled, and waits on the
+// socket are cut into short ones to notice that while blocked.
 pub struct DeadlineStream {
     stream: Stream,
     deadline: Option<Instant>,
+    timeout_read: Option<Duration>,
+    timeout_write: Option<Duration>,
+    throttle_read: Option<Throttle>,
+    throttle_write: Option<Throttle>,
+    cancel: Option<CancelHandle>,
 }
 
 impl DeadlineStream {
-    pub(crate) fn new(stream: Stream, deadline: Option<Instant>) -> Self {
-        DeadlineStream { stream, deadline }
+    pub(crate) fn new(
+        stream: Stream,
+        deadline: Option<Instant>,
+        timeout_read: Option<Duration>,
+        timeout_write: Option<Duration>,
+    ) -> Self {
+        DeadlineStream {
+            stream,
+            deadline,
+            timeout_read,
+            timeout_write,
+            throttle_read: None,
+            throttle_write: None,
+            cancel: None,
+        }
+    }
+
+    pub(crate) fn set_cancel_handle(&mut self, cancel: Option<CancelHandle>) {
+        self.cancel = cancel;
+    }
+
+    // Limit reads and writes, each, to a number of bytes per second.
+    pub(crate) fn set_max_bytes_per_sec(&mut self, max_bytes_per_sec: Option<u64>) {
+        self.throttle_read = max_bytes_per_sec.map(Throttle::new);
+        self.throttle_write = max_bytes_per_sec.map(Throttle::new);
+    }
+
+    // Turn Nagle's algorithm off or back on, for connections over TCP.
+    pub(crate) fn set_nodelay(&self, nodelay: bool) -> IoResult<()> {
+        match self.stream.socket() {
+            Some(socket) => socket.set_nodelay(nodelay),
+            None => Ok(()),
+        }
+    }
+
+    // Change the per call read timeout, for reads that may wait less long.
+    pub(crate) fn set_timeout_read(&mut self, timeout_read: Option<Duration>) {
+        self.timeout_read = timeout_read;
+    }
+
+    // The timeout for the next call: the smaller of the per call timeout
+    // and the time left until the deadline.
+    fn timeout(&self, per_call: Option<Duration>) -> IoResult<Option<Duration>> {
+        match self.deadline {
+            Some(deadline) => {
+                let remaining = time_until_deadline(deadline)?;
+                Ok(Some(per_call.map_or(remaining, |t| t.min(remaining))))
+            }
+            None => Ok(per_call),
+        }
+    }
+
+    // The error for a call that timed out: DeadlineExceeded if the overall
+    // deadline is what stopped us, otherwise the given per call error.
+    fn timeout_error(&self, err: Error) -> IoError {
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => deadline_exceeded(),
+            _ => IoError::new(ErrorKind::TimedOut, err),
+        }
+    }
+
+    fn check_cancelled(&self) -> IoResult<()> {
+        match &self.cancel {
+            // not Interrupted, which read_to_end() and io::copy() try again.
+            Some(cancel) if cancel.is_cancelled() => Err(IoError::other(Error::Cancelled)),
+            _ => Ok(()),
+        }
+    }
+
+    // Make a call on the stream that waits at most timeout, set with set_timeout
+    // on the socket. With a cancel handle the socket waits at most CANCEL_POLL
+    // at a time, and the call is tried again until the timeout has passed.
+    fn call_with_timeout<T>(
+        &mut self,
+        timeout: Option<Duration>,
+        set_timeout: fn(&Stream, Option<Duration>) -> IoResult<()>,
+        timeout_err: fn() -> Error,
+        mut call: impl FnMut(&mut Stream) -> IoResult<T>,
+    ) -> IoResult<T> {
+        let started = Instant::now();
+        loop {
+            let wait = match self.cancel {
+                None => timeout,
+                Some(_) => {
+                    let left =
+                        timeout.map(|t| t.checked_sub(started.elapsed()).unwrap_or_default());
+                    let wait = left.map_or(CANCEL_POLL, |l| l.min(CANCEL_POLL));
+                    Some(wait.max(Duration::from_millis(1)))
+                }
+            };
+            if wait.is_some() {
+                set_timeout(&self.stream, wait)?;
+            }
+            match call(&mut self.stream) {
+                Err(e) if wait.is_some() && is_timeout(&e) => {
+                    self.check_cancelled()?;
+                    let timed_out = match (&self.cancel, timeout) {
+                        (None, _) => true,
+                        (Some(_), Some(timeout)) => started.elapsed() >= timeout,
+                        (Some(_), None) => false,
+                    };
+                    if timed_out {
+                        return Err(self.timeout_error(timeout_err()));
+                    }
+                }
+                r => return r,
+            }
+        }
     }
 }
 
@@ -56,25 +181,141 @@ impl From<DeadlineStream> for Stream {
         // Since we are turning this back into a regular, non-deadline Stream,
         // remove any timeouts we set.
         let stream = deadline_stream.stream;
-        if let Some(socket) = stream.socket() {
-            socket.set_read_timeout(None).unwrap();
-            socket.set_write_timeout(None).unwrap();
-        }
+        stream.set_read_timeout(None).unwrap();
+        stream.set_write_timeout(None).unwrap();
         stream
     }
 }
 
 impl Read for DeadlineStream {
     fn read(&mut self, buf: &mut [u8]) -> IoResult<usize> {
-        if let Some(deadline) = self.deadline {
-            let timeout = time_until_deadline(deadline)?;
-            if let Some(socket) = self.stream.socket() {
-                socket.set_read_timeout(Some(timeout))?;
-                socket.set_write_timeout(Some(timeout))?;
+        self.check_cancelled()?;
+        let deadline = self.deadline;
+        let len = match &mut self.throttle_read {
+            Some(throttle) => throttle.wait(buf.len(), deadline)?,
+            None => buf.len(),
+        };
+        let timeout = self.timeout(self.timeout_read)?;
+        let n = self.call_with_timeout(
+            timeout,
+            Stream::set_read_timeout,
+            || Error::ReadTimeout,
+            |stream| stream.read(&mut buf[..len]),
+        )?;
+        if let Some(throttle) = &mut self.throttle_read {
+            throttle.consume(n);
+        }
+        Ok(n)
+    }
+}
+
+// A write that times out is an error even if part of the buffer was sent
+// by an earlier call, so write_all() never reports a short write as done.
+impl Write for DeadlineStream {
+    fn write(&mut self, buf: &[u8]) -> IoResult<usize> {
+        self.check_cancelled()?;
+        let deadline = self.deadline;
+        let len = match &mut self.throttle_write {
+            Some(throttle) => throttle.wait(buf.len(), deadline)?,
+            None => buf.len(),
+        };
+        let timeout = self.timeout(self.timeout_write)?;
+        let n = self.call_with_timeout(
+            timeout,
+            Stream::set_write_timeout,
+            || Error::WriteTimeout,
+            |stream| stream.write(&buf[..len]),
+        )?;
+        if let Some(throttle) = &mut self.throttle_write {
+            throttle.consume(n);
+        }
+        Ok(n)
+    }
+    fn flush(&mut self) -> IoResult<()> {
+        self.check_cancelled()?;
+        let timeout = self.timeout(self.timeout_write)?;
+        self.call_with_timeout(
+            timeout,
+            Stream::set_write_timeout,
+            || Error::WriteTimeout,
+            |stream| stream.flush(),
+        )
+    }
+}
+
+// A token bucket letting through bytes_per_sec bytes a second. Bytes are let
+// through in chunks of a twentieth of a second, so the rate stays even.
+struct Throttle {
+    bytes_per_sec: u64,
+    allowance: f64,
+    last: Instant,
+}
+
+impl Throttle {
+    fn new(bytes_per_sec: u64) -> Self {
+        Throttle {
+            bytes_per_sec: bytes_per_sec.max(1),
+            allowance: 0.0,
+            last: Instant::now(),
+        }
+    }
+
+    fn chunk(&self) -> usize {
+        (self.bytes_per_sec / 20).max(1) as usize
+    }
+
+    // Wait until some of want bytes may pass, and return how many. If that
+    // means waiting past the deadline, wait until it and fail.
+    fn wait(&mut self, want: usize, deadline: Option<Instant>) -> IoResult<usize> {
+        let len = want.min(self.chunk());
+        self.refill();
+        if self.allowance < len as f64 {
+            let missing = len as f64 - self.allowance;
+            let wait = Duration::from_secs_f64(missing / self.bytes_per_sec as f64);
+            if let Some(deadline) = deadline {
+                let remaining = time_until_deadline(deadline)?;
+                if wait > remaining {
+                    thread::sleep(remaining);
+                    return Err(deadline_exceeded());
+                }
             }
+            thread::sleep(wait);
+            self.refill();
         }
-        self.stream.read(buf)
+        Ok(len)
     }
+
+    fn consume(&mut self, len: usize) {
+        self.allowance -= len as f64;
+    }
+
+    // Add the allowance for the time since the last refill, saving up at
+    // most one chunk.
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last).as_secs_f64();
+        self.last = now;
+        let allowance = self.allowance + elapsed * self.bytes_per_sec as f64;
+        self.allowance = allowance.min(self.chunk() as f64);
+    }
+}
+
+// The address of a host that is an IP literal, like `127.0.0.1`, or `[::1]` as
+// IPv6 addresses are written in urls.
+fn ip_literal(host: &str) -> Option<IpAddr> {
+    host.trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+// Socket timeouts show up as WouldBlock on unix and TimedOut on windows.
+fn is_timeout(err: &IoError) -> bool {
+    matches!(err.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+fn deadline_exceeded() -> IoError {
+    IoError::new(ErrorKind::TimedOut, Error::DeadlineExceeded)
 }
 
 // If the deadline is in the future, return the remaining time until
@@ -82,10 +323,7 @@ impl Read for DeadlineStream {
 fn time_until_deadline(deadline: Instant) -> IoResult<Duration> {
     let now = Instant::now();
     match deadline.checked_duration_since(now) {
-        None => Err(IoError::new(
-            ErrorKind::TimedOut,
-            "timed out reading response",
-        )),
+        None => Err(deadline_exceeded()),
         Some(duration) => Ok(duration),
     }
 }
@@ -103,6 +341,8 @@ impl ::std::fmt::Debug for Stream {
                 ))]
                 Stream::Https(_) => "https",
                 Stream::Cursor(_) => "cursor",
+                #[cfg(unix)]
+                Stream::Unix(_) => "unix",
                 #[cfg(test)]
                 Stream::Test(_, _) => "test",
             }
@@ -158,6 +398,59 @@ impl Stream {
         }
     }
 
+    // The DER encoded certificate the server presented in the TLS handshake.
+    pub(crate) fn peer_certificate(&self) -> Option<Vec<u8>> {
+        match self {
+            #[cfg(all(feature = "tls", not(feature = "native-tls")))]
+            Stream::Https(rustls_stream) => {
+                use rustls::Session;
+                // the end-entity certificate comes first.
+                let certs = rustls_stream.sess.get_peer_certificates()?;
+                certs.into_iter().next().map(|cert| cert.0)
+            }
+            #[cfg(all(feature = "native-tls", not(feature = "tls")))]
+            Stream::Https(tls_stream) => tls_stream.peer_certificate().ok()??.to_der().ok(),
+            _ => None,
+        }
+    }
+
+    /// The protocol the server picked with ALPN during the TLS handshake.
+    pub(crate) fn alpn_protocol(&self) -> Option<String> {
+        let protocol: Option<Vec<u8>> = match self {
+            #[cfg(all(feature = "tls", not(feature = "native-tls")))]
+            Stream::Https(rustls_stream) => {
+                use rustls::Session;
+                rustls_stream.sess.get_alpn_protocol().map(|p| p.to_vec())
+            }
+            #[cfg(all(feature = "native-tls", not(feature = "tls")))]
+            Stream::Https(tls_stream) => tls_stream.negotiated_alpn().ok()?,
+            _ => None,
+        };
+        protocol.map(|p| String::from_utf8_lossy(&p).into_owned())
+    }
+
+    pub(crate) fn set_read_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+        match self {
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_read_timeout(timeout),
+            _ => match self.socket() {
+                Some(socket) => socket.set_read_timeout(timeout),
+                None => Ok(()),
+            },
+        }
+    }
+
+    pub(crate) fn set_write_timeout(&self, timeout: Option<Duration>) -> IoResult<()> {
+        match self {
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.set_write_timeout(timeout),
+            _ => match self.socket() {
+                Some(socket) => socket.set_write_timeout(timeout),
+                None => Ok(()),
+            },
+        }
+    }
+
     #[cfg(test)]
     pub fn to_write_vec(&self) -> Vec<u8> {
         match self {
@@ -177,6 +470,8 @@ impl Read for Stream {
             ))]
             Stream::Https(stream) => read_https(stream, buf),
             Stream::Cursor(read) => read.read(buf),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.read(buf),
             #[cfg(test)]
             Stream::Test(reader, _) => reader.read(buf),
         }
@@ -240,6 +535,8 @@ impl Write for Stream {
             ))]
             Stream::Https(stream) => stream.write(buf),
             Stream::Cursor(_) => panic!("Write to read only stream"),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.write(buf),
             #[cfg(test)]
             Stream::Test(_, writer) => writer.write(buf),
         }
@@ -253,6 +550,8 @@ impl Write for Stream {
             ))]
             Stream::Https(stream) => stream.flush(),
             Stream::Cursor(_) => panic!("Flush read only stream"),
+            #[cfg(unix)]
+            Stream::Unix(stream) => stream.flush(),
             #[cfg(test)]
             Stream::Test(_, writer) => writer.flush(),
         }
@@ -264,17 +563,37 @@ pub(crate) fn connect_http(unit: &Unit) -> Result<Stream, Error> {
     let hostname = unit.url.host_str().unwrap();
     let port = unit.url.port().unwrap_or(80);
 
-    connect_host(unit, hostname, port).map(Stream::Http)
+    let deadline = connect_deadline(unit);
+    let sock = connect_host(unit, hostname, port, deadline)?;
+    set_request_timeouts(unit, &sock)?;
+
+    Ok(Stream::Http(sock))
+}
+
+// The socket path is the percent encoded host of a `unix://` url, as in
+// `unix://%2Fvar%2Frun%2Fdocker.sock/containers/json`.
+#[cfg(unix)]
+pub(crate) fn connect_unix(unit: &Unit) -> Result<Stream, Error> {
+    let host = unit.url.host_str().unwrap_or("");
+    let path = percent_encoding::percent_decode_str(host)
+        .decode_utf8()
+        .map_err(|_| Error::BadUrl(format!("Bad socket path: {}", host)))?;
+    if path.is_empty() {
+        return Err(Error::BadUrl("No socket path in unix url".to_string()));
+    }
+    let stream = UnixStream::connect(path.as_ref())
+        .map_err(|e| Error::ConnectionFailed(format!("{}: {}", path, e)))?;
+    Ok(Stream::Unix(stream))
 }
 
 #[cfg(all(feature = "tls", feature = "native-certs"))]
-fn configure_certs(config: &mut rustls::ClientConfig) {
+pub(crate) fn configure_certs(config: &mut rustls::ClientConfig) {
     config.root_store =
         rustls_native_certs::load_native_certs().expect("Could not load patform certs");
 }
 
 #[cfg(all(feature = "tls", not(feature = "native-certs")))]
-fn configure_certs(config: &mut rustls::ClientConfig) {
+pub(crate) fn configure_certs(config: &mut rustls::ClientConfig) {
     config
         .root_store
         .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
@@ -283,6 +602,7 @@ fn configure_certs(config: &mut rustls::ClientConfig) {
 #[cfg(all(feature = "tls", not(feature = "native-tls")))]
 pub(crate) fn connect_https(unit: &Unit) -> Result<Stream, Error> {
     use lazy_static::lazy_static;
+    use rustls::Session;
     use std::sync::Arc;
 
     lazy_static! {
@@ -296,53 +616,299 @@ pub(crate) fn connect_https(unit: &Unit) -> Result<Stream, Error> {
     let hostname = unit.url.host_str().unwrap();
     let port = unit.url.port().unwrap_or(443);
 
-    let sni = webpki::DNSNameRef::try_from_ascii_str(hostname)
-        .map_err(|err| Error::DnsFailed(err.to_string()))?;
-    let tls_conf: &Arc<rustls::ClientConfig> =
-        unit.tls_config.as_ref().map(|c| &c.0).unwrap_or(&*TLS_CONF);
-    let sess = rustls::ClientSession::new(&tls_conf, sni);
-
-    let sock = connect_host(unit, hostname, port)?;
+    let server_name = unit.tls_server_name.as_deref().unwrap_or(hostname);
+    let mut tls_conf: Arc<rustls::ClientConfig> = unit
+        .tls_config
+        .as_ref()
+        .map(|c| Arc::clone(&c.0))
+        .unwrap_or_else(|| Arc::clone(&*TLS_CONF));
+    // SNI is only for DNS names, and webpki can't check a certificate against an
+    // IP address. For IP literals we send no SNI and verify against a name no
+    // certificate has, so they need a custom or no verifier.
+    let server_name = if ip_literal(server_name).is_some() {
+        let mut conf = (*tls_conf).clone();
+        conf.enable_sni = false;
+        tls_conf = Arc::new(conf);
+        "ip-address.invalid"
+    } else {
+        server_name
+    };
+    if tls_conf.versions.is_empty() {
+        return Err(Error::TlsVersion(
+            "no TLS version in the allowed range".to_string(),
+        ));
+    }
+    let sni = webpki::DNSNameRef::try_from_ascii_str(server_name)
+        .map_err(|err| Error::DnsFailed(format!("{}: {}", server_name, err)))?;
+    let mut sess = rustls::ClientSession::new(&tls_conf, sni);
+
+    let deadline = connect_deadline(unit);
+    let mut sock = connect_host(unit, hostname, port, deadline)?;
+
+    // Finish the handshake here, rather than on the first write, so
+    // that it happens within the connect timeout.
+    let tls_started = Instant::now();
+    while sess.is_handshaking() {
+        set_connect_timeouts(&sock, deadline)?;
+        sess.complete_io(&mut sock)
+            .map_err(|e| handshake_error(deadline, rustls_handshake_error(e)))?;
+    }
+    unit.record_timing(|t| t.tls = tls_started.elapsed());
+    set_request_timeouts(unit, &sock)?;
 
-    let stream = rustls::StreamOwned::new(sess, sock);
+    let stream = Stream::Https(rustls::StreamOwned::new(sess, sock));
+    crate::tls::check_pins(stream.peer_certificate().as_deref(), &unit.pinned_keys)?;
 
-    Ok(Stream::Https(stream))
+    Ok(stream)
 }
 
 #[cfg(all(feature = "native-tls", not(feature = "tls")))]
 pub(crate) fn connect_https(unit: &Unit) -> Result<Stream, Error> {
+    use std::sync::Arc;
+
     let hostname = unit.url.host_str().unwrap();
     let port = unit.url.port().unwrap_or(443);
-    let sock = connect_host(unit, hostname, port)?;
+    let deadline = connect_deadline(unit);
+    let sock = connect_host(unit, hostname, port, deadline)?;
+    set_connect_timeouts(&sock, deadline)?;
+
+    let tls_connector = match &unit.tls_connector {
+        Some(connector) => connector.connector()?,
+        None => Arc::new(
+            TlsConnector::builder()
+                .min_protocol_version(Some(native_tls::Protocol::Tlsv12))
+                .build()
+                .map_err(|e| Error::TlsError(e))?,
+        ),
+    };
+    let server_name = match &unit.tls_server_name {
+        Some(name) => name.as_str(),
+        None => hostname.trim_matches(|c| c == '[' || c == ']'),
+    };
+    let tls_started = Instant::now();
+    let stream = tls_connector
+        .connect(server_name, sock)
+        .map_err(|e| match e {
+            HandshakeError::Failure(err) => handshake_error(deadline, native_handshake_error(err)),
+            _ => Error::BadStatusRead,
+        })?;
+    unit.record_timing(|t| t.tls = tls_started.elapsed());
+    set_request_timeouts(unit, stream.get_ref())?;
+
+    let stream = Stream::Https(stream);
+    crate::tls::check_pins(stream.peer_certificate().as_deref(), &unit.pinned_keys)?;
 
-    let tls_connector = TlsConnector::new().map_err(|e| Error::TlsError(e))?;
-    let stream = tls_connector.connect(&hostname.trim_matches(|c| c == '[' || c == ']'), sock).map_err(|e| match e {
-        HandshakeError::Failure(err) => Error::TlsError(err),
-        _ => Error::BadStatusRead,
-    })?;
+    Ok(stream)
+}
 
-    Ok(Stream::Https(stream))
+// native-tls errors are opaque, so tell version mismatches by the messages of
+// the platform libraries.
+#[cfg(all(feature = "native-tls", not(feature = "tls")))]
+fn native_handshake_error(err: native_tls::Error) -> Error {
+    let msg = err.to_string();
+    let lower = msg.to_ascii_lowercase();
+    let is_version = [
+        "protocol version",
+        "unsupported protocol",
+        "wrong version number",
+        "no protocols available",
+    ]
+    .iter()
+    .any(|m| lower.contains(m));
+    if is_version {
+        Error::TlsVersion(msg)
+    } else {
+        Error::TlsHandshake(msg)
+    }
 }
 
-pub(crate) fn connect_host(unit: &Unit, hostname: &str, port: u16) -> Result<TcpStream, Error> {
-    let deadline: Option<Instant> = if unit.timeout_connect > 0 {
+// The connect phase (TCP connect, proxy handshake and TLS handshake) must
+// finish before both the connect timeout and the overall deadline.
+fn connect_deadline(unit: &Unit) -> Option<Instant> {
+    let connect = if unit.timeout_connect > 0 {
         Instant::now().checked_add(Duration::from_millis(unit.timeout_connect))
     } else {
-        unit.deadline
+        None
     };
+    match (connect, unit.deadline) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+// Time left to connect, or a ConnectTimeout if there is none.
+fn time_until_connect_deadline(deadline: Option<Instant>) -> Result<Option<Duration>, Error> {
+    match deadline {
+        Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+            Some(duration) => Ok(Some(duration)),
+            None => Err(Error::ConnectTimeout),
+        },
+        None => Ok(None),
+    }
+}
+
+// Bound reads and writes on the socket by the connect deadline.
+fn set_connect_timeouts(stream: &TcpStream, deadline: Option<Instant>) -> Result<(), Error> {
+    if let Some(timeout) = time_until_connect_deadline(deadline)? {
+        stream.set_read_timeout(Some(timeout))?;
+        stream.set_write_timeout(Some(timeout))?;
+    }
+    Ok(())
+}
+
+// A failed TLS handshake is a ConnectTimeout if it ran out of time.
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+fn handshake_error(deadline: Option<Instant>, err: Error) -> Error {
+    match deadline {
+        Some(deadline) if Instant::now() >= deadline => Error::ConnectTimeout,
+        _ => err,
+    }
+}
+
+// TLS errors, like an alert from a server that turned down our client
+// certificate, fail the handshake. Other io errors pass through as is.
+#[cfg(all(feature = "tls", not(feature = "native-tls")))]
+fn rustls_handshake_error(err: IoError) -> Error {
+    use rustls::internal::msgs::enums::AlertDescription;
+    use rustls::TLSError;
+    match err.get_ref().and_then(|e| e.downcast_ref::<TLSError>()) {
+        // the server doesn't speak a version we offered, or the other way around.
+        Some(TLSError::AlertReceived(AlertDescription::ProtocolVersion)) => {
+            Error::TlsVersion("the server doesn't support the allowed TLS versions".to_string())
+        }
+        Some(tls_err @ TLSError::PeerIncompatibleError(msg)) if msg.contains("TLS v1.2") => {
+            Error::TlsVersion(tls_err.to_string())
+        }
+        Some(tls_err) => Error::TlsHandshake(tls_err.to_string()),
+        None => Error::from(err),
+    }
+}
+
+// Once connected, reads and writes are bound by the request's own
+// timeouts, not the connect timeout.
+fn set_request_timeouts(unit: &Unit, stream: &TcpStream) -> Result<(), Error> {
+    // rust's absurd api returns Err if we set 0.
+    // Setting it to None will disable the native system timeout
+    if let Some(deadline) = unit.deadline {
+        stream
+            .set_read_timeout(Some(time_until_deadline(deadline)?))
+            .ok();
+    } else if unit.timeout_read > 0 {
+        stream
+            .set_read_timeout(Some(Duration::from_millis(unit.timeout_read as u64)))
+            .ok();
+    } else {
+        stream.set_read_timeout(None).ok();
+    }
+
+    if let Some(deadline) = unit.deadline {
+        stream
+            .set_write_timeout(Some(time_until_deadline(deadline)?))
+            .ok();
+    } else if unit.timeout_write > 0 {
+        stream
+            .set_write_timeout(Some(Duration::from_millis(unit.timeout_write as u64)))
+            .ok();
+    } else {
+        stream.set_write_timeout(None).ok();
+    }
+    Ok(())
+}
+
+// Looks up addr with the system resolver, unless the agent has it cached.
+fn resolve_cached(
+    unit: &Unit,
+    addr: &str,
+    deadline: Option<Instant>,
+) -> Result<Vec<SocketAddr>, Error> {
+    let cached = match unit.agent.lock().unwrap().as_mut() {
+        Some(state) => state.dns.get(addr),
+        None => None,
+    };
+    if let Some(addrs) = cached {
+        return Ok(addrs);
+    }
+    // the agent isn't locked while looking up, which may take a while.
+    let addrs = lookup(addr, deadline, |addr| {
+        addr.to_socket_addrs().map(Iterator::collect)
+    })?;
+    if let Some(state) = unit.agent.lock().unwrap().as_mut() {
+        state.dns.insert(addr, &addrs);
+    }
+    Ok(addrs)
+}
 
-    // TODO: Find a way to apply deadline to DNS lookup.
-    let sock_addrs: Vec<SocketAddr> = match unit.proxy {
-        Some(ref proxy) => format!("{}:{}", proxy.server, proxy.port),
+// Resolves addr, giving up when the deadline passes. Neither the system resolver
+// nor a user provided one can be interrupted, so with a deadline the lookup runs
+// on a thread of its own, which is left to finish in the background if it's late.
+fn lookup<F>(addr: &str, deadline: Option<Instant>, resolve: F) -> Result<Vec<SocketAddr>, Error>
+where
+    F: FnOnce(&str) -> IoResult<Vec<SocketAddr>> + Send + 'static,
+{
+    // the system's message doesn't say which host it was.
+    let host = addr.rfind(':').map(|i| &addr[..i]).unwrap_or(addr);
+    let dns_failed = |e: IoError| Error::DnsFailed(format!("{}: {}", host, e));
+    let timeout = match time_until_connect_deadline(deadline)? {
+        Some(timeout) => timeout,
+        None => return resolve(addr).map_err(dns_failed),
+    };
+    let (tx, rx) = mpsc::channel();
+    let addr = addr.to_string();
+    thread::spawn(move || tx.send(resolve(&addr)));
+    match rx.recv_timeout(timeout) {
+        Ok(result) => result.map_err(dns_failed),
+        Err(RecvTimeoutError::Timeout) => Err(Error::ConnectTimeout),
+        Err(RecvTimeoutError::Disconnected) => {
+            Err(Error::DnsFailed(format!("{}: lookup failed", host)))
+        }
+    }
+}
+
+// Drops the cached addresses when none of them could be connected to.
+fn forget_addrs(unit: &Unit, hostname: &str, port: u16) {
+    let addr = match &unit.proxy {
+        Some(proxy) => format!("{}:{}", proxy.server, proxy.port),
         None => format!("{}:{}", hostname, port),
+    };
+    if let Some(state) = unit.agent.lock().unwrap().as_mut() {
+        state.dns.remove(&addr);
     }
-    .to_socket_addrs()
-    .map_err(|e| Error::DnsFailed(format!("{}", e)))?
-    .collect();
+}
+
+pub(crate) fn connect_host(
+    unit: &Unit,
+    hostname: &str,
+    port: u16,
+    deadline: Option<Instant>,
+) -> Result<TcpStream, Error> {
+    let dns_started = Instant::now();
+    let sock_addrs: Vec<SocketAddr> = match (&unit.proxy, ip_literal(hostname)) {
+        (None, Some(ip)) => vec![SocketAddr::new(ip, port)],
+        (proxy, _) => {
+            let addr = match proxy {
+                Some(proxy) => format!("{}:{}", proxy.server, proxy.port),
+                None => format!("{}:{}", hostname, port),
+            };
+            match &unit.resolver {
+                Some(resolver) => {
+                    let resolver = resolver.clone();
+                    lookup(&addr, deadline, move |addr| (resolver.0)(addr))?
+                }
+                None => resolve_cached(unit, &addr, deadline)?,
+            }
+        }
+    };
 
     if sock_addrs.is_empty() {
-        return Err(Error::DnsFailed(format!("No ip address for {}", hostname)));
+        let host = unit
+            .proxy
+            .as_ref()
+            .map(|p| &p.server[..])
+            .unwrap_or(hostname);
+        return Err(Error::DnsFailed(format!("{}: no address found", host)));
     }
+    let connect_started = Instant::now();
+    unit.record_timing(|t| t.dns = connect_started - dns_started);
 
     let proto = if let Some(ref proxy) = unit.proxy {
         Some(proxy.proto)
@@ -350,18 +916,34 @@ pub(crate) fn connect_host(unit: &Unit, hostname: &str, port: u16) -> Result<Tcp
         None
     };
 
+    let is_socks = matches!(proto, Some(Proto::SOCKS5) | Some(Proto::SOCKS5H));
+    let sock_addrs: Vec<SocketAddr> = match unit.local_address {
+        // a socket bound to a local address can only connect to the same family.
+        Some(local) if !is_socks => {
+            let same_family: Vec<_> = sock_addrs
+                .into_iter()
+                .filter(|a| a.is_ipv4() == local.is_ipv4())
+                .collect();
+            if same_family.is_empty() {
+                return Err(Error::BindFailed(format!(
+                    "{}: no address of the same family to connect to",
+                    local
+                )));
+            }
+            same_family
+        }
+        _ => sock_addrs,
+    };
+
     let mut any_err = None;
     let mut any_stream = None;
     // Find the first sock_addr that accepts a connection
     for sock_addr in sock_addrs {
         // ensure connect timeout or overall timeout aren't yet hit.
-        let timeout = match deadline {
-            Some(deadline) => Some(time_until_deadline(deadline)?),
-            None => None,
-        };
+        let timeout = time_until_connect_deadline(deadline)?;
 
         // connect with a configured timeout.
-        let stream = if Some(Proto::SOCKS5) == proto {
+        let stream = if is_socks {
             connect_socks5(
                 unit.proxy.to_owned().unwrap(),
                 deadline,
@@ -369,6 +951,13 @@ pub(crate) fn connect_host(unit: &Unit, hostname: &str, port: u16) -> Result<Tcp
                 hostname,
                 port,
             )
+        } else if let Some(socket) = bind_socket(unit, sock_addr)? {
+            let addr = sock_addr.into();
+            match timeout {
+                Some(timeout) => socket.connect_timeout(&addr, timeout),
+                None => socket.connect(&addr),
+            }
+            .map(|_| socket.into())
         } else if let Some(timeout) = timeout {
             TcpStream::connect_timeout(&sock_addr, timeout)
         } else {
@@ -386,37 +975,24 @@ pub(crate) fn connect_host(unit: &Unit, hostname: &str, port: u16) -> Result<Tcp
     let mut stream = if let Some(stream) = any_stream {
         stream
     } else {
-        let err = Error::ConnectionFailed(format!("{}", any_err.expect("Connect error")));
-        return Err(err);
+        let err = any_err.expect("Connect error");
+        forget_addrs(unit, hostname, port);
+        if err.kind() == ErrorKind::TimedOut {
+            return Err(Error::ConnectTimeout);
+        }
+        return Err(Error::ConnectionFailed(format!("{}", err)));
     };
 
-    // rust's absurd api returns Err if we set 0.
-    // Setting it to None will disable the native system timeout
-    if let Some(deadline) = deadline {
-        stream
-            .set_read_timeout(Some(time_until_deadline(deadline)?))
-            .ok();
-    } else if unit.timeout_read > 0 {
-        stream
-            .set_read_timeout(Some(Duration::from_millis(unit.timeout_read as u64)))
-            .ok();
-    } else {
-        stream.set_read_timeout(None).ok();
+    set_connect_timeouts(&stream, deadline)?;
+    if unit.tcp_nodelay {
+        stream.set_nodelay(true)?;
     }
-
-    if let Some(deadline) = deadline {
-        stream
-            .set_write_timeout(Some(time_until_deadline(deadline)?))
-            .ok();
-    } else if unit.timeout_write > 0 {
-        stream
-            .set_write_timeout(Some(Duration::from_millis(unit.timeout_write as u64)))
-            .ok();
-    } else {
-        stream.set_write_timeout(None).ok();
+    if let Some(idle) = unit.tcp_keepalive {
+        SockRef::from(&stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(idle))?;
     }
 
-    if proto == Some(Proto::HTTPConnect) {
+    // plain http requests are handed to the proxy as they are.
+    if proto == Some(Proto::HTTPConnect) && !unit.is_forward_proxied() {
         if let Some(ref proxy) = unit.proxy {
             write!(stream, "{}", proxy.connect(hostname, port)).unwrap();
             stream.flush()?;
@@ -435,10 +1011,35 @@ pub(crate) fn connect_host(unit: &Unit, hostname: &str, port: u16) -> Result<Tcp
             Proxy::verify_response(&proxy_response)?;
         }
     }
+    unit.record_timing(|t| t.connect = connect_started.elapsed());
 
     Ok(stream)
 }
 
+// A socket bound to the local address and device of the agent, to connect to addr.
+// None when neither is set, then a plain TcpStream::connect() will do.
+fn bind_socket(unit: &Unit, addr: SocketAddr) -> Result<Option<Socket>, Error> {
+    if unit.local_address.is_none() && unit.bind_device.is_none() {
+        return Ok(None);
+    }
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, Some(Protocol::TCP))
+        .map_err(|e| Error::ConnectionFailed(e.to_string()))?;
+    #[cfg(target_os = "linux")]
+    {
+        if let Some(device) = &unit.bind_device {
+            socket
+                .bind_device(Some(device.as_bytes()))
+                .map_err(|e| Error::BindFailed(format!("{}: {}", device, e)))?;
+        }
+    }
+    if let Some(local) = unit.local_address {
+        socket
+            .bind(&SocketAddr::new(local, 0).into())
+            .map_err(|e| Error::BindFailed(format!("{}: {}", local, e)))?;
+    }
+    Ok(Some(socket))
+}
+
 #[cfg(feature = "socks-proxy")]
 fn socks5_local_nslookup(hostname: &str, port: u16) -> Result<TargetAddr, std::io::Error> {
     let addrs: Vec<SocketAddr> = format!("{}:{}", hostname, port)
@@ -473,10 +1074,10 @@ fn connect_socks5(
     port: u16,
 ) -> Result<TcpStream, std::io::Error> {
     use socks::TargetAddr::Domain;
-    use std::net::{Ipv4Addr, Ipv6Addr};
-    use std::str::FromStr;
 
-    let host_addr = if Ipv4Addr::from_str(host).is_ok() || Ipv6Addr::from_str(host).is_ok() {
+    // socks5h leaves resolving hostnames to the proxy, socks5 resolves them here.
+    let is_ip = ip_literal(host).is_some();
+    let host_addr = if is_ip || proxy.proto == Proto::SOCKS5 {
         match socks5_local_nslookup(host, port) {
             Ok(addr) => addr,
             Err(err) => return Err(err),
@@ -1,7 +1,9 @@
+use crate::pool::PoolKey;
 use crate::test;
 use crate::test::testserver::{read_headers, TestServer};
-use std::io::{self, Write};
+use std::io::{self, BufRead, BufReader, Read, Write};
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Duration;
 
 use super::super::*;
@@ -29,6 +31,42 @@ fn agent_reuse_headers() {
     assert_eq!(resp.header("X-Call").unwrap(), "2");
 }
 
+#[test]
+fn agent_default_headers() {
+    let agent = agent()
+        .set_default_header("User-Agent", "my-client/1.0")
+        .set_default_header("Accept", "application/json")
+        .set_default_header("X-Api-Key", "foobar")
+        .build();
+
+    test::set_handler("/agent_default_headers", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = agent.get("test://host/agent_default_headers").call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nUser-Agent: my-client/1.0\r\n"));
+    assert!(s.contains("\r\nAccept: application/json\r\n"));
+    assert!(s.contains("\r\nX-Api-Key: foobar\r\n"));
+
+    // headers on the request win, whatever their case.
+    test::set_handler("/agent_default_headers", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = agent
+        .get("test://host/agent_default_headers")
+        .set("x-api-key", "other")
+        .set("accept", "text/html")
+        .call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nx-api-key: other\r\n"));
+    assert!(!s.contains("foobar"));
+    assert!(s.contains("\r\naccept: text/html\r\n"));
+    assert!(!s.contains("application/json"));
+    assert!(s.contains("\r\nUser-Agent: my-client/1.0\r\n"));
+}
+
 #[cfg(feature = "cookie")]
 #[test]
 fn agent_cookies() {
@@ -57,6 +95,35 @@ fn agent_cookies() {
     agent.get("test://host/agent_cookies").call();
 }
 
+#[cfg(feature = "cookie")]
+#[test]
+fn agent_cookie_store_save_load() {
+    let agent = agent();
+
+    test::set_handler("/agent_cookie_store_save", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Set-Cookie: session=abc123; Path=/; Max-Age=3600"],
+            vec![],
+        )
+    });
+    agent.get("test://host/agent_cookie_store_save").call();
+
+    let mut saved = vec![];
+    agent.cookie_store_save(&mut saved).unwrap();
+
+    let agent = crate::agent();
+    agent.cookie_store_load(&saved[..]).unwrap();
+
+    test::set_handler("/agent_cookie_store_load", |unit| {
+        assert_eq!(unit.header("cookie").unwrap(), "session=abc123");
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = agent.get("test://host/agent_cookie_store_load").call();
+    assert_eq!(resp.status(), 200);
+}
+
 // Handler that answers with a simple HTTP response, and times
 // out idle connections after 2 seconds.
 fn idle_timeout_handler(mut stream: TcpStream) -> io::Result<()> {
@@ -99,3 +166,663 @@ fn connection_reuse() {
     }
     assert_eq!(resp.status(), 200);
 }
+
+// Handler that answers like a legacy HTTP/1.0 server, ending the body by
+// closing the connection.
+fn http10_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    stream.write_all(b"HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\n")?;
+    stream.write_all(b"first half, ")?;
+    stream.flush()?;
+    std::thread::sleep(Duration::from_millis(50));
+    stream.write_all(b"second half")?;
+    Ok(())
+}
+
+#[test]
+fn http10_body_until_close() {
+    let testserver = TestServer::new(http10_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default().build();
+    let resp = agent.get(&url).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "first half, second half");
+}
+
+// Handler for an HTTP/1.0 response with a Content-Length, that keeps the
+// connection open until the client closes it.
+fn http10_content_length_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    stream.write_all(b"HTTP/1.0 200 OK\r\nContent-Length: 5\r\n\r\nhello")?;
+    read_headers(&stream);
+    Ok(())
+}
+
+#[test]
+fn http10_not_pooled() {
+    let testserver = TestServer::new(http10_content_length_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default().build();
+    let resp = agent.get(&url).call();
+    assert_eq!(resp.into_string().unwrap(), "hello");
+
+    let mut guard_state = agent.state.lock().unwrap();
+    let state = guard_state.as_mut().unwrap();
+    assert_eq!(state.pool().len(), 0);
+}
+
+// Handler for a response that closes the connection before the body is as long
+// as its Content-Length says.
+fn truncated_body_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nabc")
+}
+
+#[test]
+fn truncated_body_not_pooled() {
+    let testserver = TestServer::new(truncated_body_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default().build();
+    let resp = agent.get(&url).call();
+    let err = resp.into_string().unwrap_err();
+    assert!(matches!(Error::from(err), Error::BodyTruncated(10, 3)));
+
+    let mut guard_state = agent.state.lock().unwrap();
+    let state = guard_state.as_mut().unwrap();
+    assert_eq!(state.pool().len(), 0);
+}
+
+// Handler that answers the first request on a connection, and closes the
+// connection without answering when a second request arrives on it.
+fn close_on_second_request_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nresponse")?;
+    read_headers(&stream);
+    Ok(())
+}
+
+#[test]
+fn pool_retries() {
+    let testserver = TestServer::new(close_on_second_request_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default().build();
+
+    let resp = agent.get(&url).call();
+    assert_eq!(resp.status(), 200);
+    resp.into_string().unwrap();
+
+    // with no reconnects allowed, the dropped pooled connection is an error.
+    let resp = agent.get(&url).pool_retries(0).call();
+    assert!(resp
+        .synthetic_error()
+        .as_ref()
+        .unwrap()
+        .is_closed_connection());
+
+    let resp = agent.get(&url).call();
+    assert_eq!(resp.status(), 200);
+    resp.into_string().unwrap();
+
+    // by default, we reconnect once.
+    let resp = agent.get(&url).call();
+    assert_eq!(resp.status(), 200);
+}
+
+// Like close_on_second_request_handler, but the connection is closed halfway
+// through the headers of the second response.
+fn close_in_second_headers_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nresponse")?;
+    read_headers(&stream);
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-")?;
+    Ok(())
+}
+
+#[test]
+fn pool_retry_on_closed_during_headers() {
+    let testserver = TestServer::new(close_in_second_headers_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default().build();
+
+    let resp = agent.get(&url).call();
+    assert_eq!(resp.status(), 200);
+    resp.into_string().unwrap();
+
+    let resp = agent.get(&url).call();
+    assert!(resp.synthetic_error().is_none());
+    assert_eq!(resp.into_string().unwrap(), "response");
+}
+
+// Handler that echoes the body of the first request on a connection, and
+// closes the connection without answering when a second request arrives on it.
+fn echo_once_handler(mut stream: TcpStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    for count in 0.. {
+        let mut len = 0;
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some(value) = line.strip_prefix("Content-Length: ") {
+                len = value.parse().unwrap();
+            }
+        }
+        let mut body = vec![0; len];
+        reader.read_exact(&mut body)?;
+        if count > 0 {
+            break;
+        }
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n", len)?;
+        stream.write_all(&body)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn pool_retry_resends_body() {
+    let testserver = TestServer::new(echo_once_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default().build();
+
+    let resp = agent.put(&url).send_string("first");
+    assert_eq!(resp.into_string().unwrap(), "first");
+
+    let resp = agent.put(&url).send_bytes(b"second");
+    assert!(resp.synthetic_error().is_none());
+    assert_eq!(resp.into_string().unwrap(), "second");
+}
+
+static KEEPALIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+// Handler that counts connections and answers every request on each one.
+fn keepalive_handler(mut stream: TcpStream) -> io::Result<()> {
+    KEEPALIVE_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+    loop {
+        read_headers(&stream);
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nresponse")?;
+    }
+}
+
+#[test]
+fn pool_keeps_connections_per_host() {
+    let testserver = TestServer::new(keepalive_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default().max_idle_connections_per_host(2).build();
+
+    // two requests in flight at once need two connections.
+    let concurrent = |agent: &Agent| {
+        let resp1 = agent.get(&url).call();
+        let resp2 = agent.get(&url).call();
+        assert_eq!(resp1.into_string().unwrap(), "response");
+        assert_eq!(resp2.into_string().unwrap(), "response");
+    };
+    concurrent(&agent);
+    assert_eq!(KEEPALIVE_CONNECTIONS.load(Ordering::SeqCst), 2);
+    {
+        let mut guard_state = agent.state.lock().unwrap();
+        let state = guard_state.as_mut().unwrap();
+        assert_eq!(state.pool().len(), 2);
+    }
+
+    // both are reused from the pool.
+    concurrent(&agent);
+    assert_eq!(KEEPALIVE_CONNECTIONS.load(Ordering::SeqCst), 2);
+}
+
+static NO_KEEPALIVE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+fn no_keepalive_handler(mut stream: TcpStream) -> io::Result<()> {
+    NO_KEEPALIVE_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+    loop {
+        read_headers(&stream);
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nresponse")?;
+    }
+}
+
+#[test]
+fn pool_off() {
+    let testserver = TestServer::new(no_keepalive_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default().pool(false).build();
+
+    for _ in 0..2 {
+        let resp = agent.get(&url).call();
+        assert_eq!(resp.into_string().unwrap(), "response");
+    }
+    assert_eq!(NO_KEEPALIVE_CONNECTIONS.load(Ordering::SeqCst), 2);
+    let mut guard_state = agent.state.lock().unwrap();
+    let state = guard_state.as_mut().unwrap();
+    assert_eq!(state.pool().len(), 0);
+}
+
+#[test]
+fn no_keepalive_sends_connection_close() {
+    test::set_handler("/no_keepalive_sends_connection_close", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/no_keepalive_sends_connection_close")
+        .no_keepalive()
+        .call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nConnection: close\r\n"));
+
+    test::set_handler("/no_keepalive_sends_connection_close", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/no_keepalive_sends_connection_close").call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(!s.contains("Connection:"));
+}
+
+static HEAD_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+// Handler that answers every request with the headers of a 500 byte body,
+// and no body, as is right for HEAD requests.
+fn head_handler(mut stream: TcpStream) -> io::Result<()> {
+    HEAD_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+    loop {
+        read_headers(&stream);
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 500\r\n\r\n")?;
+    }
+}
+
+#[test]
+fn head_reads_no_body() {
+    let testserver = TestServer::new(head_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default().build();
+
+    for _ in 0..2 {
+        let resp = agent.head(&url).timeout_read(2000).call();
+        assert_eq!(resp.header("content-length"), Some("500"));
+        assert_eq!(resp.into_string().unwrap(), "");
+    }
+    // the connection went back to the pool, and was reused.
+    assert_eq!(HEAD_CONNECTIONS.load(Ordering::SeqCst), 1);
+}
+
+static REUSED_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+fn reused_handler(mut stream: TcpStream) -> io::Result<()> {
+    REUSED_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+    loop {
+        read_headers(&stream);
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nresponse")?;
+    }
+}
+
+#[test]
+fn response_tells_connection_reused() {
+    let testserver = TestServer::new(reused_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default().build();
+
+    let resp = agent.get(&url).call();
+    assert!(!resp.connection_reused());
+    assert_eq!(resp.into_string().unwrap(), "response");
+
+    let resp = agent.get(&url).call();
+    assert!(resp.connection_reused());
+    assert_eq!(resp.into_string().unwrap(), "response");
+    assert_eq!(REUSED_CONNECTIONS.load(Ordering::SeqCst), 1);
+}
+
+fn timings_handler(mut stream: TcpStream) -> io::Result<()> {
+    loop {
+        read_headers(&stream);
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nresponse")?;
+    }
+}
+
+#[test]
+fn timings_of_new_and_reused_connection() {
+    let testserver = TestServer::new(timings_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default().build();
+
+    let resp = agent.get(&url).call();
+    let timings = resp.timings();
+    assert!(timings.connect > Duration::from_secs(0));
+    assert_eq!(timings.tls, Duration::from_secs(0));
+    assert!(timings.total >= timings.dns + timings.connect + timings.first_byte);
+    assert_eq!(resp.into_string().unwrap(), "response");
+
+    let resp = agent.get(&url).call();
+    let timings = resp.timings();
+    assert_eq!(timings.dns, Duration::from_secs(0));
+    assert_eq!(timings.connect, Duration::from_secs(0));
+    assert!(timings.first_byte > Duration::from_secs(0));
+    assert!(timings.total >= timings.first_byte);
+}
+
+#[test]
+fn connect_ipv6_literal() {
+    // not every machine has IPv6 loopback.
+    let listener = match std::net::TcpListener::bind("[::1]:0") {
+        Ok(listener) => listener,
+        Err(_) => return,
+    };
+    let port = listener.local_addr().unwrap().port();
+    std::thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream.try_clone().unwrap());
+        let mut host = String::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line).unwrap();
+            if line.trim_end().is_empty() {
+                break;
+            }
+            if line.starts_with("Host: ") {
+                host = line.trim_end()["Host: ".len()..].to_string();
+            }
+        }
+        let body = host;
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+            body.len(),
+            body
+        )
+        .unwrap();
+    });
+    let resp = get(&format!("http://[::1]:{}/", port)).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), format!("[::1]:{}", port));
+}
+
+#[test]
+fn custom_resolver() {
+    use std::net::ToSocketAddrs;
+    let testserver = TestServer::new(idle_timeout_handler);
+    let port = testserver.port;
+    let agent = agent()
+        .resolver(move |addr| {
+            assert_eq!(addr, format!("api.example.com:{}", port));
+            format!("localhost:{}", port)
+                .to_socket_addrs()
+                .map(Iterator::collect)
+        })
+        .build();
+    let resp = agent
+        .get(&format!("http://api.example.com:{}/", port))
+        .call();
+    assert_eq!(resp.into_string().unwrap(), "response");
+}
+
+#[test]
+fn custom_resolver_error() {
+    let agent = agent()
+        .resolver(|_| Err(io::Error::new(io::ErrorKind::NotFound, "no such host")))
+        .build();
+    let resp = agent.get("http://api.example.com/").call();
+    match resp.synthetic_error() {
+        Some(Error::DnsFailed(msg)) => assert_eq!(msg, "api.example.com: no such host"),
+        err => panic!("expected a dns error, got {:?}", err),
+    }
+}
+
+#[test]
+fn resolver_no_addresses() {
+    let agent = agent().resolver(|_| Ok(vec![])).build();
+    let resp = agent.get("http://api.example.com/").call();
+    match resp.synthetic_error() {
+        Some(Error::DnsFailed(msg)) => assert_eq!(msg, "api.example.com: no address found"),
+        err => panic!("expected a dns error, got {:?}", err),
+    }
+}
+
+#[test]
+fn tcp_nodelay() {
+    let testserver = TestServer::new(idle_timeout_handler);
+    let url = format!("http://localhost:{}/", testserver.port);
+    for &nodelay in &[false, true] {
+        let agent = agent().tcp_nodelay(nodelay).build();
+        let resp = agent.get(&url).call();
+        assert_eq!(resp.into_string().unwrap(), "response");
+
+        let mut guard_state = agent.state.lock().unwrap();
+        let state = guard_state.as_mut().unwrap();
+        let url = url::Url::parse(&url).unwrap();
+        match state.pool().try_get_connection(&PoolKey::new(&url, &None)) {
+            Some(crate::stream::Stream::Http(sock)) => {
+                assert_eq!(sock.nodelay().unwrap(), nodelay)
+            }
+            s => panic!("expected a pooled connection, got {:?}", s),
+        }
+    }
+}
+
+#[test]
+fn tcp_keepalive() {
+    let testserver = TestServer::new(idle_timeout_handler);
+    let url = format!("http://localhost:{}/", testserver.port);
+    for &idle in &[None, Some(Duration::from_secs(42))] {
+        let mut builder = agent();
+        if let Some(idle) = idle {
+            builder.tcp_keepalive(idle);
+        }
+        let agent = builder.build();
+        let resp = agent.get(&url).call();
+        assert_eq!(resp.into_string().unwrap(), "response");
+
+        let mut guard_state = agent.state.lock().unwrap();
+        let state = guard_state.as_mut().unwrap();
+        let url = url::Url::parse(&url).unwrap();
+        match state.pool().try_get_connection(&PoolKey::new(&url, &None)) {
+            Some(crate::stream::Stream::Http(sock)) => {
+                let sock = socket2::SockRef::from(&sock);
+                assert_eq!(sock.keepalive().unwrap(), idle.is_some());
+                #[cfg(unix)]
+                {
+                    if let Some(idle) = idle {
+                        assert_eq!(sock.keepalive_time().unwrap(), idle);
+                    }
+                }
+            }
+            s => panic!("expected a pooled connection, got {:?}", s),
+        }
+    }
+}
+
+// Answers with the address the connection came from.
+fn peer_address_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    let peer = stream.peer_addr()?.ip().to_string();
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        peer.len(),
+        peer
+    )
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn local_address() {
+    let testserver = TestServer::new(peer_address_handler);
+    let url = format!("http://127.0.0.1:{}/", testserver.port);
+    // all of 127.0.0.0/8 is this host on linux.
+    let agent = agent().local_address("127.0.0.2".parse().unwrap()).build();
+    let resp = agent.get(&url).call();
+    assert_eq!(resp.into_string().unwrap(), "127.0.0.2");
+}
+
+#[test]
+fn local_address_bind_failed() {
+    let testserver = TestServer::new(peer_address_handler);
+    let url = format!("http://127.0.0.1:{}/", testserver.port);
+    // TEST-NET-1, not an address of this host.
+    let resp = agent()
+        .local_address("192.0.2.1".parse().unwrap())
+        .build()
+        .get(&url)
+        .call();
+    assert!(matches!(resp.synthetic_error(), Some(Error::BindFailed(_))));
+
+    // an IPv6 source can't reach an IPv4 server.
+    let resp = agent()
+        .local_address("::1".parse().unwrap())
+        .build()
+        .get(&url)
+        .call();
+    assert!(matches!(resp.synthetic_error(), Some(Error::BindFailed(_))));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn bind_device_failed() {
+    let testserver = TestServer::new(peer_address_handler);
+    let url = format!("http://127.0.0.1:{}/", testserver.port);
+    let agent = agent().bind_device("nosuchdev0").build();
+    let resp = agent.get(&url).call();
+    assert!(matches!(resp.synthetic_error(), Some(Error::BindFailed(_))));
+}
+
+static IDLE_AGE_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+fn idle_age_handler(mut stream: TcpStream) -> io::Result<()> {
+    IDLE_AGE_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+    loop {
+        read_headers(&stream);
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nresponse")?;
+    }
+}
+
+#[test]
+fn pool_skips_expired_connections() {
+    let testserver = TestServer::new(idle_age_handler);
+    let url = format!("http://localhost:{}", testserver.port);
+    let agent = Agent::default()
+        .max_idle_age(Duration::from_millis(100))
+        .build();
+
+    let resp = agent.get(&url).call();
+    assert_eq!(resp.into_string().unwrap(), "response");
+    // reused while fresh.
+    let resp = agent.get(&url).call();
+    assert_eq!(resp.into_string().unwrap(), "response");
+    assert_eq!(IDLE_AGE_CONNECTIONS.load(Ordering::SeqCst), 1);
+
+    // too old to reuse, so a new connection is opened.
+    std::thread::sleep(Duration::from_millis(200));
+    let resp = agent.get(&url).call();
+    assert_eq!(resp.into_string().unwrap(), "response");
+    assert_eq!(IDLE_AGE_CONNECTIONS.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn dns_cache() {
+    let testserver = TestServer::new(idle_timeout_handler);
+    let addr = format!("localhost:{}", testserver.port);
+    let agent = agent().build();
+    let resp = agent.get(&format!("http://{}/", addr)).call();
+    assert_eq!(resp.into_string().unwrap(), "response");
+    let mut guard_state = agent.state.lock().unwrap();
+    let state = guard_state.as_mut().unwrap();
+    assert!(state.dns.get(&addr).is_some());
+}
+
+#[test]
+fn dns_cache_off() {
+    let testserver = TestServer::new(idle_timeout_handler);
+    let addr = format!("localhost:{}", testserver.port);
+    let agent = agent().dns_cache_ttl(Duration::from_secs(0)).build();
+    let resp = agent.get(&format!("http://{}/", addr)).call();
+    assert_eq!(resp.into_string().unwrap(), "response");
+    let mut guard_state = agent.state.lock().unwrap();
+    let state = guard_state.as_mut().unwrap();
+    assert!(state.dns.get(&addr).is_none());
+}
+
+#[test]
+fn dns_cache_forgets_on_connect_failure() {
+    // a port nothing listens on anymore
+    let port = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let addr = format!("localhost:{}", port);
+    let agent = agent().build();
+    let resp = agent.get(&format!("http://{}/", addr)).call();
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::ConnectionFailed(_))
+    ));
+    let mut guard_state = agent.state.lock().unwrap();
+    let state = guard_state.as_mut().unwrap();
+    assert!(state.dns.get(&addr).is_none());
+}
+
+#[test]
+fn agent_is_send_sync() {
+    fn is_send_sync<T: Send + Sync>() {}
+    is_send_sync::<Agent>();
+}
+
+static SHARED_AGENT_CONNECTIONS: AtomicUsize = AtomicUsize::new(0);
+
+fn shared_agent_handler(mut stream: TcpStream) -> io::Result<()> {
+    SHARED_AGENT_CONNECTIONS.fetch_add(1, Ordering::SeqCst);
+    loop {
+        read_headers(&stream);
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 8\r\n\r\nresponse")?;
+    }
+}
+
+#[test]
+fn shared_agent_across_threads() {
+    let testserver = TestServer::new(shared_agent_handler);
+    let url = format!("http://localhost:{}/", testserver.port);
+    let agent = std::sync::Arc::new(agent().max_idle_connections_per_host(4).build());
+    let threads: Vec<_> = (0..4)
+        .map(|_| {
+            let agent = agent.clone();
+            let url = url.clone();
+            std::thread::spawn(move || {
+                for _ in 0..10 {
+                    let resp = agent.get(&url).call();
+                    assert_eq!(resp.into_string().unwrap(), "response");
+                }
+            })
+        })
+        .collect();
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    // connections are only opened when all others are in use.
+    assert!(SHARED_AGENT_CONNECTIONS.load(Ordering::SeqCst) <= 4);
+    let mut guard_state = agent.state.lock().unwrap();
+    let state = guard_state.as_mut().unwrap();
+    assert!(state.pool().len() >= 1);
+}
+
+#[test]
+fn agent_request_defaults() {
+    let base = agent()
+        .timeout(Duration::from_secs(7))
+        .redirects(2)
+        .user_agent("base/1.0")
+        .clone();
+    let agent = base.clone().redirects(0).build();
+    let mut req = agent.get("test://host/agent_request_defaults");
+    assert_eq!(req.timeout, Some(Duration::from_secs(7)));
+    assert_eq!(req.redirects, 0);
+    assert_eq!(base.build().get("/").redirects, 2);
+
+    test::set_handler("/agent_request_defaults", |unit| {
+        assert_eq!(unit.header("user-agent"), Some("base/1.0"));
+        test::make_response(302, "Found", vec!["Location: /elsewhere"], vec![])
+    });
+    // no redirects, so the 302 is given back.
+    let resp = req.call();
+    assert_eq!(resp.status(), 302);
+    // the request's own setting wins.
+    assert_eq!(agent.get("/").redirects(3).redirects, 3);
+}
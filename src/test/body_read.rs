@@ -80,3 +80,437 @@ fn no_reader_on_head() {
     reader.read_to_string(&mut text).unwrap();
     assert_eq!(text, "");
 }
+
+#[cfg(feature = "gzip")]
+fn gzipped(data: &[u8]) -> Vec<u8> {
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    let mut enc = GzEncoder::new(vec![], Compression::default());
+    enc.write_all(data).unwrap();
+    enc.finish().unwrap()
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn gzip_content_length() {
+    test::set_handler("/gzip_content_length", |_unit| {
+        let body = gzipped(b"hello world!!!");
+        let len = format!("Content-Length: {}", body.len());
+        test::make_response(200, "OK", vec!["Content-Encoding: gzip", &len], body)
+    });
+    let resp = get("test://host/gzip_content_length").call();
+    // neither describes the decoded body.
+    assert_eq!(resp.header("Content-Encoding"), None);
+    assert_eq!(resp.content_length(), None);
+    assert_eq!(resp.into_string().unwrap(), "hello world!!!");
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn gzip_chunked() {
+    test::set_handler("/gzip_chunked", |_unit| {
+        let gz = gzipped(b"hello world!!!");
+        let (a, b) = gz.split_at(gz.len() / 2);
+        let mut body = vec![];
+        for part in &[a, b] {
+            body.extend(format!("{:x}\r\n", part.len()).into_bytes());
+            body.extend_from_slice(part);
+            body.extend(b"\r\n");
+        }
+        body.extend(b"0\r\n\r\n");
+        test::make_response(
+            200,
+            "OK",
+            vec!["Transfer-Encoding: chunked", "Content-Encoding: gzip"],
+            body,
+        )
+    });
+    let resp = get("test://host/gzip_chunked").call();
+    assert_eq!(resp.into_string().unwrap(), "hello world!!!");
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn gzip_transfer_encoding() {
+    test::set_handler("/gzip_transfer_encoding", |_unit| {
+        // gzipped once as the content, and once more for the transfer.
+        let gz = gzipped(&gzipped(b"hello world!!!"));
+        let mut body = format!("{:x}\r\n", gz.len()).into_bytes();
+        body.extend(gz);
+        body.extend(b"\r\n0\r\n\r\n");
+        test::make_response(
+            200,
+            "OK",
+            vec!["Transfer-Encoding: gzip, chunked", "Content-Encoding: gzip"],
+            body,
+        )
+    });
+    let resp = get("test://host/gzip_transfer_encoding").call();
+    assert_eq!(resp.into_string().unwrap(), "hello world!!!");
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn deflate_content_length() {
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+    test::set_handler("/deflate_content_length", |_unit| {
+        let mut enc = ZlibEncoder::new(vec![], Compression::default());
+        enc.write_all(b"hello world!!!").unwrap();
+        let body = enc.finish().unwrap();
+        let len = format!("Content-Length: {}", body.len());
+        test::make_response(200, "OK", vec!["Content-Encoding: deflate", &len], body)
+    });
+    let resp = get("test://host/deflate_content_length").call();
+    assert_eq!(resp.into_string().unwrap(), "hello world!!!");
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn gzip_compression_off() {
+    test::set_handler("/gzip_compression_off", |_unit| {
+        let body = gzipped(b"hello world!!!");
+        let len = format!("Content-Length: {}", body.len());
+        test::make_response(200, "OK", vec!["Content-Encoding: gzip", &len], body)
+    });
+    let agent = agent().compression(false).build();
+    let resp = agent.get("test://host/gzip_compression_off").call();
+    assert_eq!(resp.header("Content-Encoding"), Some("gzip"));
+    assert!(resp.content_length().is_some());
+    let mut bytes = vec![];
+    resp.into_reader().read_to_end(&mut bytes).unwrap();
+    assert_eq!(bytes, gzipped(b"hello world!!!"));
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn gzip_accept_encoding() {
+    test::set_handler("/gzip_accept_encoding", |_unit| {
+        test::make_response(200, "OK", vec!["Content-Length: 5"], b"hello".to_vec())
+    });
+    let resp = get("test://host/gzip_accept_encoding").call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nAccept-Encoding: gzip, deflate"));
+    // a server that ignores the header still works.
+    assert_eq!(resp.into_string().unwrap(), "hello");
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn gzip_accept_encoding_user_set() {
+    test::set_handler("/gzip_accept_encoding_user_set", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Content-Encoding: identity", "Content-Length: 5"],
+            b"hello".to_vec(),
+        )
+    });
+    let resp = get("test://host/gzip_accept_encoding_user_set")
+        .set("Accept-Encoding", "identity")
+        .call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nAccept-Encoding: identity\r\n"));
+    assert!(!s.contains("Accept-Encoding: gzip"));
+    assert_eq!(resp.into_string().unwrap(), "hello");
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn gzip_no_accept_encoding_when_off() {
+    test::set_handler("/gzip_no_accept_encoding_when_off", |_unit| {
+        test::make_response(200, "OK", vec!["Content-Length: 0"], vec![])
+    });
+    let agent = agent().compression(false).build();
+    let resp = agent
+        .get("test://host/gzip_no_accept_encoding_when_off")
+        .call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(!s.contains("Accept-Encoding"));
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn unsupported_content_encoding() {
+    test::set_handler("/unsupported_content_encoding", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Content-Encoding: compress", "Content-Length: 5"],
+            b"hello".to_vec(),
+        )
+    });
+    let resp = get("test://host/unsupported_content_encoding").call();
+    // not decoded, so kept as is.
+    assert_eq!(resp.header("Content-Encoding"), Some("compress"));
+    assert_eq!(resp.content_length(), Some(5));
+    let err = resp.into_string().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "Unsupported Content-Encoding: compress");
+}
+
+#[test]
+fn unsupported_transfer_encoding() {
+    test::set_handler("/unsupported_transfer_encoding", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Transfer-Encoding: compress, chunked"],
+            b"5\r\nhello\r\n0\r\n\r\n".to_vec(),
+        )
+    });
+    let resp = get("test://host/unsupported_transfer_encoding").call();
+    let err = resp.into_string().unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+    assert_eq!(err.to_string(), "Unsupported Transfer-Encoding: compress");
+}
+
+// "hello world!!!" as a brotli stream of one uncompressed meta-block.
+#[cfg(feature = "brotli")]
+const BROTLI_HELLO: &[u8] = b"\xd0\x00\x10hello world!!!\x03";
+
+#[test]
+#[cfg(feature = "brotli")]
+fn brotli_content_length() {
+    test::set_handler("/brotli_content_length", |_unit| {
+        let len = format!("Content-Length: {}", BROTLI_HELLO.len());
+        test::make_response(
+            200,
+            "OK",
+            vec!["Content-Encoding: br", &len],
+            BROTLI_HELLO.to_vec(),
+        )
+    });
+    let resp = get("test://host/brotli_content_length").call();
+    assert_eq!(resp.into_string().unwrap(), "hello world!!!");
+}
+
+#[test]
+#[cfg(feature = "brotli")]
+fn brotli_chunked() {
+    test::set_handler("/brotli_chunked", |_unit| {
+        let (a, b) = BROTLI_HELLO.split_at(7);
+        let mut body = vec![];
+        for part in &[a, b] {
+            body.extend(format!("{:x}\r\n", part.len()).into_bytes());
+            body.extend_from_slice(part);
+            body.extend(b"\r\n");
+        }
+        body.extend(b"0\r\n\r\n");
+        test::make_response(
+            200,
+            "OK",
+            vec!["Transfer-Encoding: chunked", "Content-Encoding: br"],
+            body,
+        )
+    });
+    let resp = get("test://host/brotli_chunked").call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nAccept-Encoding: ") && s.contains("br\r\n"));
+    assert_eq!(resp.into_string().unwrap(), "hello world!!!");
+}
+
+#[test]
+#[cfg(all(feature = "brotli", feature = "gzip"))]
+fn brotli_and_gzip_by_content_encoding() {
+    test::set_handler("/brotli_and_gzip_1", |_unit| {
+        let body = gzipped(b"hello world!!!");
+        let len = format!("Content-Length: {}", body.len());
+        test::make_response(200, "OK", vec!["Content-Encoding: gzip", &len], body)
+    });
+    test::set_handler("/brotli_and_gzip_2", |_unit| {
+        let len = format!("Content-Length: {}", BROTLI_HELLO.len());
+        test::make_response(
+            200,
+            "OK",
+            vec!["Content-Encoding: br", &len],
+            BROTLI_HELLO.to_vec(),
+        )
+    });
+    let resp = get("test://host/brotli_and_gzip_1").call();
+    assert_eq!(resp.into_string().unwrap(), "hello world!!!");
+    let resp = get("test://host/brotli_and_gzip_2").call();
+    assert_eq!(resp.into_string().unwrap(), "hello world!!!");
+}
+
+fn too_large(err: std::io::Error) -> bool {
+    matches!(Error::from(err), Error::ResponseTooLarge(10))
+}
+
+#[test]
+fn max_response_size_content_length() {
+    test::set_handler("/max_response_size_content_length", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Content-Length: 11"],
+            "hello world".to_string().into_bytes(),
+        )
+    });
+    let agent = agent().max_response_size(10).build();
+    let resp = agent
+        .get("test://host/max_response_size_content_length")
+        .call();
+    let mut reader = resp.into_reader();
+    let mut buf = [0; 1];
+    // rejected before reading anything
+    assert!(too_large(reader.read(&mut buf).unwrap_err()));
+}
+
+#[test]
+fn max_response_size_chunked() {
+    test::set_handler("/max_response_size_chunked", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Transfer-Encoding: chunked"],
+            "5\r\nhello\r\n6\r\n world\r\n0\r\n\r\n"
+                .to_string()
+                .into_bytes(),
+        )
+    });
+    let agent = agent().max_response_size(10).build();
+    let resp = agent.get("test://host/max_response_size_chunked").call();
+    assert!(too_large(resp.into_string().unwrap_err()));
+}
+
+#[test]
+fn max_response_size_until_close() {
+    test::set_handler("/max_response_size_until_close", |_unit| {
+        test::make_response(200, "OK", vec![], "hello world".to_string().into_bytes())
+    });
+    let agent = agent().max_response_size(10).build();
+    let resp = agent
+        .get("test://host/max_response_size_until_close")
+        .call();
+    assert!(too_large(resp.into_string().unwrap_err()));
+}
+
+#[test]
+fn max_response_size_exact() {
+    test::set_handler("/max_response_size_exact", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Transfer-Encoding: chunked"],
+            "5\r\nhello\r\n5\r\nworld\r\n0\r\n\r\n"
+                .to_string()
+                .into_bytes(),
+        )
+    });
+    let agent = agent().max_response_size(10).build();
+    let resp = agent.get("test://host/max_response_size_exact").call();
+    assert_eq!(resp.into_string().unwrap(), "helloworld");
+}
+
+#[test]
+fn chunked_trailers() {
+    test::set_handler("/chunked_trailers", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Transfer-Encoding: chunked", "Trailer: X-Content-SHA256"],
+            "5\r\nhello\r\n0\r\nX-Content-SHA256: 2cf24dba\r\nX-Other: no\r\n\r\n"
+                .to_string()
+                .into_bytes(),
+        )
+    });
+    let resp = get("test://host/chunked_trailers").call();
+    let trailers = resp.trailers();
+    let mut reader = resp.into_reader();
+    let mut buf = [0; 5];
+    reader.read_exact(&mut buf).unwrap();
+    // not known until the last chunk is read
+    assert!(!trailers.is_complete());
+    assert_eq!(trailers.get("X-Content-SHA256"), None);
+    let mut rest = vec![];
+    reader.read_to_end(&mut rest).unwrap();
+    assert!(trailers.is_complete());
+    assert_eq!(
+        trailers.get("x-content-sha256").as_deref(),
+        Some("2cf24dba")
+    );
+    // not announced
+    assert_eq!(trailers.get("X-Other"), None);
+}
+
+#[test]
+fn chunked_no_trailers() {
+    test::set_handler("/chunked_no_trailers", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Transfer-Encoding: chunked"],
+            "5\r\nhello\r\n0\r\n\r\n".to_string().into_bytes(),
+        )
+    });
+    let resp = get("test://host/chunked_no_trailers").call();
+    let trailers = resp.trailers();
+    assert_eq!(resp.into_string().unwrap(), "hello");
+    assert!(trailers.is_complete());
+    assert_eq!(trailers.get("X-Content-SHA256"), None);
+}
+
+#[test]
+fn chunk_extensions() {
+    test::set_handler("/chunk_extensions", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Transfer-Encoding: chunked"],
+            "5;ext=val\r\nhello\r\n6 ; a=\"b;c\"; d\r\n world\r\n0;last\r\n\r\n"
+                .to_string()
+                .into_bytes(),
+        )
+    });
+    let resp = get("test://host/chunk_extensions").call();
+    assert_eq!(resp.into_string().unwrap(), "hello world");
+}
+
+#[test]
+fn chunk_size_not_hex() {
+    test::set_handler("/chunk_size_not_hex", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Transfer-Encoding: chunked"],
+            "5\r\nhello\r\nzz\r\n world\r\n0\r\n\r\n"
+                .to_string()
+                .into_bytes(),
+        )
+    });
+    let resp = get("test://host/chunk_size_not_hex").call();
+    let err = resp.into_string().unwrap_err();
+    assert!(matches!(Error::from(err), Error::BadChunk(_)));
+}
+
+#[test]
+fn into_seekable_spools_large_body() {
+    use std::io::{Seek, SeekFrom};
+    test::set_handler("/into_seekable_spools_large_body", |_| {
+        test::make_response(
+            200,
+            "OK",
+            vec!["Content-Length: 26"],
+            b"abcdefghijklmnopqrstuvwxyz".to_vec(),
+        )
+    });
+    let resp = get("test://host/into_seekable_spools_large_body").call();
+    let mut body = resp.into_seekable_with_limit(10).unwrap();
+    assert!(body.is_file());
+    assert_eq!(body.len(), 26);
+    body.seek(SeekFrom::End(-3)).unwrap();
+    let mut end = String::new();
+    body.read_to_string(&mut end).unwrap();
+    assert_eq!(end, "xyz");
+    body.seek(SeekFrom::Start(0)).unwrap();
+    let mut all = String::new();
+    body.read_to_string(&mut all).unwrap();
+    assert_eq!(all, "abcdefghijklmnopqrstuvwxyz");
+}
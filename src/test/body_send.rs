@@ -109,3 +109,263 @@ fn content_type_not_overriden_on_json() {
     let s = String::from_utf8_lossy(&vec);
     assert!(s.contains("\r\ncontent-type: text/plain\r\n"));
 }
+
+#[test]
+#[cfg(feature = "json")]
+fn send_json_serialize() {
+    use serde::Serialize;
+
+    #[derive(Serialize)]
+    struct Hello {
+        hello: &'static str,
+    }
+
+    test::set_handler("/send_json_serialize", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = post("test://host/send_json_serialize").send_json(&Hello { hello: "world" });
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nContent-Type: application/json\r\n"));
+    assert!(s.ends_with("\r\n\r\n{\"hello\":\"world\"}"));
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn send_json_serialize_error() {
+    use std::collections::HashMap;
+
+    // JSON object keys must be strings.
+    let mut map = HashMap::new();
+    map.insert((1, 2), "nope");
+    let resp = post("test://host/send_json_serialize_error").send_json(map);
+    assert_eq!(resp.status(), 500);
+    assert_eq!(resp.status_text(), "JSON Error");
+}
+
+#[test]
+fn multipart_with_content_length() {
+    test::set_handler("/multipart_with_content_length", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let form =
+        Multipart::new()
+            .text("a", "1")
+            .file_with_len("f", "f.txt", "text/plain", &b"hello"[..], 5);
+    let content_type = form.content_type();
+    let resp = post("test://host/multipart_with_content_length").send_multipart(form);
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains(&format!("\r\nContent-Type: {}\r\n", content_type)));
+    let (_, body) = s.split_at(s.find("\r\n\r\n").unwrap() + 4);
+    assert!(s.contains(&format!("\r\nContent-Length: {}\r\n", body.len())));
+    assert!(!s.contains("Transfer-Encoding"));
+    assert!(body.contains("filename=\"f.txt\"\r\nContent-Type: text/plain\r\n\r\nhello\r\n"));
+}
+
+#[test]
+fn multipart_file_too_short() {
+    test::set_handler("/multipart_file_too_short", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let form = Multipart::new().file_with_len("f", "f.txt", "text/plain", &b"hel"[..], 5);
+    let resp = post("test://host/multipart_file_too_short").send_multipart(form);
+    assert_eq!(resp.status(), 500);
+    match resp.synthetic_error() {
+        Some(Error::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof),
+        err => panic!("expected UnexpectedEof, got {:?}", err),
+    }
+}
+
+#[test]
+fn multipart_chunked_for_unknown_len() {
+    test::set_handler("/multipart_chunked_for_unknown_len", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let form = Multipart::new().file("f", "f.txt", "text/plain", &b"hello"[..]);
+    let closing = format!("--{}--\r\n", form.boundary());
+    let resp = post("test://host/multipart_chunked_for_unknown_len").send_multipart(form);
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nTransfer-Encoding: chunked\r\n"));
+    assert!(!s.contains("Content-Length"));
+    assert!(s.contains(&closing));
+    assert!(s.ends_with("0\r\n\r\n"));
+}
+
+#[test]
+fn send_form_encoded() {
+    test::set_handler("/send_form_encoded", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = post("test://host/send_form_encoded")
+        .send_form(&[("name", "martin rubber"), ("q", "a&b=c")]);
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nContent-Type: application/x-www-form-urlencoded\r\n"));
+    assert!(s.contains("\r\nContent-Length: 30\r\n"));
+    assert!(s.ends_with("\r\n\r\nname=martin+rubber&q=a%26b%3Dc"));
+}
+
+#[test]
+fn send_form_empty() {
+    test::set_handler("/send_form_empty", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = post("test://host/send_form_empty").send_form(&[]);
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nContent-Length: 0\r\n"));
+    assert!(s.ends_with("\r\n\r\n"));
+}
+
+#[test]
+fn content_length_on_empty_bytes() {
+    test::set_handler("/content_length_on_empty_bytes", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = put("test://host/content_length_on_empty_bytes").send_bytes(b"");
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nContent-Length: 0\r\n"));
+    assert!(!s.contains("Transfer-Encoding"));
+}
+
+#[test]
+fn content_length_on_empty_str() {
+    test::set_handler("/content_length_on_empty_str", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = put("test://host/content_length_on_empty_str").send_string("");
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nContent-Length: 0\r\n"));
+}
+
+#[test]
+fn sized_bodies_retryable() {
+    use crate::body::Payload;
+    let req = put("test://host/sized_bodies_retryable");
+    assert!(req.is_retryable(&Payload::Empty.into_read()));
+    assert!(req.is_retryable(&Payload::Bytes(vec![]).into_read()));
+    assert!(req.is_retryable(&Payload::Bytes(b"hello".to_vec()).into_read()));
+    let reader = Payload::Reader(Box::new(std::io::empty()), None).into_read();
+    assert!(!req.is_retryable(&reader));
+    let req = post("test://host/sized_bodies_retryable");
+    assert!(!req.is_retryable(&Payload::Bytes(b"hello".to_vec()).into_read()));
+}
+
+#[test]
+fn content_length_on_sized_reader() {
+    test::set_handler("/content_length_on_sized_reader", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let body = std::io::Cursor::new(b"Hello World!!!".to_vec());
+    let resp = put("test://host/content_length_on_sized_reader").send(body, Some(14));
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nContent-Length: 14\r\n"));
+    assert!(!s.contains("Transfer-Encoding"));
+    assert!(s.ends_with("\r\n\r\nHello World!!!"));
+}
+
+#[test]
+fn sized_reader_sends_only_len() {
+    test::set_handler("/sized_reader_sends_only_len", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let body = std::io::Cursor::new(b"Hello World!!!".to_vec());
+    let resp = put("test://host/sized_reader_sends_only_len").send(body, Some(5));
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nContent-Length: 5\r\n"));
+    assert!(s.ends_with("\r\n\r\nHello"));
+}
+
+#[test]
+fn sized_reader_too_short() {
+    test::set_handler("/sized_reader_too_short", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let body = std::io::Cursor::new(b"Hello".to_vec());
+    let resp = put("test://host/sized_reader_too_short").send(body, Some(14));
+    assert_eq!(resp.status(), 500);
+    match resp.synthetic_error() {
+        Some(Error::Io(err)) => assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof),
+        err => panic!("expected UnexpectedEof, got {:?}", err),
+    }
+}
+
+#[test]
+fn chunks_of_buffer_size() {
+    test::set_handler("/chunks_of_buffer_size", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let agent = agent().buffer_size(4).build();
+    let body = std::io::Cursor::new(b"hello world".to_vec());
+    let resp = agent
+        .post("test://host/chunks_of_buffer_size")
+        .set("Transfer-Encoding", "chunked")
+        .send(body, None);
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.ends_with("\r\n\r\n4\r\nhell\r\n4\r\no wo\r\n3\r\nrld\r\n0\r\n\r\n"));
+}
+
+#[test]
+#[cfg(feature = "gzip")]
+fn send_compressed_gzip() {
+    use chunked_transfer::Decoder;
+    use flate2::read::GzDecoder;
+    use std::io::{Cursor, Read};
+
+    test::set_handler("/send_compressed_gzip", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let text = "hello hello hello hello hello hello";
+    let resp = post("test://host/send_compressed_gzip").send_compressed(Cursor::new(text));
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nContent-Encoding: gzip\r\n"));
+    assert!(s.contains("\r\nTransfer-Encoding: chunked\r\n"));
+    assert!(!s.contains("Content-Length"));
+
+    let start = s.find("\r\n\r\n").unwrap() + 4;
+    let mut body = String::new();
+    GzDecoder::new(Decoder::new(&vec[start..]))
+        .read_to_string(&mut body)
+        .unwrap();
+    assert_eq!(body, text);
+}
+
+#[test]
+fn send_with_closure_chunks() {
+    test::set_handler("/send_with_closure_chunks", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let mut parts = vec!["hello", "", " world"].into_iter();
+    let resp = post("test://host/send_with_closure_chunks").send_with(move |buf| {
+        if let Some(part) = parts.next() {
+            buf.extend_from_slice(part.as_bytes());
+        }
+        Ok(parts.len() > 0)
+    });
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nTransfer-Encoding: chunked\r\n"));
+    assert!(s.ends_with("\r\n\r\nb\r\nhello world\r\n0\r\n\r\n"));
+}
+
+#[test]
+fn send_with_closure_error() {
+    test::set_handler("/send_with_closure_error", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = post("test://host/send_with_closure_error").send_with(|_buf| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "no more rows",
+        ))
+    });
+    assert!(matches!(resp.synthetic_error(), Some(Error::Io(_))));
+}
@@ -0,0 +1,86 @@
+use crate::test::testserver::*;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::super::*;
+
+// Send the headers and the start of the body, then stall for two seconds.
+fn stalled_body_respond(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello")?;
+    stream.flush()?;
+    thread::sleep(Duration::from_secs(2));
+    stream.write_all(b"world")?;
+    Ok(())
+}
+
+fn cancel_after(handle: &CancelHandle, wait: Duration) {
+    let handle = handle.clone();
+    thread::spawn(move || {
+        thread::sleep(wait);
+        handle.cancel();
+    });
+}
+
+#[test]
+fn cancel_during_body() {
+    let server = TestServer::new(stalled_body_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let handle = CancelHandle::new();
+    let resp = get(&url).set_cancel_handle(handle.clone()).call();
+    assert_eq!(resp.status(), 200);
+    let started = Instant::now();
+    cancel_after(&handle, Duration::from_millis(100));
+    let err = resp
+        .into_string()
+        .expect_err("expected the read to be cancelled");
+    assert!(started.elapsed() < Duration::from_secs(1));
+    let inner = err.get_ref().and_then(|e| e.downcast_ref::<Error>());
+    assert!(matches!(inner, Some(Error::Cancelled)));
+}
+
+#[test]
+fn cancel_during_headers_with_read_timeout() {
+    // the server never answers, the read timeout is far off.
+    let server = TestServer::new(|stream| {
+        read_headers(&stream);
+        thread::sleep(Duration::from_secs(2));
+        Ok(())
+    });
+    let url = format!("http://localhost:{}/", server.port);
+    let handle = CancelHandle::new();
+    cancel_after(&handle, Duration::from_millis(100));
+    let started = Instant::now();
+    let resp = get(&url)
+        .timeout_read(5000)
+        .set_cancel_handle(handle)
+        .call();
+    assert!(matches!(resp.synthetic_error(), Some(Error::Cancelled)));
+    assert!(started.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn cancelled_before_call() {
+    let handle = CancelHandle::new();
+    handle.cancel();
+    let resp = get("http://localhost:1/").set_cancel_handle(handle).call();
+    assert!(matches!(resp.synthetic_error(), Some(Error::Cancelled)));
+}
+
+#[test]
+fn read_timeout_with_cancel_handle() {
+    // the handle cuts the waits on the socket short, they still add up to the timeout.
+    let server = TestServer::new(stalled_body_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url)
+        .timeout_read(300)
+        .set_cancel_handle(CancelHandle::new())
+        .call();
+    let started = Instant::now();
+    let err = resp.into_string().expect_err("expected a read timeout");
+    assert!(started.elapsed() >= Duration::from_millis(300));
+    let inner = err.get_ref().and_then(|e| e.downcast_ref::<Error>());
+    assert!(matches!(inner, Some(Error::ReadTimeout)));
+}
@@ -0,0 +1,266 @@
+use crate::test;
+use cookie::SameSite;
+
+use super::super::*;
+
+// Request the url, and check the cookies sent with it. Handlers are used once,
+// so this sets a new one each time.
+fn expect_cookies(agent: &Agent, url: &str, expect: Vec<&'static str>) {
+    let path = Url::parse(url).unwrap().path().to_string();
+    test::set_handler(&path, move |unit| {
+        // they all come in one header.
+        assert!(unit.all("cookie").len() <= 1);
+        let mut cookies: Vec<_> = unit
+            .header("cookie")
+            .map(|c| c.split("; ").collect())
+            .unwrap_or_else(Vec::new);
+        cookies.sort();
+        assert_eq!(cookies, expect);
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    assert_eq!(agent.get(url).call().status(), 200);
+}
+
+#[test]
+fn secure_cookie_not_sent_over_http() {
+    let agent = agent();
+    agent.set_cookie(Cookie::build("plain", "1").domain("host").finish());
+    agent.set_cookie(
+        Cookie::build("secret", "2")
+            .domain("host")
+            .secure(true)
+            .finish(),
+    );
+    expect_cookies(
+        &agent,
+        "test://host/secure_cookie_not_sent_over_http",
+        vec!["plain=1"],
+    );
+}
+
+#[test]
+fn cookie_domain_and_path_match() {
+    let agent = agent();
+    agent.set_cookie(
+        Cookie::build("app", "1")
+            .domain("example.com")
+            .path("/cookie_domain_and_path_match")
+            .finish(),
+    );
+    expect_cookies(
+        &agent,
+        "test://sub.example.com/cookie_domain_and_path_match/sub",
+        vec!["app=1"],
+    );
+    // only a suffix, not a subdomain.
+    expect_cookies(
+        &agent,
+        "test://notexample.com/cookie_domain_and_path_match/sub",
+        vec![],
+    );
+    // the path only shares a prefix.
+    expect_cookies(
+        &agent,
+        "test://example.com/cookie_domain_and_path_match_not",
+        vec![],
+    );
+}
+
+#[test]
+fn host_only_cookie_not_sent_to_subdomain() {
+    let agent = agent();
+    test::set_handler("/host_only_cookie_not_sent_to_subdomain", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec![
+                "Set-Cookie: host=1; Path=/",
+                "Set-Cookie: domain=1; Path=/; Domain=example.com",
+            ],
+            vec![],
+        )
+    });
+    agent
+        .get("test://example.com/host_only_cookie_not_sent_to_subdomain")
+        .call();
+    // the same after saving and loading the cookies.
+    let mut saved = vec![];
+    agent.cookie_store_save(&mut saved).unwrap();
+    let loaded = crate::agent();
+    loaded.cookie_store_load(&saved[..]).unwrap();
+    for agent in &[agent, loaded] {
+        let sub = Url::parse("test://sub.example.com/").unwrap();
+        assert!(agent.cookie_for("host", &sub).is_none());
+        assert!(agent.cookie_for("domain", &sub).is_some());
+        let host = Url::parse("test://example.com/").unwrap();
+        assert!(agent.cookie_for("host", &host).is_some());
+    }
+}
+
+#[test]
+fn set_cookie_for_other_domain_ignored() {
+    let agent = agent();
+    test::set_handler("/set_cookie_for_other_domain_ignored", |_unit| {
+        test::make_response(
+            200,
+            "OK",
+            vec![
+                "Set-Cookie: theirs=1; Domain=other.com",
+                "Set-Cookie: ours=1; Domain=example.com",
+            ],
+            vec![],
+        )
+    });
+    agent
+        .get("test://www.example.com/set_cookie_for_other_domain_ignored")
+        .call();
+    let other = Url::parse("test://other.com/").unwrap();
+    assert!(agent.cookie_for("theirs", &other).is_none());
+    let ours = Url::parse("test://www.example.com/").unwrap();
+    assert!(agent.cookie_for("ours", &ours).is_some());
+}
+
+#[test]
+fn cookie_default_path() {
+    let agent = agent();
+    test::set_handler("/cookie_default_path/login", |_unit| {
+        test::make_response(200, "OK", vec!["Set-Cookie: id=1"], vec![])
+    });
+    agent.get("test://host/cookie_default_path/login").call();
+    let url = Url::parse("test://host/cookie_default_path/login").unwrap();
+    let cookie = agent.cookie_for("id", &url).unwrap();
+    assert_eq!(cookie.path(), Some("/cookie_default_path"));
+    let other_path = Url::parse("test://host/elsewhere").unwrap();
+    assert!(agent.cookie_for("id", &other_path).is_none());
+}
+
+#[test]
+fn strict_cookie_dropped_on_cross_site_redirect() {
+    let agent = agent();
+    for (name, same_site) in &[("strict", SameSite::Strict), ("lax", SameSite::Lax)] {
+        agent.set_cookie(
+            Cookie::build(*name, "1")
+                .domain("example.com")
+                .same_site(*same_site)
+                .finish(),
+        );
+    }
+
+    let to = "test://example.com/strict_cookie_redirect_to";
+    test::set_handler("/strict_cookie_redirect_from", move |_unit| {
+        test::make_response(302, "Found", vec![&format!("Location: {}", to)], vec![])
+    });
+    test::set_handler("/strict_cookie_redirect_to", |unit| {
+        assert_eq!(unit.header("cookie"), Some("lax=1"));
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = agent
+        .get("test://tracker.test/strict_cookie_redirect_from")
+        .call();
+    assert_eq!(resp.status(), 200);
+
+    // sent when the request starts on the site itself.
+    expect_cookies(&agent, to, vec!["lax=1", "strict=1"]);
+}
+
+#[test]
+fn cookies_for_url() {
+    let agent = agent();
+    agent.set_cookie(Cookie::build("a", "1").domain("example.com").finish());
+    agent.set_cookie(Cookie::build("b", "2").domain("other.com").finish());
+    let url = Url::parse("https://example.com/").unwrap();
+    let names: Vec<_> = agent
+        .cookies_for(&url)
+        .iter()
+        .map(|c| c.name().to_string())
+        .collect();
+    assert_eq!(names, vec!["a"]);
+}
+
+#[test]
+fn set_and_remove_cookie() {
+    let agent = agent();
+    agent.set_cookie(
+        Cookie::build("token", "from-sso")
+            .domain("host")
+            .path("/")
+            .finish(),
+    );
+    expect_cookies(
+        &agent,
+        "test://host/set_and_remove_cookie",
+        vec!["token=from-sso"],
+    );
+
+    agent.remove_cookie("token");
+    expect_cookies(&agent, "test://host/set_and_remove_cookie", vec![]);
+}
+
+#[test]
+fn set_cookie_without_domain_ignored() {
+    let agent = agent();
+    agent.set_cookie(Cookie::new("nowhere", "1"));
+    assert!(agent.cookie("nowhere").is_none());
+}
+
+#[test]
+fn cookies_sent_in_one_header_longest_path_first() {
+    let agent = agent();
+    for (name, path) in &[
+        ("root", "/"),
+        ("deep", "/cookies_one_header/a/b"),
+        ("mid", "/cookies_one_header/a"),
+    ] {
+        agent.set_cookie(
+            Cookie::build(*name, "1")
+                .domain("host")
+                .path(*path)
+                .finish(),
+        );
+    }
+    test::set_handler("/cookies_one_header/a/b/c", |unit| {
+        assert_eq!(unit.all("cookie"), vec!["deep=1; mid=1; root=1"]);
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = agent.get("test://host/cookies_one_header/a/b/c").call();
+    assert_eq!(resp.status(), 200);
+}
+
+#[test]
+fn user_cookie_header_merged_with_jar() {
+    let agent = agent();
+    agent.set_cookie(Cookie::build("jar", "1").domain("host").finish());
+    test::set_handler("/user_cookie_header_merged_with_jar", |unit| {
+        assert_eq!(unit.all("cookie"), vec!["mine=2; jar=1"]);
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = agent
+        .get("test://host/user_cookie_header_merged_with_jar")
+        .set("Cookie", "mine=2")
+        .call();
+    assert_eq!(resp.status(), 200);
+}
+
+#[test]
+fn no_cookies_leaves_jar_out() {
+    let agent = agent();
+    agent.set_cookie(Cookie::build("session", "s3cr3t").domain("host").finish());
+    test::set_handler("/no_cookies_leaves_jar_out", |unit| {
+        assert_eq!(unit.header("cookie"), Some("mine=1"));
+        test::make_response(200, "OK", vec!["Set-Cookie: tracker=1"], vec![])
+    });
+    let resp = agent
+        .get("test://host/no_cookies_leaves_jar_out")
+        .set("Cookie", "mine=1")
+        .no_cookies()
+        .call();
+    assert_eq!(resp.status(), 200);
+    // the jar is as it was, and used by other requests.
+    let url = Url::parse("test://host/").unwrap();
+    assert!(agent.cookie_for("tracker", &url).is_none());
+    expect_cookies(
+        &agent,
+        "test://host/no_cookies_other_request",
+        vec!["session=s3cr3t"],
+    );
+}
@@ -0,0 +1,118 @@
+use crate::test::testserver::TestServer;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use super::super::*;
+
+// Read the request headers one byte at a time, so none of the body is consumed.
+fn read_request_head(stream: &mut TcpStream) -> io::Result<String> {
+    let mut head = vec![];
+    let mut byte = [0];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte)?;
+        head.push(byte[0]);
+    }
+    Ok(String::from_utf8_lossy(&head).into_owned())
+}
+
+// Answer the response with the length of the body if it was sent.
+fn respond_with_body_len(stream: &mut TcpStream) -> io::Result<()> {
+    let mut body = vec![0; 10];
+    stream.read_exact(&mut body)?;
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\n10")
+}
+
+fn continue_handler(mut stream: TcpStream) -> io::Result<()> {
+    let head = read_request_head(&mut stream)?;
+    assert!(head.contains("\r\nExpect: 100-continue\r\n"));
+    stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    respond_with_body_len(&mut stream)
+}
+
+#[test]
+fn expect_continue_sends_body_after_100() {
+    let server = TestServer::new(continue_handler);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = put(&url).expect_continue(5_000).send_bytes(b"0123456789");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "10");
+}
+
+static BODY_BYTES_SEEN: AtomicUsize = AtomicUsize::new(0);
+
+fn reject_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_request_head(&mut stream)?;
+    stream.write_all(b"HTTP/1.1 413 Payload Too Large\r\nContent-Length: 0\r\n\r\n")?;
+    // anything the client sends now is the body we asked it not to send.
+    stream.set_read_timeout(Some(Duration::from_millis(500)))?;
+    let mut buf = [0; 100];
+    if let Ok(n) = stream.read(&mut buf) {
+        BODY_BYTES_SEEN.fetch_add(n, Ordering::SeqCst);
+    }
+    Ok(())
+}
+
+#[test]
+fn expect_continue_final_status_skips_body() {
+    let server = TestServer::new(reject_handler);
+    let url = format!("http://localhost:{}/", server.port);
+    let agent = agent();
+    let resp = agent
+        .put(&url)
+        .set("Expect", "100-continue")
+        .send_bytes(b"0123456789");
+    assert_eq!(resp.status(), 413);
+    assert_eq!(resp.into_string().unwrap(), "");
+    std::thread::sleep(Duration::from_millis(700));
+    assert_eq!(BODY_BYTES_SEEN.load(Ordering::SeqCst), 0);
+    // the connection is closed rather than pooled.
+    let state = agent.state.lock().unwrap();
+    assert_eq!(state.as_ref().unwrap().pool.len(), 0);
+}
+
+fn silent_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_request_head(&mut stream)?;
+    respond_with_body_len(&mut stream)
+}
+
+#[test]
+fn expect_continue_times_out_and_sends_body() {
+    let server = TestServer::new(silent_handler);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = put(&url).expect_continue(100).send_bytes(b"0123456789");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "10");
+}
+
+fn late_continue_handler(mut stream: TcpStream) -> io::Result<()> {
+    read_request_head(&mut stream)?;
+    let mut body = vec![0; 10];
+    stream.read_exact(&mut body)?;
+    stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n")?;
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+}
+
+#[test]
+fn expect_continue_late_100_skipped() {
+    let server = TestServer::new(late_continue_handler);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = put(&url).expect_continue(100).send_bytes(b"0123456789");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "ok");
+}
+
+fn no_expect_handler(mut stream: TcpStream) -> io::Result<()> {
+    let head = read_request_head(&mut stream)?;
+    assert!(!head.contains("Expect"));
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+}
+
+#[test]
+fn expect_continue_not_sent_without_body() {
+    let server = TestServer::new(no_expect_handler);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url).expect_continue(5_000).call();
+    assert_eq!(resp.status(), 200);
+}
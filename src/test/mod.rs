@@ -10,12 +10,26 @@ mod agent_test;
 mod auth;
 mod body_read;
 mod body_send;
+mod cancel;
+#[cfg(feature = "cookie")]
+mod cookies;
+mod expect;
+mod proxy;
 mod query_string;
 mod range;
 mod redirect;
+mod retry;
 mod simple;
+#[cfg(feature = "socks-proxy")]
+mod socks;
 mod testserver;
+mod throttle;
 mod timeout;
+#[cfg(all(feature = "tls", not(feature = "native-tls")))]
+mod tls;
+#[cfg(unix)]
+mod unix;
+mod upgrade;
 
 type RequestHandler = dyn Fn(&Unit) -> Result<Stream, Error> + Send + 'static;
 
@@ -50,3 +50,32 @@ fn query_in_path_and_req() {
     let s = String::from_utf8_lossy(&vec);
     assert!(s.contains("GET /query_in_path_and_req?foo=bar&baz=1%202%203 HTTP/1.1"))
 }
+
+#[test]
+fn query_special_chars() {
+    test::set_handler("/query_special_chars", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/query_special_chars")
+        .query("q", "a&b=c")
+        .query("sum", "1+1 100%")
+        .query("frag", "#x")
+        .call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("GET /query_special_chars?q=a%26b%3Dc&sum=1%2B1%20100%25&frag=%23x HTTP/1.1"))
+}
+
+#[test]
+fn query_repeated_and_pairs() {
+    test::set_handler("/query_repeated_and_pairs", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/query_repeated_and_pairs?")
+        .query("a", "1")
+        .query_pairs(vec![("a", "2"), ("b", "3")])
+        .call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("GET /query_repeated_and_pairs?a=1&a=2&b=3 HTTP/1.1"))
+}
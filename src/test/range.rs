@@ -1,3 +1,5 @@
+use crate::test;
+
 #[cfg(any(feature = "tls", feature = "native-tls"))]
 use std::io::Read;
 
@@ -20,3 +22,47 @@ fn read_range() {
         [83, 99, 111, 116, 116, 34, 10, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32, 32]
     )
 }
+
+#[test]
+fn set_range_and_content_range() {
+    test::set_handler("/set_range_and_content_range", |unit| {
+        assert_eq!(unit.header("Range"), Some("bytes=2-4"));
+        test::make_response(
+            206,
+            "Partial Content",
+            vec!["Content-Range: bytes 2-4/12", "Content-Length: 3"],
+            b"llo".to_vec(),
+        )
+    });
+    let resp = crate::get("test://host/set_range_and_content_range")
+        .set_range(2, Some(4))
+        .call();
+    assert!(resp.ok());
+    assert_eq!(resp.content_range(), Some((2, 4, Some(12))));
+    assert_eq!(resp.into_string().unwrap(), "llo");
+}
+
+#[test]
+fn set_range_open_ended() {
+    let req = crate::get("test://host/").set_range(1000, None).build();
+    assert_eq!(req.header("Range"), Some("bytes=1000-"));
+}
+
+#[test]
+fn content_range_parsing() {
+    let range = |value: &str| {
+        format!(
+            "HTTP/1.1 206 Partial Content\r\nContent-Range: {}\r\n\r\n",
+            value
+        )
+        .parse::<crate::Response>()
+        .unwrap()
+        .content_range()
+    };
+    assert_eq!(range("bytes 0-499/1234"), Some((0, 499, Some(1234))));
+    assert_eq!(range("bytes 500-999/*"), Some((500, 999, None)));
+    assert_eq!(range("bytes */1234"), None);
+    assert_eq!(range("bytes 10-5/20"), None);
+    assert_eq!(range("bytes 0-20/20"), None);
+    assert_eq!(range("items 0-1/2"), None);
+}
@@ -1,4 +1,7 @@
 use crate::test;
+use crate::test::testserver::TestServer;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpStream;
 
 use super::super::*;
 
@@ -16,6 +19,39 @@ fn redirect_on() {
     assert_eq!(resp.header("x-foo").unwrap(), "bar");
 }
 
+#[test]
+fn redirect_history() {
+    test::set_handler("/redirect_history1", |_| {
+        test::make_response(302, "Go here", vec!["Location: /redirect_history2"], vec![])
+    });
+    test::set_handler("/redirect_history2", |_| {
+        test::make_response(301, "Go here", vec!["Location: /redirect_history3"], vec![])
+    });
+    test::set_handler("/redirect_history3", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/redirect_history1").call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.get_url(), "test://host/redirect_history3");
+    assert_eq!(
+        resp.history(),
+        &[
+            (302, "test://host/redirect_history2".to_string()),
+            (301, "test://host/redirect_history3".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn no_redirect_history() {
+    test::set_handler("/no_redirect_history", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/no_redirect_history").call();
+    assert_eq!(resp.get_url(), "test://host/no_redirect_history");
+    assert!(resp.history().is_empty());
+}
+
 #[test]
 fn redirect_many() {
     test::set_handler("/redirect_many1", |_| {
@@ -29,6 +65,94 @@ fn redirect_many() {
     assert_eq!(resp.status_text(), "Too Many Redirects");
 }
 
+#[test]
+fn redirect_limit_then_not_modified() {
+    test::set_handler("/redirect_limit_304_1", |_| {
+        test::make_response(
+            302,
+            "Go here",
+            vec!["Location: /redirect_limit_304_2"],
+            vec![],
+        )
+    });
+    test::set_handler("/redirect_limit_304_2", |_| {
+        test::make_response(304, "Not Modified", vec![], vec![])
+    });
+    let resp = get("test://host/redirect_limit_304_1").redirects(1).call();
+    assert_eq!(resp.status(), 304);
+    assert!(resp.synthetic_error().is_none());
+    assert_eq!(resp.get_url(), "test://host/redirect_limit_304_2");
+}
+
+#[test]
+fn redirect_loop() {
+    test::set_handler("/redirect_loop1", |_| {
+        test::make_response(302, "Go here", vec!["Location: /redirect_loop2"], vec![])
+    });
+    test::set_handler("/redirect_loop2", |_| {
+        test::make_response(302, "Go here", vec!["Location: /redirect_loop1"], vec![])
+    });
+    let resp = get("test://host/redirect_loop1").redirects(10).call();
+    assert_eq!(resp.status(), 500);
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::RedirectLoop(url)) if url == "test://host/redirect_loop1"
+    ));
+}
+
+// Answer a POST with a 303 to the same url, and a GET with 200.
+fn post_redirect_get_respond(mut stream: TcpStream) -> io::Result<()> {
+    let mut lines = BufReader::new(&stream).lines();
+    let is_post = lines.next().unwrap()?.starts_with("POST ");
+    for line in lines {
+        if line? == "" {
+            break;
+        }
+    }
+    if is_post {
+        stream.write_all(
+            b"HTTP/1.1 303 See Other\r\nLocation: /\r\nConnection: close\r\nContent-Length: 0\r\n\r\n",
+        )
+    } else {
+        stream.write_all(b"HTTP/1.1 200 OK\r\nConnection: close\r\nContent-Length: 2\r\n\r\nok")
+    }
+}
+
+#[test]
+fn redirect_post_to_same_url() {
+    let server = TestServer::new(post_redirect_get_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = post(&url).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "ok");
+}
+
+#[test]
+fn redirect_at_limit() {
+    test::set_handler("/redirect_at_limit1", |_| {
+        test::make_response(
+            302,
+            "Go here",
+            vec!["Location: /redirect_at_limit2"],
+            vec![],
+        )
+    });
+    test::set_handler("/redirect_at_limit2", |_| {
+        test::make_response(
+            302,
+            "Go here",
+            vec!["Location: /redirect_at_limit3"],
+            vec![],
+        )
+    });
+    test::set_handler("/redirect_at_limit3", |_| {
+        test::make_response(200, "OK", vec!["x-foo: bar"], vec![])
+    });
+    let resp = get("test://host/redirect_at_limit1").redirects(2).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.get_url(), "test://host/redirect_at_limit3");
+}
+
 #[test]
 fn redirect_off() {
     test::set_handler("/redirect_off", |_| {
@@ -76,6 +200,41 @@ fn redirect_get() {
     assert_eq!(resp.header("x-foo").unwrap(), "bar");
 }
 
+#[test]
+fn redirect_lowercase_methods() {
+    test::set_handler("/redirect_lowercase1", |_| {
+        test::make_response(
+            302,
+            "Go here",
+            vec!["Location: /redirect_lowercase2"],
+            vec![],
+        )
+    });
+    test::set_handler("/redirect_lowercase2", |unit| {
+        assert_eq!(unit.method, "GET");
+        assert!(!unit.is_chunked);
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = request("post", "test://host/redirect_lowercase1").send_string("data");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.get_url(), "test://host/redirect_lowercase2");
+
+    test::set_handler("/redirect_lowercase_head1", |_| {
+        test::make_response(
+            301,
+            "Go here",
+            vec!["Location: /redirect_lowercase_head2"],
+            vec![],
+        )
+    });
+    test::set_handler("/redirect_lowercase_head2", |unit| {
+        assert_eq!(unit.method, "HEAD");
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = request("head", "test://host/redirect_lowercase_head1").call();
+    assert_eq!(resp.status(), 200);
+}
+
 #[test]
 fn redirect_post() {
     test::set_handler("/redirect_post1", |_| {
@@ -91,3 +250,285 @@ fn redirect_post() {
     assert!(resp.has("x-foo"));
     assert_eq!(resp.header("x-foo").unwrap(), "bar");
 }
+
+#[test]
+fn redirect_307_keeps_body() {
+    test::set_handler("/redirect_307_1", |_| {
+        test::make_response(307, "Go here", vec!["Location: /redirect_307_2"], vec![])
+    });
+    test::set_handler("/redirect_307_2", |unit| {
+        assert_eq!(unit.method, "POST");
+        assert_eq!(unit.header("Content-Length").unwrap(), "5");
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = post("test://host/redirect_307_1").send_string("hello");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.get_url(), "test://host/redirect_307_2");
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.starts_with("POST /redirect_307_2 HTTP/1.1\r\n"));
+    assert!(s.ends_with("\r\n\r\nhello"));
+}
+
+#[test]
+fn redirect_308_keeps_method() {
+    test::set_handler("/redirect_308_1", |_| {
+        test::make_response(308, "Go here", vec!["Location: /redirect_308_2"], vec![])
+    });
+    test::set_handler("/redirect_308_2", |unit| {
+        assert_eq!(unit.method, "PUT");
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = put("test://host/redirect_308_1").send_bytes(b"hello");
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.get_url(), "test://host/redirect_308_2");
+}
+
+#[test]
+fn redirect_303_gets_result() {
+    test::set_handler("/redirect_303_1", |_| {
+        test::make_response(303, "See Other", vec!["Location: /redirect_303_2"], vec![])
+    });
+    test::set_handler("/redirect_303_2", |unit| {
+        assert_eq!(unit.method, "GET");
+        assert!(!unit.has("content-length"));
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = put("test://host/redirect_303_1").send_string("hello");
+    assert_eq!(resp.status(), 200);
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.starts_with("GET /redirect_303_2 HTTP/1.1\r\n"));
+    assert!(s.ends_with("\r\n\r\n"));
+}
+
+#[test]
+fn redirect_302_keeps_put() {
+    test::set_handler("/redirect_302_put1", |_| {
+        test::make_response(302, "Found", vec!["Location: /redirect_302_put2"], vec![])
+    });
+    test::set_handler("/redirect_302_put2", |unit| {
+        assert_eq!(unit.method, "PUT");
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = put("test://host/redirect_302_put1").send_string("hello");
+    assert_eq!(resp.status(), 200);
+    let vec = resp.to_write_vec();
+    assert!(String::from_utf8_lossy(&vec).ends_with("\r\n\r\nhello"));
+}
+
+#[test]
+fn redirect_307_reader_body() {
+    test::set_handler("/redirect_307_reader", |_| {
+        test::make_response(307, "Go here", vec!["Location: /elsewhere"], vec![])
+    });
+    let body = std::io::Cursor::new(b"hello".to_vec());
+    let resp = post("test://host/redirect_307_reader").send(body, None);
+    assert_eq!(resp.status(), 500);
+    assert_eq!(resp.status_text(), "Body Not Replayable");
+}
+
+#[test]
+fn redirect_same_origin_keeps_credentials() {
+    test::set_handler("/redirect_same_origin1", |_| {
+        test::make_response(
+            302,
+            "Go here",
+            vec!["Location: /redirect_same_origin2"],
+            vec![],
+        )
+    });
+    test::set_handler("/redirect_same_origin2", |unit| {
+        assert_eq!(unit.header("Authorization").unwrap(), "Bearer secret");
+        assert_eq!(unit.header("Cookie").unwrap(), "a=b");
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/redirect_same_origin1")
+        .set("Authorization", "Bearer secret")
+        .set("Cookie", "a=b")
+        .call();
+    assert_eq!(resp.status(), 200);
+}
+
+#[test]
+fn redirect_cross_origin_strips_credentials() {
+    test::set_handler("/redirect_cross_origin1", |_| {
+        test::make_response(
+            302,
+            "Go here",
+            vec!["Location: test://other/redirect_cross_origin2"],
+            vec![],
+        )
+    });
+    test::set_handler("/redirect_cross_origin2", |unit| {
+        assert!(!unit.has("Authorization"));
+        assert!(!unit.has("Proxy-Authorization"));
+        assert!(!unit.has("Cookie"));
+        assert_eq!(unit.header("X-Other").unwrap(), "kept");
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/redirect_cross_origin1")
+        .set("Authorization", "Bearer secret")
+        .set("Proxy-Authorization", "Basic c2VjcmV0")
+        .set("Cookie", "a=b")
+        .set("X-Other", "kept")
+        .call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.get_url(), "test://other/redirect_cross_origin2");
+}
+
+#[test]
+fn redirect_back_to_origin_keeps_credentials_stripped() {
+    test::set_handler("/redirect_back1", |_| {
+        test::make_response(
+            302,
+            "Go here",
+            vec!["Location: test://other/redirect_back2"],
+            vec![],
+        )
+    });
+    test::set_handler("/redirect_back2", |unit| {
+        assert!(!unit.has("Authorization"));
+        test::make_response(
+            302,
+            "Go back",
+            vec!["Location: test://host/redirect_back3"],
+            vec![],
+        )
+    });
+    test::set_handler("/redirect_back3", |unit| {
+        assert!(!unit.has("Authorization"));
+        assert!(!unit.has("Proxy-Authorization"));
+        assert!(!unit.has("Cookie"));
+        assert_eq!(unit.header("X-Other").unwrap(), "kept");
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/redirect_back1")
+        .set("Authorization", "Bearer secret")
+        .set("Proxy-Authorization", "Basic c2VjcmV0")
+        .set("Cookie", "a=b")
+        .set("X-Other", "kept")
+        .call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.get_url(), "test://host/redirect_back3");
+}
+
+#[test]
+fn redirect_fn_rewrite() {
+    test::set_handler("/redirect_fn_rewrite1", |_| {
+        test::make_response(
+            302,
+            "Go here",
+            vec!["Location: /redirect_fn_nowhere"],
+            vec![],
+        )
+    });
+    test::set_handler("/redirect_fn_rewrite2", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/redirect_fn_rewrite1")
+        .redirect_fn(|from, status, to| {
+            assert_eq!(from.path(), "/redirect_fn_rewrite1");
+            assert_eq!(status, 302);
+            assert_eq!(to.as_str(), "test://host/redirect_fn_nowhere");
+            RedirectAction::Follow(from.join("/redirect_fn_rewrite2").unwrap())
+        })
+        .call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.get_url(), "test://host/redirect_fn_rewrite2");
+}
+
+#[test]
+fn redirect_fn_stop() {
+    test::set_handler("/redirect_fn_stop", |_| {
+        test::make_response(301, "Go here", vec!["Location: /elsewhere"], vec![])
+    });
+    let resp = get("test://host/redirect_fn_stop")
+        .redirect_fn(|_, _, _| RedirectAction::Stop)
+        .call();
+    assert_eq!(resp.status(), 301);
+    assert_eq!(resp.header("Location").unwrap(), "/elsewhere");
+}
+
+#[test]
+fn redirect_fn_stop_after_limit() {
+    test::set_handler("/redirect_fn_stop_limit1", |_| {
+        test::make_response(
+            302,
+            "Go here",
+            vec!["Location: /redirect_fn_stop_limit2"],
+            vec![],
+        )
+    });
+    test::set_handler("/redirect_fn_stop_limit2", |_| {
+        test::make_response(302, "Go here", vec!["Location: /elsewhere"], vec![])
+    });
+    let resp = get("test://host/redirect_fn_stop_limit1")
+        .redirects(1)
+        .redirect_fn(|from, _, to| {
+            if from.path() == "/redirect_fn_stop_limit1" {
+                RedirectAction::Follow(to.clone())
+            } else {
+                RedirectAction::Stop
+            }
+        })
+        .call();
+    assert_eq!(resp.status(), 302);
+    assert_eq!(resp.header("Location").unwrap(), "/elsewhere");
+    assert_eq!(resp.get_url(), "test://host/redirect_fn_stop_limit2");
+}
+
+#[test]
+fn redirect_fn_error() {
+    test::set_handler("/redirect_fn_error", |_| {
+        test::make_response(302, "Go here", vec!["Location: test://10.0.0.1/"], vec![])
+    });
+    let resp = get("test://host/redirect_fn_error")
+        .redirect_fn(|_, _, _| RedirectAction::Error)
+        .call();
+    assert_eq!(resp.status(), 500);
+    assert_eq!(resp.status_text(), "Redirect Denied");
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::RedirectDenied(_))
+    ));
+}
+
+#[test]
+fn redirect_responses_kept() {
+    test::set_handler("/redirect_kept1", |_| {
+        test::make_response(
+            302,
+            "Found",
+            vec!["Location: /redirect_kept2", "X-Why: moved"],
+            b"moved to redirect_kept2".to_vec(),
+        )
+    });
+    test::set_handler("/redirect_kept2", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/redirect_kept1")
+        .keep_redirect_responses(8)
+        .call();
+    assert_eq!(resp.status(), 200);
+    let redirects = resp.redirect_responses();
+    assert_eq!(redirects.len(), 1);
+    assert_eq!(redirects[0].status, 302);
+    assert_eq!(redirects[0].url, "test://host/redirect_kept1");
+    let why = header::get_header(&redirects[0].headers, "x-why");
+    assert_eq!(why, Some("moved"));
+    assert_eq!(redirects[0].body, b"moved to");
+}
+
+#[test]
+fn redirect_responses_not_kept() {
+    test::set_handler("/redirect_not_kept1", |_| {
+        test::make_response(302, "Found", vec!["Location: /redirect_not_kept2"], vec![])
+    });
+    test::set_handler("/redirect_not_kept2", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/redirect_not_kept1").call();
+    assert_eq!(resp.history().len(), 1);
+    assert!(resp.redirect_responses().is_empty());
+}
@@ -0,0 +1,180 @@
+use crate::test::testserver::*;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use super::super::*;
+
+static FLAKY_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+// Answer the first two requests with 503, and the rest with 200.
+fn flaky_respond(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    if FLAKY_CALLS.fetch_add(1, Ordering::SeqCst) < 2 {
+        stream.write_all(b"HTTP/1.1 503 Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n")
+    } else {
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+    }
+}
+
+#[test]
+fn retry_until_ok() {
+    let server = TestServer::new(flaky_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url).retries(2).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "ok");
+    assert_eq!(FLAKY_CALLS.load(Ordering::SeqCst), 3);
+}
+
+static BAD_GATEWAY_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn bad_gateway_respond(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    BAD_GATEWAY_CALLS.fetch_add(1, Ordering::SeqCst);
+    stream.write_all(b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\n\r\n")
+}
+
+#[test]
+fn retries_exhausted() {
+    let server = TestServer::new(bad_gateway_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url).retries(2).call();
+    assert_eq!(resp.status(), 502);
+    assert_eq!(BAD_GATEWAY_CALLS.load(Ordering::SeqCst), 3);
+}
+
+static POST_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn post_respond(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    POST_CALLS.fetch_add(1, Ordering::SeqCst);
+    stream.write_all(b"HTTP/1.1 504 Gateway Timeout\r\nContent-Length: 0\r\n\r\n")
+}
+
+#[test]
+fn no_retry_for_post() {
+    let server = TestServer::new(post_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = post(&url).retries(2).call();
+    assert_eq!(resp.status(), 504);
+    assert_eq!(POST_CALLS.load(Ordering::SeqCst), 1);
+}
+
+static DEADLINE_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn long_retry_after_respond(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    DEADLINE_CALLS.fetch_add(1, Ordering::SeqCst);
+    stream.write_all(b"HTTP/1.1 503 Unavailable\r\nRetry-After: 60\r\nContent-Length: 0\r\n\r\n")
+}
+
+#[test]
+fn no_retry_past_deadline() {
+    let server = TestServer::new(long_retry_after_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url)
+        .retries(2)
+        .timeout(std::time::Duration::from_secs(5))
+        .call();
+    assert_eq!(resp.status(), 503);
+    assert_eq!(DEADLINE_CALLS.load(Ordering::SeqCst), 1);
+}
+
+static IDEMPOTENT_POST_KEYS: std::sync::Mutex<Vec<String>> = std::sync::Mutex::new(Vec::new());
+
+// Note the Idempotency-Key of each request, answering the first with 503.
+fn idempotent_post_respond(mut stream: TcpStream) -> io::Result<()> {
+    use std::io::{BufRead, BufReader};
+    let mut key = String::new();
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(value) = line.strip_prefix("Idempotency-Key: ") {
+            key = value.to_string();
+        }
+    }
+    let mut keys = IDEMPOTENT_POST_KEYS.lock().unwrap();
+    keys.push(key);
+    if keys.len() == 1 {
+        stream.write_all(b"HTTP/1.1 503 Unavailable\r\nRetry-After: 0\r\nContent-Length: 0\r\n\r\n")
+    } else {
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+    }
+}
+
+#[test]
+fn retry_post_with_idempotency_key() {
+    let server = TestServer::new(idempotent_post_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = post(&url)
+        .with_idempotency_key()
+        .retries(2)
+        .send_string("amount=10");
+    assert_eq!(resp.status(), 200);
+    let keys = IDEMPOTENT_POST_KEYS.lock().unwrap();
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys[0], keys[1]);
+    assert_eq!(keys[0].len(), 36);
+    assert_eq!(&keys[0][14..15], "4");
+}
+
+#[test]
+fn idempotency_key_set_once() {
+    let mut req = post("http://localhost/");
+    req.with_idempotency_key();
+    let key = req.header("Idempotency-Key").unwrap().to_string();
+    req.with_idempotency_key();
+    assert_eq!(req.header("Idempotency-Key"), Some(key.as_str()));
+    assert_ne!(
+        post("http://localhost/")
+            .with_idempotency_key()
+            .header("Idempotency-Key"),
+        Some(key.as_str())
+    );
+}
+
+static RATE_LIMITED_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+// Answer the first request with 429 and a Retry-After date in the past.
+fn rate_limited_respond(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    if RATE_LIMITED_CALLS.fetch_add(1, Ordering::SeqCst) == 0 {
+        stream.write_all(
+            b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: Sun, 06 Nov 1994 08:49:37 GMT\r\nContent-Length: 0\r\n\r\n",
+        )
+    } else {
+        stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\n\r\nok")
+    }
+}
+
+#[test]
+fn retry_rate_limited_with_date() {
+    let server = TestServer::new(rate_limited_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url).retries(1).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(RATE_LIMITED_CALLS.load(Ordering::SeqCst), 2);
+}
+
+static LONG_RATE_LIMIT_CALLS: AtomicUsize = AtomicUsize::new(0);
+
+fn long_rate_limit_respond(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    LONG_RATE_LIMIT_CALLS.fetch_add(1, Ordering::SeqCst);
+    stream.write_all(
+        b"HTTP/1.1 429 Too Many Requests\r\nRetry-After: 999999\r\nContent-Length: 4\r\n\r\nslow",
+    )
+}
+
+#[test]
+fn no_retry_past_max_retry_wait() {
+    let server = TestServer::new(long_rate_limit_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url).retries(3).call();
+    assert_eq!(resp.status(), 429);
+    assert_eq!(resp.into_string().unwrap(), "slow");
+    assert_eq!(LONG_RATE_LIMIT_CALLS.load(Ordering::SeqCst), 1);
+}
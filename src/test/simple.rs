@@ -48,6 +48,174 @@ fn repeat_x_header() {
     assert_eq!(resp.status(), 200);
 }
 
+#[test]
+fn user_agent_override() {
+    test::set_handler("/user_agent_override", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/user_agent_override")
+        .set("User-Agent", "first/1.0")
+        .set("user-agent", "my-client/2.0")
+        .call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec).to_lowercase();
+    assert_eq!(s.matches("user-agent:").count(), 1);
+    assert!(s.contains("\r\nuser-agent: my-client/2.0\r\n"));
+}
+
+#[test]
+fn user_agent_suppressed() {
+    test::set_handler("/user_agent_suppressed", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/user_agent_suppressed")
+        .set("User-Agent", "")
+        .call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec).to_lowercase();
+    assert!(!s.contains("user-agent"));
+}
+
+#[test]
+fn host_override() {
+    test::set_handler("/host_override", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host:8080/host_override")
+        .set("Host", "evil.example.com")
+        .call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec).to_lowercase();
+    assert_eq!(s.matches("host:").count(), 1);
+    assert!(s.contains("\r\nhost: evil.example.com\r\n"));
+}
+
+#[test]
+fn host_suppressed() {
+    test::set_handler("/host_suppressed", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/host_suppressed").set("Host", "").call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec).to_lowercase();
+    assert!(!s.contains("host:"));
+}
+
+#[test]
+fn header_case_and_order() {
+    test::set_handler("/header_case_and_order", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = post("test://host/header_case_and_order")
+        .set("x-first", "1")
+        .set("ACCEPT", "text/html")
+        .set("x-last", "2")
+        .set("accept", "text/plain")
+        .send_string("hi");
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    let headers: Vec<_> = s
+        .split("\r\n")
+        .skip(1)
+        .take_while(|l| !l.is_empty())
+        // only sent with the gzip or brotli features.
+        .filter(|l| !l.starts_with("Accept-Encoding"))
+        .collect();
+    assert_eq!(
+        headers,
+        vec![
+            "Host: host",
+            "User-Agent: ureq",
+            "x-first: 1",
+            "accept: text/plain",
+            "x-last: 2",
+            "Content-Length: 2",
+        ]
+    );
+}
+
+#[test]
+fn header_order() {
+    test::set_handler("/header_order", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = post("test://host/header_order")
+        .set("Accept-Language", "en")
+        .set("X-Other", "1")
+        .header_order(&["content-length", "accept-language", "user-agent", "host"])
+        .send_string("hi");
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    let headers: Vec<_> = s
+        .split("\r\n")
+        .skip(1)
+        .take_while(|l| !l.is_empty())
+        // only sent with the gzip or brotli features.
+        .filter(|l| !l.starts_with("Accept-Encoding"))
+        .collect();
+    assert_eq!(
+        &headers[..4],
+        &[
+            "Content-Length: 2",
+            "Accept-Language: en",
+            "User-Agent: ureq",
+            "Host: host",
+        ]
+    );
+    assert_eq!(&headers[4..], &["Accept: */*", "X-Other: 1"]);
+}
+
+#[test]
+fn cache_key_vary() {
+    let agent = agent().set("X-Team", "blue").build();
+    let mut req = agent.get("http://example.com/page");
+    req.query("a", "1").set("Accept-Language", "en");
+    assert_eq!(
+        req.cache_key(None).unwrap(),
+        "GET http://example.com/page?a=1"
+    );
+    // the names are sorted, and ones not set are empty.
+    assert_eq!(
+        req.cache_key(Some("x-team, Accept-Language, X-None, accept-language"))
+            .unwrap(),
+        "GET http://example.com/page?a=1\naccept-language: en\nx-none: \nx-team: blue"
+    );
+    assert_eq!(req.cache_key(Some("Accept, *")), None);
+    #[cfg(feature = "gzip")]
+    assert_eq!(
+        req.cache_key(Some("Accept-Encoding")).unwrap(),
+        format!(
+            "GET http://example.com/page?a=1\naccept-encoding: {}",
+            crate::decoder::ACCEPT_ENCODING
+        )
+    );
+    assert_ne!(
+        req.cache_key(Some("Accept-Encoding")),
+        req.clone()
+            .set("Accept-Encoding", "identity")
+            .cache_key(Some("Accept-Encoding"))
+    );
+}
+
+#[test]
+fn try_call_status_error() {
+    test::set_handler("/try_call_status_error", |_| {
+        test::make_response(404, "Not Found", vec![], b"no such page".to_vec())
+    });
+    match get("test://host/try_call_status_error").try_call() {
+        Err(Error::Status(404, resp)) => {
+            assert_eq!(resp.into_string().unwrap(), "no such page");
+        }
+        r => panic!("expected a status error, got {:?}", r),
+    }
+
+    test::set_handler("/try_call_status_error", |_| {
+        test::make_response(200, "OK", vec![], b"hello".to_vec())
+    });
+    let resp = get("test://host/try_call_status_error").try_call().unwrap();
+    assert_eq!(resp.into_string().unwrap(), "hello");
+}
+
 #[test]
 fn body_as_text() {
     test::set_handler("/body_as_text", |_unit| {
@@ -70,12 +238,25 @@ fn body_as_json() {
         )
     });
     let resp = get("test://host/body_as_json").call();
-    let json = resp.into_json().unwrap();
+    let json = resp.into_json::<serde_json::Value>().unwrap();
     assert_eq!(json["hello"], "world");
 }
 
 #[test]
 #[cfg(feature = "json")]
+fn body_as_json_malformed() {
+    test::set_handler("/body_as_json_malformed", |_unit| {
+        test::make_response(200, "OK", vec![], b"{\"hello\": wor".to_vec())
+    });
+    let resp = get("test://host/body_as_json_malformed").call();
+    let err = resp.into_json::<serde_json::Value>().unwrap_err();
+    assert!(matches!(err, Error::BadJson(_)), "{:?}", err);
+    assert_eq!(err.kind(), ErrorKind::Transport);
+}
+
+#[test]
+#[cfg(feature = "json")]
+#[allow(deprecated)]
 fn body_as_json_deserialize() {
     use serde::Deserialize;
 
@@ -132,7 +313,7 @@ fn request_debug() {
 
     assert_eq!(
         s,
-        "Request(GET /my/page, [Authorization: abcdef, \
+        "Request(GET /my/page, [Authorization: <redacted>, \
          Content-Length: 1234, Content-Type: application/json])"
     );
 
@@ -145,7 +326,7 @@ fn request_debug() {
 
     assert_eq!(
         s,
-        "Request(GET /my/page?q=z&foo=bar%20baz, [Authorization: abcdef])"
+        "Request(GET /my/page?q=z&foo=bar%20baz, [Authorization: <redacted>])"
     );
 }
 
@@ -209,3 +390,67 @@ pub fn host_with_port() {
     let s = String::from_utf8_lossy(&vec);
     assert!(s.contains("\r\nHost: myhost:234\r\n"));
 }
+
+#[test]
+pub fn host_ipv6_with_port() {
+    test::set_handler("/host_ipv6_with_port", |_| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://[::1]:8080/host_ipv6_with_port").call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nHost: [::1]:8080\r\n"));
+}
+
+#[test]
+fn request_target_percent_encoded() {
+    test::set_handler("/search/hello%20world", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/search/hello world?q=a|b^[c]&x=ü").call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.starts_with("GET /search/hello%20world?q=a%7Cb%5E%5Bc%5D&x=%C3%BC HTTP/1.1\r\n"));
+}
+
+#[test]
+fn request_target_keeps_encoded_and_reserved() {
+    test::set_handler("/a%20b/c:d@e;f=g,h!$&'()*+", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let resp = get("test://host/a%20b/c:d@e;f=g,h!$&'()*+?x=%7C/?").call();
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.starts_with("GET /a%20b/c:d@e;f=g,h!$&'()*+?x=%7C/? HTTP/1.1\r\n"));
+}
+
+#[test]
+fn conditional_request_headers() {
+    test::set_handler("/conditional_request_headers", |_| {
+        test::make_response(304, "Not Modified", vec!["ETag: \"abc\""], vec![])
+    });
+    let since = std::time::UNIX_EPOCH + std::time::Duration::from_secs(784_111_777);
+    let resp = get("test://host/conditional_request_headers")
+        .set_if_modified_since(since)
+        .set_if_none_match("abc")
+        .call();
+    assert!(resp.not_modified());
+    assert!(!resp.ok());
+    let vec = resp.to_write_vec();
+    let s = String::from_utf8_lossy(&vec);
+    assert!(s.contains("\r\nIf-Modified-Since: Sun, 06 Nov 1994 08:49:37 GMT\r\n"));
+    assert!(s.contains("\r\nIf-None-Match: \"abc\"\r\n"));
+}
+
+#[test]
+fn if_none_match_keeps_quoted_etags() {
+    for (etag, sent) in &[
+        ("\"abc\"", "\"abc\""),
+        ("W/\"abc\"", "W/\"abc\""),
+        ("*", "*"),
+        ("W/abc", "\"W/abc\""),
+    ] {
+        let req = get("test://host/").set_if_none_match(etag).build();
+        assert_eq!(req.header("If-None-Match"), Some(*sent));
+    }
+}
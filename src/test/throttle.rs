@@ -0,0 +1,54 @@
+use crate::test;
+use crate::test::testserver::*;
+use std::io::{self, Write};
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use super::super::*;
+
+// Send a 2000 byte body as fast as possible.
+fn fast_respond(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2000\r\n\r\n")?;
+    stream.write_all(&[b'a'; 2000])?;
+    Ok(())
+}
+
+#[test]
+fn throttled_download() {
+    let server = TestServer::new(fast_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let agent = agent().max_bytes_per_sec(10_000).build();
+    let start = Instant::now();
+    let body = agent.get(&url).call().into_string().unwrap();
+    assert_eq!(body.len(), 2000);
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[test]
+fn throttled_upload() {
+    test::set_handler("/throttled_upload", |_unit| {
+        test::make_response(200, "OK", vec![], vec![])
+    });
+    let agent = agent().max_bytes_per_sec(10_000).build();
+    let start = Instant::now();
+    let resp = agent
+        .post("test://host/throttled_upload")
+        .send_bytes(&[b'a'; 2000]);
+    assert_eq!(resp.status(), 200);
+    assert!(start.elapsed() >= Duration::from_millis(150));
+}
+
+#[test]
+fn throttle_counts_against_deadline() {
+    let server = TestServer::new(fast_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let agent = agent().max_bytes_per_sec(1_000).build();
+    let resp = agent.get(&url).timeout(Duration::from_millis(500)).call();
+    let err = resp
+        .into_string()
+        .expect_err("expected the deadline to pass");
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    let inner = err.get_ref().and_then(|e| e.downcast_ref::<Error>());
+    assert!(matches!(inner, Some(Error::DeadlineExceeded)));
+}
@@ -1,6 +1,7 @@
 use crate::test::testserver::*;
 use std::io::{self, Write};
 use std::net::TcpStream;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::thread;
 use std::time::Duration;
 
@@ -68,3 +69,260 @@ fn overall_timeout_during_headers() {
     let url = format!("http://localhost:{}/", server.port);
     get_and_expect_timeout(url);
 }
+
+// The ureq error inside an io::Error from reading a response body.
+fn inner_error(io_error: &io::Error) -> Option<&Error> {
+    io_error.get_ref().and_then(|e| e.downcast_ref::<Error>())
+}
+
+// Send the headers and the start of the body, then stall for two seconds.
+fn stalled_body_respond(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\n\r\nhello")?;
+    stream.flush()?;
+    thread::sleep(Duration::from_secs(2));
+    stream.write_all(b"world")?;
+    Ok(())
+}
+
+#[test]
+fn read_timeout_during_body() {
+    let server = TestServer::new(stalled_body_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url).timeout_read(200).call();
+    assert_eq!(resp.status(), 200);
+    let err = resp.into_string().expect_err("expected a read timeout");
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(matches!(inner_error(&err), Some(Error::ReadTimeout)));
+}
+
+#[test]
+fn read_timeout_during_copy_to() {
+    let server = TestServer::new(stalled_body_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url).timeout_read(200).call();
+    let mut out = vec![];
+    let err = resp.copy_to(&mut out).expect_err("expected a read timeout");
+    match err {
+        Error::Download(written, err) => {
+            assert_eq!(written, 5);
+            assert!(matches!(*err, Error::ReadTimeout));
+        }
+        err => panic!("expected a download error, got {:?}", err),
+    }
+    assert_eq!(out, b"hello");
+}
+
+#[test]
+fn read_timeout_during_headers() {
+    // Headers arrive every 100ms, which is slower than the read timeout.
+    let server = TestServer::new(dribble_headers_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url).timeout_read(50).call();
+    assert!(matches!(resp.synthetic_error(), Some(Error::ReadTimeout)));
+}
+
+#[test]
+fn deadline_with_read_timeout() {
+    // Every read is well within the read timeout, but the body as a whole
+    // takes longer than the overall timeout.
+    let server = TestServer::new(dribble_body_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url)
+        .timeout_read(1_000)
+        .timeout(Duration::from_millis(500))
+        .call();
+    let err = resp
+        .into_string()
+        .expect_err("expected the deadline to pass");
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(matches!(inner_error(&err), Some(Error::DeadlineExceeded)));
+}
+
+// Accept the connection but never answer, so a TLS handshake stalls.
+#[cfg(feature = "tls")]
+fn silent_respond(_stream: TcpStream) -> io::Result<()> {
+    thread::sleep(Duration::from_secs(2));
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "tls")]
+fn connect_timeout_during_tls_handshake() {
+    let server = TestServer::new(silent_respond);
+    let url = format!("https://localhost:{}/", server.port);
+    let start = std::time::Instant::now();
+    let resp = get(&url).timeout_connect(200).call();
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::ConnectTimeout)
+    ));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+#[cfg(feature = "tls")]
+fn connect_timeout_limited_by_deadline() {
+    let server = TestServer::new(silent_respond);
+    let url = format!("https://localhost:{}/", server.port);
+    let start = std::time::Instant::now();
+    let resp = get(&url)
+        .timeout_connect(5_000)
+        .timeout(Duration::from_millis(200))
+        .call();
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::DeadlineExceeded)
+    ));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+// Wait before answering, longer than the connect timeout in the test below.
+fn slow_respond(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    thread::sleep(Duration::from_millis(300));
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 5\r\n\r\nhello")?;
+    Ok(())
+}
+
+#[test]
+fn connect_timeout_does_not_limit_reads() {
+    let server = TestServer::new(slow_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url).timeout_connect(100).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "hello");
+}
+
+// Read the request headers, then stop reading so the body upload stalls.
+fn stop_reading_respond(stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    thread::sleep(Duration::from_secs(3));
+    Ok(())
+}
+
+// Bigger than the socket buffers, so writing it has to wait for the server.
+const BIG_BODY: usize = 32 * 1024 * 1024;
+
+#[test]
+fn write_timeout_during_body() {
+    let server = TestServer::new(stop_reading_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let body = vec![b'a'; BIG_BODY];
+    let resp = put(&url).timeout_write(200).send_bytes(&body);
+    assert!(matches!(resp.synthetic_error(), Some(Error::WriteTimeout)));
+}
+
+#[test]
+fn write_timeout_during_chunked_body() {
+    let server = TestServer::new(stop_reading_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let body = io::Read::take(io::repeat(b'a'), BIG_BODY as u64);
+    let resp = put(&url)
+        .set("Transfer-Encoding", "chunked")
+        .timeout_write(200)
+        .send(body, None);
+    assert!(matches!(resp.synthetic_error(), Some(Error::WriteTimeout)));
+}
+
+#[test]
+fn deadline_during_body_upload() {
+    let server = TestServer::new(stop_reading_respond);
+    let url = format!("http://localhost:{}/", server.port);
+    let body = vec![b'a'; BIG_BODY];
+    let resp = put(&url)
+        .timeout_write(5_000)
+        .timeout(Duration::from_millis(300))
+        .send_bytes(&body);
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::DeadlineExceeded)
+    ));
+}
+
+fn slow_resolver(_addr: &str) -> io::Result<Vec<std::net::SocketAddr>> {
+    thread::sleep(Duration::from_secs(2));
+    Ok(vec![])
+}
+
+#[test]
+fn deadline_covers_dns() {
+    let agent = agent().resolver(slow_resolver).build();
+    let start = std::time::Instant::now();
+    let resp = agent
+        .get("http://api.example.com/")
+        .timeout(Duration::from_millis(200))
+        .call();
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::DeadlineExceeded)
+    ));
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn connect_timeout_covers_dns() {
+    let agent = agent().resolver(slow_resolver).build();
+    let start = std::time::Instant::now();
+    let resp = agent
+        .get("http://api.example.com/")
+        .timeout_connect(200)
+        .call();
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::ConnectTimeout)
+    ));
+    assert!(start.elapsed() < Duration::from_secs(2));
+}
+
+static SLOW_REDIRECTS: AtomicUsize = AtomicUsize::new(0);
+
+// Redirect to another page, after a while.
+fn slow_redirect(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    thread::sleep(Duration::from_millis(150));
+    let n = SLOW_REDIRECTS.fetch_add(1, Ordering::SeqCst);
+    let resp = format!(
+        "HTTP/1.1 302 Found\r\nLocation: /{}\r\nContent-Length: 0\r\n\r\n",
+        n
+    );
+    stream.write_all(resp.as_bytes())?;
+    Ok(())
+}
+
+#[test]
+fn deadline_covers_redirects() {
+    let server = TestServer::new(slow_redirect);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url).timeout(Duration::from_millis(400)).call();
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::DeadlineExceeded)
+    ));
+}
+
+// Send one event, then leave the stream open without sending more.
+#[cfg(feature = "sse")]
+fn stalled_event_stream(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\n\r\n")?;
+    stream.write_all(b"data: one\n\n")?;
+    stream.flush()?;
+    thread::sleep(Duration::from_secs(5));
+    Ok(())
+}
+
+#[test]
+#[cfg(feature = "sse")]
+fn event_stream_times_out() {
+    let server = TestServer::new(stalled_event_stream);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url).timeout(Duration::from_millis(500)).call();
+    let mut events = resp.into_events();
+    assert_eq!(events.next().unwrap().unwrap().data, "one");
+    let err = events.next().unwrap().unwrap_err();
+    assert!(matches!(
+        err.kind(),
+        io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock
+    ));
+}
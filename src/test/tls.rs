@@ -0,0 +1,295 @@
+use rustls::internal::pemfile;
+use rustls::{
+    AllowAnyAuthenticatedClient, NoClientAuth, RootCertStore, ServerConfig, ServerSession,
+    StreamOwned,
+};
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+
+use super::super::*;
+
+pub const CA_PEM: &[u8] = include_bytes!("certs/ca.pem");
+pub const SERVER_PEM: &[u8] = include_bytes!("certs/server.pem");
+pub const SERVER_KEY: &[u8] = include_bytes!("certs/server.key");
+pub const CLIENT_PEM: &[u8] = include_bytes!("certs/client.pem");
+pub const CLIENT_KEY: &[u8] = include_bytes!("certs/client.key");
+
+// A server config with the localhost certificate signed by the test CA, that
+// requires a client certificate signed by the same CA if asked to.
+fn server_config(require_client_cert: bool) -> Arc<ServerConfig> {
+    let mut config = if require_client_cert {
+        let mut roots = RootCertStore::empty();
+        roots.add_pem_file(&mut &CA_PEM[..]).unwrap();
+        ServerConfig::new(AllowAnyAuthenticatedClient::new(roots))
+    } else {
+        ServerConfig::new(NoClientAuth::new())
+    };
+    let certs = pemfile::certs(&mut &SERVER_PEM[..]).unwrap();
+    let key = pemfile::pkcs8_private_keys(&mut &SERVER_KEY[..])
+        .unwrap()
+        .remove(0);
+    config.set_single_cert(certs, key).unwrap();
+    Arc::new(config)
+}
+
+// Serve a single https connection, answering `200 OK` with body "secure".
+fn serve_tls(require_client_cert: bool) -> u16 {
+    serve_tls_with(server_config(require_client_cert))
+}
+
+fn serve_tls_with(config: Arc<ServerConfig>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || -> io::Result<()> {
+        let (sock, _) = listener.accept()?;
+        let mut tls = StreamOwned::new(ServerSession::new(&config), sock);
+        {
+            let mut reader = BufReader::new(&mut tls);
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line)?;
+                if line.trim_end().is_empty() {
+                    break;
+                }
+            }
+        }
+        tls.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 6\r\n\r\nsecure")?;
+        tls.flush()
+    });
+    port
+}
+
+#[test]
+fn client_certificate_in_config() {
+    let agent = agent()
+        .client_certificate(CLIENT_PEM, CLIENT_KEY)
+        .unwrap()
+        .build();
+    let config = agent.tls_config.expect("agent tls config");
+    assert!(config.0.client_auth_cert_resolver.has_certs());
+}
+
+#[test]
+fn client_certificate_bad_key() {
+    let result = agent()
+        .client_certificate(CLIENT_PEM, b"not a key")
+        .map(|_| ());
+    assert!(matches!(result, Err(Error::BadCertificate(_))));
+}
+
+#[test]
+fn untrusted_server_is_handshake_error() {
+    // the test CA isn't among the default roots.
+    let port = serve_tls(false);
+    let resp = get(&format!("https://localhost:{}/", port)).call();
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::TlsHandshake(_))
+    ));
+}
+
+#[test]
+fn added_root_certificate_is_trusted() {
+    let port = serve_tls(false);
+    let agent = agent().add_root_certificate(CA_PEM).unwrap().build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "secure");
+}
+
+#[test]
+fn mutual_tls_with_client_certificate() {
+    let port = serve_tls(true);
+    let agent = agent()
+        .add_root_certificate(CA_PEM)
+        .unwrap()
+        .client_certificate(CLIENT_PEM, CLIENT_KEY)
+        .unwrap()
+        .build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "secure");
+}
+
+#[test]
+fn mutual_tls_without_client_certificate() {
+    let port = serve_tls(true);
+    let agent = agent().add_root_certificate(CA_PEM).unwrap().build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::TlsHandshake(_))
+    ));
+}
+
+#[test]
+fn danger_accept_invalid_certs() {
+    let port = serve_tls(false);
+    let agent = agent().danger_accept_invalid_certs(true).build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "secure");
+}
+
+#[test]
+fn ip_literal_without_sni() {
+    let port = serve_tls(false);
+    let agent = agent().danger_accept_invalid_certs(true).build();
+    let resp = agent.get(&format!("https://127.0.0.1:{}/", port)).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "secure");
+}
+
+#[test]
+fn custom_tls_config_used_verbatim() {
+    let port = serve_tls(false);
+    let mut config = rustls::ClientConfig::new();
+    config.root_store.add_pem_file(&mut &CA_PEM[..]).unwrap();
+    // the default roots don't trust the test CA, so this only passes with our config.
+    let agent = agent().set_tls_config(Arc::new(config)).build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert_eq!(resp.status(), 200);
+}
+
+#[test]
+fn custom_tls_config_overrides_options() {
+    let port = serve_tls(false);
+    // no roots at all, so the server can't be trusted.
+    let agent = agent()
+        .add_root_certificate(CA_PEM)
+        .unwrap()
+        .set_tls_config(Arc::new(rustls::ClientConfig::new()))
+        .build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::TlsHandshake(_))
+    ));
+}
+
+#[test]
+fn tls_server_name_for_ip_address() {
+    let port = serve_tls(false);
+    let agent = agent().add_root_certificate(CA_PEM).unwrap().build();
+    let resp = agent
+        .get(&format!("https://127.0.0.1:{}/", port))
+        .tls_server_name("localhost")
+        .call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "secure");
+}
+
+#[test]
+fn tls_server_name_checked_against_certificate() {
+    let port = serve_tls(false);
+    let agent = agent().add_root_certificate(CA_PEM).unwrap().build();
+    let resp = agent
+        .get(&format!("https://localhost:{}/", port))
+        .tls_server_name("api.example.com")
+        .call();
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::TlsHandshake(_))
+    ));
+}
+
+#[test]
+fn peer_certificate_of_server() {
+    let port = serve_tls(false);
+    let agent = agent().add_root_certificate(CA_PEM).unwrap().build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert_eq!(resp.status(), 200);
+    let server_cert = pemfile::certs(&mut &SERVER_PEM[..]).unwrap().remove(0);
+    assert_eq!(resp.peer_certificate(), Some(&server_cert.0[..]));
+}
+
+#[test]
+fn negotiated_protocol_with_alpn() {
+    let mut server = (*server_config(false)).clone();
+    server.set_protocols(&[b"http/1.1".to_vec()]);
+    let port = serve_tls_with(Arc::new(server));
+    let mut config = rustls::ClientConfig::new();
+    config.root_store.add_pem_file(&mut &CA_PEM[..]).unwrap();
+    config.set_protocols(&[b"h2".to_vec(), b"http/1.1".to_vec()]);
+    let agent = agent().set_tls_config(Arc::new(config)).build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.negotiated_protocol().as_deref(), Some("http/1.1"));
+}
+
+#[test]
+fn negotiated_protocol_without_alpn() {
+    let port = serve_tls(false);
+    let agent = agent().add_root_certificate(CA_PEM).unwrap().build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.negotiated_protocol(), None);
+}
+
+#[test]
+fn pinned_public_key_accepted() {
+    let port = serve_tls(false);
+    let agent = agent()
+        .add_root_certificate(CA_PEM)
+        .unwrap()
+        .pinned_public_keys(&[
+            "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=",
+            "sha256//6BSf7/GUGXZy+7SlCVVH7U/BZXvVYyWSC8XkuuRDoGc=",
+        ])
+        .unwrap()
+        .build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(resp.into_string().unwrap(), "secure");
+}
+
+#[test]
+fn pinned_public_key_mismatch() {
+    let port = serve_tls(false);
+    let agent = agent()
+        .add_root_certificate(CA_PEM)
+        .unwrap()
+        .pinned_public_keys(&["47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="])
+        .unwrap()
+        .build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert!(matches!(resp.synthetic_error(), Some(Error::PinMismatch)));
+}
+
+#[test]
+fn min_tls_version_negotiated() {
+    let port = serve_tls(false);
+    let agent = agent()
+        .add_root_certificate(CA_PEM)
+        .unwrap()
+        .min_tls_version(TlsVersion::Tls13)
+        .build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert_eq!(resp.status(), 200);
+}
+
+#[test]
+fn max_tls_version_below_server() {
+    let mut config = (*server_config(false)).clone();
+    config.versions = vec![rustls::ProtocolVersion::TLSv1_3];
+    let port = serve_tls_with(Arc::new(config));
+    let agent = agent()
+        .add_root_certificate(CA_PEM)
+        .unwrap()
+        .max_tls_version(TlsVersion::Tls12)
+        .build();
+    let resp = agent.get(&format!("https://localhost:{}/", port)).call();
+    assert!(matches!(resp.synthetic_error(), Some(Error::TlsVersion(_))));
+}
+
+#[test]
+fn no_tls_version_allowed() {
+    let agent = agent()
+        .min_tls_version(TlsVersion::Tls13)
+        .max_tls_version(TlsVersion::Tls12)
+        .build();
+    let resp = agent.get("https://localhost:1/").call();
+    assert!(matches!(resp.synthetic_error(), Some(Error::TlsVersion(_))));
+}
@@ -0,0 +1,102 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use super::super::*;
+
+// Serve one connection on a fresh socket with the handler, and return the
+// url to reach it and the socket path to clean up.
+fn serve_once(name: &str, handler: fn(UnixStream) -> io::Result<()>) -> (String, PathBuf) {
+    let path = std::env::temp_dir().join(format!("ureq-{}-{}.sock", name, std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let listener = UnixListener::bind(&path).unwrap();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        handler(stream)
+    });
+    let encoded = path.to_str().unwrap().replace('/', "%2F");
+    (format!("unix://{}", encoded), path)
+}
+
+// Answer with the request line, Host header and body the server saw.
+fn echo_respond(mut stream: UnixStream) -> io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut seen = String::new();
+    let mut chunked = false;
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end().to_string();
+        if line.is_empty() {
+            break;
+        }
+        chunked |= line.eq_ignore_ascii_case("transfer-encoding: chunked");
+        if !line.contains(':') || line.starts_with("Host:") {
+            seen.push_str(&line);
+            seen.push('\n');
+        }
+    }
+    if chunked {
+        let mut body = String::new();
+        chunked_transfer::Decoder::new(reader).read_to_string(&mut body)?;
+        seen.push_str(&body);
+    }
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        seen.len(),
+        seen
+    )
+}
+
+#[test]
+fn unix_socket_get() {
+    let (url, path) = serve_once("get", echo_respond);
+    let resp = get(&format!("{}/containers/json", url))
+        .query("all", "1")
+        .call();
+    assert_eq!(resp.status(), 200);
+    assert_eq!(
+        resp.into_string().unwrap(),
+        "GET /containers/json?all=1 HTTP/1.1\nHost: localhost\n"
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn unix_socket_chunked_body() {
+    let (url, path) = serve_once("chunked", echo_respond);
+    let resp = post(&format!("{}/upload", url))
+        .set("Transfer-Encoding", "chunked")
+        .send_string("hello unix");
+    assert_eq!(
+        resp.into_string().unwrap(),
+        "POST /upload HTTP/1.1\nHost: localhost\nhello unix"
+    );
+    std::fs::remove_file(path).unwrap();
+}
+
+fn stall_respond(stream: UnixStream) -> io::Result<()> {
+    thread::sleep(Duration::from_secs(2));
+    drop(stream);
+    Ok(())
+}
+
+#[test]
+fn unix_socket_read_timeout() {
+    let (url, path) = serve_once("timeout", stall_respond);
+    let resp = get(&format!("{}/", url)).timeout_read(100).call();
+    assert!(matches!(resp.synthetic_error(), Some(Error::ReadTimeout)));
+    std::fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn unix_socket_missing() {
+    let resp = get("unix://%2Fnonexistent%2Fureq.sock/").call();
+    assert!(matches!(
+        resp.synthetic_error(),
+        Some(Error::ConnectionFailed(_))
+    ));
+}
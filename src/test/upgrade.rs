@@ -0,0 +1,72 @@
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+
+use super::super::*;
+use crate::test::testserver::{read_headers, TestServer};
+
+// switches to a protocol that echoes each line back in upper case.
+fn echo_upgrade(mut stream: TcpStream) -> io::Result<()> {
+    read_headers(&stream);
+    stream.write_all(
+        b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\nUpgrade: shout\r\n\r\n",
+    )?;
+    let mut buf = [0; 64];
+    loop {
+        let n = stream.read(&mut buf)?;
+        if n == 0 {
+            return Ok(());
+        }
+        stream.write_all(&buf[..n].to_ascii_uppercase())?;
+    }
+}
+
+#[test]
+fn upgraded_connection() {
+    let server = TestServer::new(echo_upgrade);
+    let url = format!("http://localhost:{}/", server.port);
+    let resp = get(&url)
+        .set("Connection", "Upgrade")
+        .set("Upgrade", "shout")
+        .call();
+    assert_eq!(resp.status(), 101);
+    assert_eq!(resp.header("upgrade"), Some("shout"));
+    let mut conn = resp.into_upgraded().unwrap();
+    for word in &["hello\n", "there\n"] {
+        conn.write_all(word.as_bytes()).unwrap();
+        let mut buf = [0; 6];
+        conn.read_exact(&mut buf).unwrap();
+        assert_eq!(&buf, word.to_uppercase().as_bytes());
+    }
+}
+
+#[test]
+fn upgrade_refused() {
+    test::set_handler("/upgrade_refused", |_| {
+        test::make_response(400, "Bad Request", vec![], b"no upgrade".to_vec())
+    });
+    let resp = get("test://host/upgrade_refused")
+        .set("Connection", "Upgrade")
+        .set("Upgrade", "shout")
+        .call();
+    match resp.into_upgraded() {
+        Err(Error::Status(400, resp)) => assert_eq!(resp.into_string().unwrap(), "no upgrade"),
+        other => panic!("expected a status error, got {:?}", other),
+    }
+}
+
+#[test]
+fn parsed_upgrade_is_read_only() {
+    let resp = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: shout\r\n\r\nHELLO"
+        .parse::<Response>()
+        .unwrap();
+    let mut conn = resp.into_upgraded().unwrap();
+    let err = conn.write_all(b"hello").unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::NotConnected);
+    assert_eq!(
+        conn.flush().unwrap_err().kind(),
+        io::ErrorKind::NotConnected
+    );
+    let mut s = String::new();
+    conn.read_to_string(&mut s).unwrap();
+    assert_eq!(s, "HELLO");
+}
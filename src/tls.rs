@@ -0,0 +1,501 @@
+use std::fmt;
+
+use crate::error::Error;
+
+/// TLS settings of an agent, applied to the https connections of its requests.
+///
+/// *Internal API*
+#[derive(Clone, Default)]
+pub(crate) struct TlsOptions {
+    /// Certificate chain and private key offered when the server asks for one.
+    pub(crate) client_cert: Option<ClientCert>,
+    /// Extra DER encoded CA certificates, trusted alongside the default roots.
+    pub(crate) root_certs: Vec<Vec<u8>>,
+    /// Skip verifying the server's certificate and hostname.
+    pub(crate) accept_invalid_certs: bool,
+    /// SHA-256 hashes of the public keys (SPKI) the server's certificate may have.
+    /// Checked after the handshake, so they don't go into the config.
+    pub(crate) pinned_keys: Vec<[u8; 32]>,
+    /// The oldest TLS version to negotiate, TLS 1.2 if not set.
+    pub(crate) min_version: Option<TlsVersion>,
+    /// The newest TLS version to negotiate, the newest the backend has if not set.
+    pub(crate) max_version: Option<TlsVersion>,
+    /// A config set by the user, used instead of the options above.
+    #[cfg(feature = "tls")]
+    pub(crate) custom_config: Option<crate::request::TLSClientConfig>,
+    #[cfg(feature = "native-tls")]
+    pub(crate) custom_connector: Option<crate::request::TLSConnector>,
+}
+
+/// A version of the TLS protocol, for
+/// [`min_tls_version()`](struct.Agent.html#method.min_tls_version) and
+/// [`max_tls_version()`](struct.Agent.html#method.max_tls_version).
+///
+/// rustls only has TLS 1.2 and 1.3, the older versions only work with native-tls.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TlsVersion {
+    /// TLS 1.0, deprecated by RFC 8996.
+    Tls10,
+    /// TLS 1.1, deprecated by RFC 8996.
+    Tls11,
+    /// TLS 1.2
+    Tls12,
+    /// TLS 1.3
+    Tls13,
+}
+
+impl TlsVersion {
+    #[cfg(feature = "native-tls")]
+    fn native_protocol(self) -> native_tls::Protocol {
+        match self {
+            TlsVersion::Tls10 => native_tls::Protocol::Tlsv10,
+            TlsVersion::Tls11 => native_tls::Protocol::Tlsv11,
+            TlsVersion::Tls12 => native_tls::Protocol::Tlsv12,
+            TlsVersion::Tls13 => native_tls::Protocol::Tlsv13,
+        }
+    }
+}
+
+/// A client certificate chain and its private key, both DER encoded.
+#[derive(Clone)]
+pub(crate) struct ClientCert {
+    pub(crate) chain: Vec<Vec<u8>>,
+    pub(crate) key: Vec<u8>,
+}
+
+// Never show the private key, since agents are logged with Debug.
+impl fmt::Debug for TlsOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsOptions")
+            .field("client_cert", &self.client_cert.as_ref().map(|_| "***"))
+            .field("root_certs", &self.root_certs.len())
+            .field("accept_invalid_certs", &self.accept_invalid_certs)
+            .field("pinned_keys", &self.pinned_keys.len())
+            .field("min_version", &self.min_version)
+            .field("max_version", &self.max_version)
+            .finish()
+    }
+}
+
+impl TlsOptions {
+    fn is_default(&self) -> bool {
+        self.client_cert.is_none()
+            && self.root_certs.is_empty()
+            && !self.accept_invalid_certs
+            && self.min_version.is_none()
+            && self.max_version.is_none()
+    }
+
+    /// Whether `version` may be negotiated.
+    #[cfg(feature = "tls")]
+    fn allows(&self, version: TlsVersion) -> bool {
+        version >= self.min_version.unwrap_or(TlsVersion::Tls12)
+            && self.max_version.map(|max| version <= max).unwrap_or(true)
+    }
+
+    /// Parse one or more CA certificates, PEM or DER, to trust in addition to the
+    /// default roots. Each is checked with the TLS backend right away.
+    pub(crate) fn add_root_certificates(&mut self, data: &[u8]) -> Result<(), Error> {
+        let certs: Vec<Vec<u8>> = parse_pem_or_der(data, "CERTIFICATE")?
+            .into_iter()
+            .filter(|(label, _)| label == "CERTIFICATE")
+            .map(|(_, der)| der)
+            .collect();
+        if certs.is_empty() {
+            return Err(Error::BadCertificate("No certificate found".to_string()));
+        }
+        for der in &certs {
+            #[cfg(feature = "tls")]
+            add_rustls_root(&mut rustls::RootCertStore::empty(), der)?;
+            #[cfg(feature = "native-tls")]
+            native_tls::Certificate::from_der(der)
+                .map_err(|e| Error::BadCertificate(e.to_string()))?;
+        }
+        self.root_certs.extend(certs);
+        Ok(())
+    }
+
+    /// The rustls config for these options, or None to use the default one.
+    #[cfg(feature = "tls")]
+    pub(crate) fn rustls_config(&self) -> Option<crate::request::TLSClientConfig> {
+        if let Some(config) = &self.custom_config {
+            return Some(config.clone());
+        }
+        if self.is_default() {
+            return None;
+        }
+        let mut config = rustls::ClientConfig::new();
+        crate::stream::configure_certs(&mut config);
+        for der in &self.root_certs {
+            // checked by add_root_certificates() already.
+            add_rustls_root(&mut config.root_store, der).expect("root certificate");
+        }
+        if let Some(cert) = &self.client_cert {
+            // checked by ClientCert::parse() already.
+            set_client_cert(&mut config, cert).expect("client certificate");
+        }
+        if self.accept_invalid_certs {
+            config
+                .dangerous()
+                .set_certificate_verifier(std::sync::Arc::new(NoVerifier));
+        }
+        // left empty if none allowed, which fails before connecting.
+        config.versions = [
+            (TlsVersion::Tls13, rustls::ProtocolVersion::TLSv1_3),
+            (TlsVersion::Tls12, rustls::ProtocolVersion::TLSv1_2),
+        ]
+        .iter()
+        .filter(|(version, _)| self.allows(*version))
+        .map(|(_, protocol)| *protocol)
+        .collect();
+        Some(crate::request::TLSClientConfig(std::sync::Arc::new(config)))
+    }
+
+    /// The native-tls connector for these options, or None to use the default one.
+    /// When it can't be built, the options are kept for the requests to report the error.
+    #[cfg(feature = "native-tls")]
+    pub(crate) fn native_tls_connector(&self) -> Option<crate::request::TLSConnector> {
+        use crate::request::TLSConnector;
+
+        if let Some(connector) = &self.custom_connector {
+            return Some(connector.clone());
+        }
+        if self.is_default() {
+            return None;
+        }
+        Some(match self.build_native_connector() {
+            Ok(connector) => TLSConnector::Built(std::sync::Arc::new(connector)),
+            Err(_) => TLSConnector::Failed(std::sync::Arc::new(self.clone())),
+        })
+    }
+
+    #[cfg(feature = "native-tls")]
+    pub(crate) fn build_native_connector(
+        &self,
+    ) -> Result<native_tls::TlsConnector, native_tls::Error> {
+        let mut builder = native_tls::TlsConnector::builder();
+        if let Some(cert) = &self.client_cert {
+            // checked by ClientCert::parse() already.
+            builder.identity(native_identity(cert).expect("client certificate"));
+        }
+        for der in &self.root_certs {
+            // checked by add_root_certificates() already.
+            let cert = native_tls::Certificate::from_der(der).expect("root certificate");
+            builder.add_root_certificate(cert);
+        }
+        if self.accept_invalid_certs {
+            builder
+                .danger_accept_invalid_certs(true)
+                .danger_accept_invalid_hostnames(true);
+        }
+        builder
+            .min_protocol_version(Some(
+                self.min_version
+                    .unwrap_or(TlsVersion::Tls12)
+                    .native_protocol(),
+            ))
+            .max_protocol_version(self.max_version.map(TlsVersion::native_protocol));
+        builder.build()
+    }
+}
+
+/// Parse a public key pin: the base64 of the SHA-256 hash of a DER encoded
+/// SubjectPublicKeyInfo, optionally with curl's `sha256//` in front.
+pub(crate) fn parse_pin(pin: &str) -> Result<[u8; 32], Error> {
+    let base64 = pin.trim();
+    let base64 = base64.strip_prefix("sha256//").unwrap_or(base64);
+    let hash = base64::decode(base64)
+        .map_err(|e| Error::BadCertificate(format!("Bad pin {}: {}", pin, e)))?;
+    if hash.len() != 32 {
+        return Err(Error::BadCertificate(format!(
+            "Bad pin {}: not a SHA-256 hash",
+            pin
+        )));
+    }
+    let mut key = [0; 32];
+    key.copy_from_slice(&hash);
+    Ok(key)
+}
+
+/// Check the public key of the server's DER encoded certificate against the pins.
+/// No pins allow any key, and a certificate we can't read matches none.
+pub(crate) fn check_pins(cert: Option<&[u8]>, pins: &[[u8; 32]]) -> Result<(), Error> {
+    if pins.is_empty() {
+        return Ok(());
+    }
+    let spki = cert.and_then(subject_public_key_info);
+    let hash = spki.map(|spki| ring::digest::digest(&ring::digest::SHA256, &spki));
+    match hash {
+        Some(hash) if pins.iter().any(|pin| pin[..] == *hash.as_ref()) => Ok(()),
+        _ => Err(Error::PinMismatch),
+    }
+}
+
+// The DER encoded SubjectPublicKeyInfo of a certificate. webpki parses the
+// certificate and gives the contents of the SEQUENCE, the pin is a hash of all
+// of it, so the tag and length go back in front.
+fn subject_public_key_info(cert: &[u8]) -> Option<Vec<u8>> {
+    const SEQUENCE: u8 = 0x30;
+
+    let spki = webpki::trust_anchor_util::cert_der_as_trust_anchor(cert)
+        .ok()?
+        .spki;
+    let mut der = vec![SEQUENCE];
+    if spki.len() < 0x80 {
+        der.push(spki.len() as u8);
+    } else {
+        let len = spki.len().to_be_bytes();
+        let len = &len[len.iter().take_while(|b| **b == 0).count()..];
+        der.push(0x80 | len.len() as u8);
+        der.extend_from_slice(len);
+    }
+    der.extend_from_slice(spki);
+    Some(der)
+}
+
+impl ClientCert {
+    /// Parse a certificate chain and a private key, each either PEM or DER. A DER
+    /// key must be PKCS#8. The pair is checked with the TLS backend right away, so
+    /// the agent can report a mistake before any request is made.
+    pub(crate) fn parse(cert_chain: &[u8], key: &[u8]) -> Result<ClientCert, Error> {
+        let chain: Vec<Vec<u8>> = parse_pem_or_der(cert_chain, "CERTIFICATE")?
+            .into_iter()
+            .filter(|(label, _)| label == "CERTIFICATE")
+            .map(|(_, der)| der)
+            .collect();
+        if chain.is_empty() {
+            return Err(Error::BadCertificate("No certificate found".to_string()));
+        }
+        let key = parse_pem_or_der(key, "PRIVATE KEY")?
+            .into_iter()
+            .find(|(label, _)| label.ends_with("PRIVATE KEY"))
+            .map(|(_, der)| der)
+            .ok_or_else(|| Error::BadCertificate("No private key found".to_string()))?;
+        let cert = ClientCert { chain, key };
+
+        #[cfg(feature = "tls")]
+        set_client_cert(&mut rustls::ClientConfig::new(), &cert)?;
+        #[cfg(feature = "native-tls")]
+        native_identity(&cert)?;
+
+        Ok(cert)
+    }
+}
+
+/// Accepts any server certificate, for `Agent::danger_accept_invalid_certs`.
+#[cfg(feature = "tls")]
+struct NoVerifier;
+
+#[cfg(feature = "tls")]
+impl rustls::ServerCertVerifier for NoVerifier {
+    fn verify_server_cert(
+        &self,
+        _roots: &rustls::RootCertStore,
+        _presented_certs: &[rustls::Certificate],
+        _dns_name: webpki::DNSNameRef,
+        _ocsp_response: &[u8],
+    ) -> Result<rustls::ServerCertVerified, rustls::TLSError> {
+        Ok(rustls::ServerCertVerified::assertion())
+    }
+}
+
+#[cfg(feature = "tls")]
+fn set_client_cert(config: &mut rustls::ClientConfig, cert: &ClientCert) -> Result<(), Error> {
+    let chain = cert
+        .chain
+        .iter()
+        .cloned()
+        .map(rustls::Certificate)
+        .collect();
+    config
+        .set_single_client_cert(chain, rustls::PrivateKey(cert.key.clone()))
+        .map_err(|e| Error::BadCertificate(e.to_string()))
+}
+
+#[cfg(feature = "tls")]
+fn add_rustls_root(store: &mut rustls::RootCertStore, der: &[u8]) -> Result<(), Error> {
+    store
+        .add(&rustls::Certificate(der.to_vec()))
+        .map_err(|e| Error::BadCertificate(format!("{:?}", e)))
+}
+
+#[cfg(feature = "native-tls")]
+fn native_identity(cert: &ClientCert) -> Result<native_tls::Identity, Error> {
+    let chain: String = cert
+        .chain
+        .iter()
+        .map(|der| to_pem("CERTIFICATE", der))
+        .collect();
+    let key = to_pem("PRIVATE KEY", &cert.key);
+    native_tls::Identity::from_pkcs8(chain.as_bytes(), key.as_bytes())
+        .map_err(|e| Error::BadCertificate(e.to_string()))
+}
+
+/// Read the blocks of a PEM file as (label, DER) pairs. Data that isn't PEM is
+/// taken to be a single DER block with the default label.
+pub(crate) fn parse_pem_or_der(
+    data: &[u8],
+    default_label: &str,
+) -> Result<Vec<(String, Vec<u8>)>, Error> {
+    let text = match std::str::from_utf8(data) {
+        Ok(text) if text.trim_start().starts_with("-----BEGIN ") => text,
+        _ => return Ok(vec![(default_label.to_string(), data.to_vec())]),
+    };
+    let mut blocks = vec![];
+    let mut lines = text.lines().map(str::trim);
+    while let Some(line) = lines.next() {
+        let label = match pem_label(line, "-----BEGIN ") {
+            Some(label) => label,
+            None => continue,
+        };
+        let end = format!("-----END {}-----", label);
+        let mut base64 = String::new();
+        loop {
+            match lines.next() {
+                Some(line) if line == end => break,
+                Some(line) => base64.push_str(line),
+                None => {
+                    return Err(Error::BadCertificate(format!("Missing {}", end)));
+                }
+            }
+        }
+        let der = base64::decode(&base64)
+            .map_err(|e| Error::BadCertificate(format!("Bad PEM {}: {}", label, e)))?;
+        blocks.push((label.to_string(), der));
+    }
+    Ok(blocks)
+}
+
+fn pem_label<'a>(line: &'a str, prefix: &str) -> Option<&'a str> {
+    if line.starts_with(prefix) && line.ends_with("-----") && line.len() > prefix.len() + 5 {
+        Some(&line[prefix.len()..line.len() - 5])
+    } else {
+        None
+    }
+}
+
+#[cfg(feature = "native-tls")]
+fn to_pem(label: &str, der: &[u8]) -> String {
+    let base64 = base64::encode(der);
+    let mut pem = format!("-----BEGIN {}-----\n", label);
+    for chunk in base64.as_bytes().chunks(64) {
+        pem.push_str(std::str::from_utf8(chunk).unwrap());
+        pem.push('\n');
+    }
+    pem.push_str(&format!("-----END {}-----\n", label));
+    pem
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLIENT_PEM: &[u8] = include_bytes!("test/certs/client.pem");
+    const CLIENT_KEY: &[u8] = include_bytes!("test/certs/client.key");
+    const SERVER_PEM: &[u8] = include_bytes!("test/certs/server.pem");
+
+    #[test]
+    fn parse_pem_blocks() {
+        let blocks = parse_pem_or_der(CLIENT_PEM, "CERTIFICATE").unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(blocks[0].0, "CERTIFICATE");
+        // DER starts with a SEQUENCE tag.
+        assert_eq!(blocks[0].1[0], 0x30);
+    }
+
+    #[test]
+    fn parse_der_untouched() {
+        let der = parse_pem_or_der(CLIENT_PEM, "CERTIFICATE")
+            .unwrap()
+            .remove(0)
+            .1;
+        let blocks = parse_pem_or_der(&der, "CERTIFICATE").unwrap();
+        assert_eq!(blocks, vec![("CERTIFICATE".to_string(), der)]);
+    }
+
+    #[test]
+    fn parse_pem_truncated() {
+        let pem = "-----BEGIN CERTIFICATE-----\nMIIB\n";
+        assert!(matches!(
+            parse_pem_or_der(pem.as_bytes(), "CERTIFICATE"),
+            Err(Error::BadCertificate(_))
+        ));
+    }
+
+    #[test]
+    fn client_cert_from_pem_and_der() {
+        assert!(ClientCert::parse(CLIENT_PEM, CLIENT_KEY).is_ok());
+        let cert = parse_pem_or_der(CLIENT_PEM, "CERTIFICATE")
+            .unwrap()
+            .remove(0)
+            .1;
+        let key = parse_pem_or_der(CLIENT_KEY, "PRIVATE KEY")
+            .unwrap()
+            .remove(0)
+            .1;
+        assert!(ClientCert::parse(&cert, &key).is_ok());
+    }
+
+    #[test]
+    fn client_cert_without_key() {
+        assert!(matches!(
+            ClientCert::parse(CLIENT_PEM, CLIENT_PEM),
+            Err(Error::BadCertificate(_))
+        ));
+    }
+
+    #[test]
+    fn root_certificates_accumulate() {
+        let mut options = TlsOptions::default();
+        options.add_root_certificates(CLIENT_PEM).unwrap();
+        let der = options.root_certs[0].clone();
+        options.add_root_certificates(&der).unwrap();
+        assert_eq!(options.root_certs.len(), 2);
+        assert!(!options.is_default());
+    }
+
+    #[test]
+    fn root_certificate_not_a_certificate() {
+        let mut options = TlsOptions::default();
+        assert!(matches!(
+            options.add_root_certificates(CLIENT_KEY),
+            Err(Error::BadCertificate(_))
+        ));
+        assert!(options.root_certs.is_empty());
+    }
+
+    #[test]
+    fn pin_matches_server_key() {
+        let der = parse_pem_or_der(SERVER_PEM, "CERTIFICATE")
+            .unwrap()
+            .remove(0)
+            .1;
+        let pin = parse_pin("sha256//6BSf7/GUGXZy+7SlCVVH7U/BZXvVYyWSC8XkuuRDoGc=").unwrap();
+        let other = parse_pin(&base64::encode([0u8; 32])).unwrap();
+        assert!(check_pins(Some(&der), &[other, pin]).is_ok());
+        assert!(matches!(
+            check_pins(Some(&der), &[other]),
+            Err(Error::PinMismatch)
+        ));
+        assert!(matches!(check_pins(None, &[pin]), Err(Error::PinMismatch)));
+        assert!(check_pins(None, &[]).is_ok());
+    }
+
+    #[test]
+    fn pin_not_a_hash() {
+        assert!(matches!(parse_pin("abc"), Err(Error::BadCertificate(_))));
+        assert!(matches!(
+            parse_pin("sha256//AAAA"),
+            Err(Error::BadCertificate(_))
+        ));
+    }
+
+    #[test]
+    fn debug_hides_key() {
+        let options = TlsOptions {
+            client_cert: Some(ClientCert::parse(CLIENT_PEM, CLIENT_KEY).unwrap()),
+            ..Default::default()
+        };
+        assert!(!format!("{:?}", options).contains("key: ["));
+    }
+}
@@ -1,26 +1,38 @@
 use std::io::{Result as IoResult, Write};
+use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
-use std::time;
+use std::thread;
+use std::time::{self, Duration, Instant};
 
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
 use qstring::QString;
 use url::Url;
 
 #[cfg(feature = "cookie")]
-use cookie::{Cookie, CookieJar};
+use cookie::{Cookie, SameSite};
 
-use crate::agent::AgentState;
+use crate::agent::{AgentState, Resolver};
 use crate::body::{self, Payload, SizedReader};
+use crate::cancel::CancelHandle;
 use crate::header;
-use crate::stream::{self, connect_test, Stream};
+use crate::pool::PoolKey;
+use crate::proxy;
+use crate::request::RedirectAction;
+use crate::stream::{self, connect_test, DeadlineStream, Stream};
 use crate::Proxy;
-use crate::{Error, Header, Request, Response};
+use crate::{Error, Header, RedirectResponse, Request, Response, Timings};
 
 #[cfg(feature = "tls")]
 use crate::request::TLSClientConfig;
+#[cfg(feature = "native-tls")]
+use crate::request::TLSConnector;
 
 #[cfg(feature = "cookie")]
 use crate::pool::DEFAULT_HOST;
 
+/// How long to wait for `100 Continue` when the `Expect` header is set by the user.
+const DEFAULT_EXPECT_CONTINUE_WAIT: Duration = Duration::from_secs(1);
+
 /// It's a "unit of work". Maybe a bad name for it?
 ///
 /// *Internal API*
@@ -29,22 +41,75 @@ pub(crate) struct Unit {
     pub agent: Arc<Mutex<Option<AgentState>>>,
     pub url: Url,
     pub is_chunked: bool,
+    /// How long to wait for `100 Continue` before sending the body, if at all.
+    pub expect_continue: Option<Duration>,
+    /// Set when the server answered before we sent the body, so the
+    /// connection can't be used again.
+    pub body_unsent: bool,
+    /// Whether the connection may come from and go back to the pool.
+    pub keepalive: bool,
+    /// Whether the agent's cookie jar is left out of the request.
+    pub no_cookies: bool,
+    /// Whether the connection for this attempt came from the pool.
+    pub connection_reused: bool,
     pub query_string: String,
     pub headers: Vec<Header>,
+    /// The lowercase names of headers to send first, in this order.
+    pub header_order: Vec<String>,
     pub timeout_connect: u64,
     pub timeout_read: u64,
     pub timeout_write: u64,
     pub deadline: Option<time::Instant>,
+    /// When the call started, for the total in the timings.
+    pub started: time::Instant,
+    /// How long the phases of this attempt took. Written while connecting, which
+    /// only has a `&Unit`.
+    pub timings: Mutex<Timings>,
     pub method: String,
     pub proxy: Option<Proxy>,
+    pub retry_count: u32,
+    pub pool_retries_left: u32,
+    pub max_bytes_per_sec: Option<u64>,
+    pub max_response_size: Option<u64>,
+    pub buffer_size: Option<usize>,
+    pub flush_each_chunk: bool,
+    pub resolver: Option<Resolver>,
+    pub cancel: Option<CancelHandle>,
+    pub tcp_nodelay: bool,
+    pub tcp_keepalive: Option<Duration>,
+    pub local_address: Option<IpAddr>,
+    pub bind_device: Option<String>,
+    /// Whether the credentials of the request are left out, since a redirect went to
+    /// another origin. They stay out for the rest of the redirects.
+    pub credentials_stripped: bool,
+    /// The status and location of each redirect followed to get here.
+    pub history: Vec<(u16, String)>,
+    /// The redirects followed to get here, if the request keeps them.
+    pub redirect_responses: Vec<RedirectResponse>,
+    /// The method and url of each request made before this one, following redirects.
+    pub visited: Vec<(String, String)>,
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    pub compression: bool,
     #[cfg(feature = "tls")]
     pub tls_config: Option<TLSClientConfig>,
+    #[cfg(feature = "native-tls")]
+    pub tls_connector: Option<TLSConnector>,
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub tls_server_name: Option<String>,
+    #[cfg(any(feature = "tls", feature = "native-tls"))]
+    pub pinned_keys: Vec<[u8; 32]>,
 }
 
 impl Unit {
     //
 
-    pub(crate) fn new(req: &Request, url: &Url, mix_queries: bool, body: &SizedReader) -> Self {
+    pub(crate) fn new(
+        req: &Request,
+        url: &Url,
+        mix_queries: bool,
+        body: &SizedReader,
+        strip_credentials: bool,
+    ) -> Self {
         //
 
         let is_chunked = req
@@ -56,11 +121,36 @@ impl Unit {
 
         let query_string = combine_query(&url, &req.query, mix_queries);
 
-        let cookie_headers: Vec<_> = extract_cookies(&req.agent, &url);
+        // SameSite=Strict cookies are only sent if we haven't been redirected
+        // from another site.
+        let same_site = req
+            .to_url()
+            .map(|orig| is_same_site(&orig, url))
+            .unwrap_or(true);
+        let jar_cookies = if req.no_cookies {
+            vec![]
+        } else {
+            extract_cookies(&req.agent, &url, same_site)
+        };
+
+        let user_expects = req
+            .header("expect")
+            .map(|e| e.eq_ignore_ascii_case("100-continue"))
+            .unwrap_or(false);
+        let expect_continue = match req.expect_continue {
+            Some(wait) => Some(wait),
+            None if user_expects => Some(DEFAULT_EXPECT_CONTINUE_WAIT),
+            None => None,
+        }
+        .filter(|_| !body.is_empty());
 
         let extra_headers = {
             let mut extra = vec![];
 
+            if expect_continue.is_some() && !user_expects {
+                extra.push(Header::new("Expect", "100-continue"));
+            }
+
             // chunking and Content-Length headers are mutually exclusive
             // also don't write this if the user has set it themselves
             if !is_chunked && !req.has("content-length") {
@@ -69,23 +159,55 @@ impl Unit {
                 }
             }
 
-            let username = url.username();
-            let password = url.password().unwrap_or("");
+            // the url keeps them percent encoded, send them as UTF-8 like browsers do.
+            let username = percent_decode_str(url.username()).decode_utf8_lossy();
+            let password = percent_decode_str(url.password().unwrap_or("")).decode_utf8_lossy();
             if (username != "" || password != "") && !req.has("authorization") {
-                let encoded = base64::encode(&format!("{}:{}", username, password));
+                let encoded = crate::agent::basic_auth(&username, &password);
                 extra.push(Header::new("Authorization", &format!("Basic {}", encoded)));
             }
 
             extra
         };
-        let headers: Vec<_> = req
+        // the agent's default headers, unless the request sets them.
+        let default_headers = req
+            .default_headers
+            .iter()
+            .filter(|h| !header::has_header(&req.headers, h.name()));
+
+        let (user_cookies, user_headers): (Vec<_>, Vec<_>) = req
             .headers
             .iter()
-            .chain(cookie_headers.iter())
+            .chain(default_headers)
+            .filter(|h| !strip_credentials || !is_credential_header(h))
+            .partition(|h| h.is_name("Cookie"));
+
+        // all cookies are sent in one header, the ones set by the user before the jar's.
+        let cookies: Vec<_> = user_cookies
+            .iter()
+            .map(|h| h.value().trim().trim_end_matches(';').to_string())
+            .chain(jar_cookies)
+            .filter(|c| !c.is_empty())
+            .collect();
+        let cookie_header = if cookies.is_empty() {
+            None
+        } else {
+            Some(Header::new("Cookie", &cookies.join("; ")))
+        };
+
+        let headers: Vec<_> = user_headers
+            .into_iter()
+            .chain(cookie_header.iter())
             .chain(extra_headers.iter())
             .cloned()
             .collect();
 
+        // asking the server to close the connection means we can't reuse it either.
+        let keepalive = req.keepalive
+            && !header::get_header(&headers, "connection")
+                .map(|c| c.eq_ignore_ascii_case("close"))
+                .unwrap_or(false);
+
         let deadline = match req.timeout {
             None => None,
             Some(timeout) => {
@@ -98,16 +220,74 @@ impl Unit {
             agent: Arc::clone(&req.agent),
             url: url.clone(),
             is_chunked,
+            expect_continue,
+            body_unsent: false,
+            keepalive,
+            no_cookies: req.no_cookies,
+            connection_reused: false,
             query_string,
             headers,
             timeout_connect: req.timeout_connect,
             timeout_read: req.timeout_read,
             timeout_write: req.timeout_write,
             deadline,
+            started: time::Instant::now(),
+            timings: Mutex::new(Timings::default()),
             method: req.method.clone(),
-            proxy: req.proxy.clone(),
+            proxy: req
+                .proxy
+                .clone()
+                .filter(|_| !proxy::is_no_proxy(url.host_str().unwrap_or(""))),
+            retry_count: 0,
+            pool_retries_left: req.pool_retries,
+            max_bytes_per_sec: req.max_bytes_per_sec,
+            max_response_size: req.max_response_size,
+            buffer_size: req.buffer_size,
+            flush_each_chunk: req.flush_each_chunk,
+            resolver: req.resolver.clone(),
+            cancel: req.cancel.clone(),
+            tcp_nodelay: req.tcp_nodelay,
+            tcp_keepalive: req.tcp_keepalive,
+            local_address: req.local_address,
+            bind_device: req.bind_device.clone(),
+            credentials_stripped: strip_credentials,
+            header_order: req.header_order.clone(),
+            history: vec![],
+            redirect_responses: vec![],
+            visited: vec![],
+            #[cfg(any(feature = "gzip", feature = "brotli"))]
+            compression: req.compression,
             #[cfg(feature = "tls")]
             tls_config: req.tls_config.clone(),
+            #[cfg(feature = "native-tls")]
+            tls_connector: req.tls_connector.clone(),
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            tls_server_name: req.tls_server_name.clone(),
+            #[cfg(any(feature = "tls", feature = "native-tls"))]
+            pinned_keys: req.pinned_keys.clone(),
+        }
+    }
+
+    // Note how long a phase of this attempt took.
+    pub(crate) fn record_timing(&self, record: impl FnOnce(&mut Timings)) {
+        record(&mut self.timings.lock().unwrap());
+    }
+
+    // The per read timeout, if one is set.
+    pub(crate) fn read_timeout(&self) -> Option<Duration> {
+        if self.timeout_read > 0 {
+            Some(Duration::from_millis(self.timeout_read))
+        } else {
+            None
+        }
+    }
+
+    // The per write timeout, if one is set.
+    pub(crate) fn write_timeout(&self) -> Option<Duration> {
+        if self.timeout_write > 0 {
+            Some(Duration::from_millis(self.timeout_write))
+        } else {
+            None
         }
     }
 
@@ -115,6 +295,46 @@ impl Unit {
         self.method.eq_ignore_ascii_case("head")
     }
 
+    /// Whether the request is sent to an HTTP proxy for it to pass on, rather than
+    /// through a tunnel opened with CONNECT. That's how plain http requests go.
+    pub fn is_forward_proxied(&self) -> bool {
+        let http_proxy = matches!(&self.proxy, Some(p) if p.proto == proxy::Proto::HTTPConnect);
+        http_proxy && self.url.scheme() == "http"
+    }
+
+    /// A key to cache the response under: the method, the url and the values of the
+    /// headers named in the `Vary` of the response, as they're sent. `None` for
+    /// `Vary: *`, which means the response can't be reused.
+    pub(crate) fn cache_key(&self, vary: &str) -> Option<String> {
+        let mut names: Vec<String> = vary
+            .split(',')
+            .map(|n| n.trim().to_ascii_lowercase())
+            .filter(|n| !n.is_empty())
+            .collect();
+        if names.iter().any(|n| n == "*") {
+            return None;
+        }
+        names.sort();
+        names.dedup();
+
+        let mut url = self.url.clone();
+        url.set_query(None);
+        url.set_fragment(None);
+        let mut key = format!("{} {}{}", self.method, url, self.query_string);
+
+        let added = added_headers(self);
+        let headers: Vec<&Header> = added.iter().chain(self.headers.iter()).collect();
+        for name in names {
+            let values: Vec<&str> = headers
+                .iter()
+                .filter(|h| h.is_name(&name))
+                .map(|h| h.value())
+                .collect();
+            key.push_str(&format!("\n{}: {}", name, values.join(", ")));
+        }
+        Some(key)
+    }
+
     #[cfg(test)]
     pub fn header(&self, name: &str) -> Option<&str> {
         header::get_header(&self.headers, name)
@@ -129,6 +349,25 @@ impl Unit {
     }
 }
 
+/// Whether two urls have the same scheme, host and port.
+fn is_same_origin(a: &Url, b: &Url) -> bool {
+    a.scheme() == b.scheme()
+        && a.host_str() == b.host_str()
+        && a.port_or_known_default() == b.port_or_known_default()
+}
+
+/// Whether two urls belong to the same site. Without a public suffix list the
+/// host stands in for the registrable domain.
+fn is_same_site(a: &Url, b: &Url) -> bool {
+    a.scheme() == b.scheme() && a.host_str() == b.host_str()
+}
+
+fn is_credential_header(header: &Header) -> bool {
+    header.is_name("Authorization")
+        || header.is_name("Proxy-Authorization")
+        || header.is_name("Cookie")
+}
+
 /// Perform a connection. Used recursively for redirects.
 pub(crate) fn connect(
     req: &Request,
@@ -136,33 +375,102 @@ pub(crate) fn connect(
     use_pooled: bool,
     redirect_count: u32,
     body: SizedReader,
-    redir: bool,
 ) -> Result<Response, Error> {
     //
 
+    if unit
+        .cancel
+        .as_ref()
+        .map(|c| c.is_cancelled())
+        .unwrap_or(false)
+    {
+        return Err(Error::Cancelled);
+    }
+
+    // the timings are of the connection that gives the final response.
+    unit.record_timing(|t| *t = Timings::default());
+
     // open socket
-    let (mut stream, is_recycled) = connect_socket(&unit, use_pooled)?;
+    let (stream, is_recycled) = match connect_socket(&unit, use_pooled) {
+        Ok(v) => v,
+        Err(err) => {
+            if is_connection_error(&err) && req.is_retryable(&body) {
+                if let Some(delay) = retry_delay(req, &unit, None) {
+                    return retry(req, unit, use_pooled, redirect_count, body, delay);
+                }
+            }
+            return Err(err);
+        }
+    };
 
-    let send_result = send_prelude(&unit, &mut stream, redir);
+    // bound everything we write and read on the socket by the timeouts.
+    let mut stream = DeadlineStream::new(
+        stream,
+        unit.deadline,
+        unit.read_timeout(),
+        unit.write_timeout(),
+    );
+    stream.set_max_bytes_per_sec(unit.max_bytes_per_sec);
+    stream.set_cancel_handle(unit.cancel.clone());
+
+    let send_result = send_prelude(&unit, &mut stream);
+    let retryable = req.is_retryable(&body);
 
     if let Err(err) = send_result {
-        if is_recycled {
-            // we try open a new connection, this time there will be
-            // no connection in the pool. don't use it.
-            return connect(req, unit, false, redirect_count, body, redir);
-        } else {
-            // not a pooled connection, propagate the error.
-            return Err(err.into());
+        // a pooled connection the server has closed, retried like one that closes
+        // while reading the status line (below). nothing of the body was sent.
+        if retryable && is_recycled && unit.pool_retries_left > 0 && !is_past_deadline(&unit) {
+            let mut unit = unit;
+            unit.pool_retries_left -= 1;
+            let use_pooled = unit.pool_retries_left > 0;
+            return connect(req, unit, use_pooled, redirect_count, body);
         }
+        return Err(err.into());
     }
-    let retryable = req.is_retryable(&body);
+    // keep a copy of the body in case a 307/308 redirect needs it again.
+    let replay = body.try_clone();
 
-    // send the body (which can be empty now depending on redirects)
-    body::send_body(body, unit.is_chunked, &mut stream)?;
+    let mut waiting = Instant::now();
+    let early_resp = match unit.expect_continue {
+        Some(wait) => wait_for_continue(&unit, &mut stream, wait),
+        None => None,
+    };
 
-    // start reading the response to process cookies and redirects.
-    let mut stream = stream::DeadlineStream::new(stream, unit.deadline);
-    let mut resp = Response::from_read(&mut stream);
+    let mut unit = unit;
+    unit.connection_reused = is_recycled;
+    let mut resp = match early_resp {
+        Some(resp) => {
+            unit.body_unsent = true;
+            resp
+        }
+        None => {
+            // send the body (which can be empty now depending on redirects)
+            // small chunks go out right away, not held back by Nagle's algorithm.
+            let nodelay = unit.flush_each_chunk && unit.is_chunked && !unit.tcp_nodelay;
+            if nodelay {
+                stream.set_nodelay(true)?;
+            }
+            body::send_body(
+                body,
+                unit.is_chunked,
+                &mut stream,
+                unit.buffer_size,
+                unit.flush_each_chunk,
+            )?;
+            if nodelay {
+                stream.set_nodelay(false)?;
+            }
+            waiting = Instant::now();
+
+            // start reading the response to process cookies and redirects.
+            read_final_response(&mut stream)
+        }
+    };
+    let started = unit.started;
+    unit.record_timing(|t| {
+        t.first_byte = waiting.elapsed();
+        t.total = started.elapsed();
+    });
 
     // https://tools.ietf.org/html/rfc7230#section-6.3.1
     // When an inbound connection is closed prematurely, a client MAY
@@ -170,29 +478,57 @@ pub(crate) fn connect(
     // sequence of requests if all of those requests have idempotent
     // methods.
     //
-    // We choose to retry only once. To do that, we rely on is_recycled,
-    // the "one connection per hostname" police of the ConnectionPool,
-    // and the fact that connections with errors are dropped.
+    // We retry as many times as the request's pool_retries allows (once by
+    // default). Every retry counts down unit.pool_retries_left, and the last
+    // one doesn't use the pool, so it gets a fresh connection. We rely on
+    // is_recycled and the fact that connections with errors are dropped.
+    //
+    // This covers the connection being closed while sending the prelude
+    // (above) as well as while reading the status line or headers.
     //
-    // TODO: is_bad_status_read is too narrow since it covers only the
-    // first line. It's also allowable to retry requests that hit a
-    // closed connection during the sending or receiving of headers.
+    // The body was sent on the broken connection, so the retry sends the
+    // copy in replay, which retryable guarantees there is.
     if let Some(err) = resp.synthetic_error() {
-        if err.is_bad_status_read() && retryable && is_recycled {
-            let empty = Payload::Empty.into_read();
-            return connect(req, unit, false, redirect_count, empty, redir);
+        if err.is_closed_connection()
+            && retryable
+            && is_recycled
+            && unit.pool_retries_left > 0
+            && !is_past_deadline(&unit)
+        {
+            if let Some(body) = replay.as_ref().and_then(|r| r.try_clone()) {
+                let mut unit = unit;
+                unit.pool_retries_left -= 1;
+                let use_pooled = unit.pool_retries_left > 0;
+                return connect(req, unit, use_pooled, redirect_count, body);
+            }
+        }
+    }
+
+    // a proxy turning down a tunnel gives the same error.
+    if unit.is_forward_proxied() && resp.status() == 407 {
+        return Err(Error::ProxyAuthRequired);
+    }
+
+    // retry on gateway errors and connections that broke down, if the user asked for it.
+    let should_retry = match resp.synthetic_error() {
+        Some(err) => is_connection_error(err),
+        None => matches!(resp.status(), 429 | 502 | 503 | 504),
+    };
+    if should_retry && retryable {
+        if let Some(delay) = retry_delay(req, &unit, resp.header("retry-after")) {
+            if let Some(body) = replay {
+                return retry(req, unit, use_pooled, redirect_count, body, delay);
+            }
         }
     }
 
     // squirrel away cookies
-    save_cookies(&unit, &resp);
+    if !unit.no_cookies {
+        save_cookies(&unit, &resp);
+    }
 
     // handle redirects
     if resp.redirect() && req.redirects > 0 {
-        if redirect_count == req.redirects {
-            return Err(Error::TooManyRedirects);
-        }
-
         // the location header
         let location = resp.header("location");
         if let Some(location) = location {
@@ -202,24 +538,48 @@ pub(crate) fn connect(
                 .join(location)
                 .map_err(|_| Error::BadUrl(format!("Bad redirection: {}", location)))?;
 
-            // perform the redirect differently depending on 3xx code.
-            match resp.status() {
-                301 | 302 | 303 => {
-                    let empty = Payload::Empty.into_read();
-                    // recreate the unit to get a new hostname and cookies for the new host.
-                    let mut new_unit = Unit::new(req, &new_url, false, &empty);
-                    // this is to follow how curl does it. POST, PUT etc change
-                    // to GET on a redirect.
-                    new_unit.method = match &unit.method[..] {
-                        "GET" | "HEAD" => unit.method,
-                        _ => "GET".into(),
-                    };
-                    return connect(req, new_unit, use_pooled, redirect_count + 1, empty, true);
+            let status = resp.status();
+            if let 301 | 302 | 303 | 307 | 308 = status {
+                // let the user decide what to do with this hop.
+                let action = match &req.redirect_fn {
+                    Some(redirect_fn) => redirect_fn(&unit.url, status, &new_url),
+                    None => RedirectAction::Follow(new_url.clone()),
+                };
+                match action {
+                    RedirectAction::Follow(url) => {
+                        // only a hop that is followed counts against the limit, any
+                        // other 3xx after the last one is the response.
+                        if redirect_count == req.redirects {
+                            return Err(Error::TooManyRedirects);
+                        }
+                        if let Some(limit) = req.keep_redirect_responses.filter(|_| !unit.is_head())
+                        {
+                            let url = unit.url.to_string();
+                            crate::response::set_stream(
+                                &mut resp,
+                                url.clone(),
+                                None,
+                                stream.into(),
+                            );
+                            let redirect = resp.into_redirect_response(&url, limit);
+                            unit.redirect_responses.push(redirect);
+                        }
+                        return follow_redirect(
+                            req,
+                            unit,
+                            use_pooled,
+                            redirect_count,
+                            status,
+                            url,
+                            replay,
+                        );
+                    }
+                    RedirectAction::Stop => (),
+                    RedirectAction::Error => {
+                        return Err(Error::RedirectDenied(new_url.to_string()))
+                    }
                 }
-                _ => (),
-                // reinstate this with expect-100
-                // 307 | 308 | _ => connect(unit, method, use_pooled, redirects - 1, body),
-            };
+            }
         }
     }
 
@@ -231,57 +591,276 @@ pub(crate) fn connect(
     Ok(resp)
 }
 
+fn is_connection_error(err: &Error) -> bool {
+    matches!(
+        err,
+        Error::ConnectionFailed(_)
+            | Error::ConnectTimeout
+            | Error::Io(_)
+            | Error::BadStatusRead
+            | Error::BadHeaderRead
+    )
+}
+
+fn is_past_deadline(unit: &Unit) -> bool {
+    unit.deadline.map(|d| Instant::now() >= d).unwrap_or(false)
+}
+
+/// How long to wait before the next retry, or `None` if there should be no retry.
+fn retry_delay(req: &Request, unit: &Unit, retry_after: Option<&str>) -> Option<Duration> {
+    if unit.retry_count >= req.retries {
+        return None;
+    }
+    let delay = match retry_after.and_then(parse_retry_after) {
+        // better to give back the response than to sleep this long.
+        Some(delay) if delay > req.max_retry_wait => return None,
+        Some(delay) => delay,
+        // 100ms, 200ms, 400ms…
+        None => Duration::from_millis(100 << unit.retry_count.min(16)).min(req.max_retry_wait),
+    };
+    if let Some(deadline) = unit.deadline {
+        if Instant::now() + delay >= deadline {
+            return None;
+        }
+    }
+    Some(delay)
+}
+
+/// The wait asked for by a `Retry-After` header, either seconds or an HTTP-date.
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    if let Ok(secs) = value.trim().parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = header::parse_http_date(value)?;
+    // a date in the past means no wait.
+    Some(
+        at.duration_since(time::SystemTime::now())
+            .unwrap_or_default(),
+    )
+}
+
+fn retry(
+    req: &Request,
+    mut unit: Unit,
+    use_pooled: bool,
+    redirect_count: u32,
+    body: SizedReader,
+    delay: Duration,
+) -> Result<Response, Error> {
+    thread::sleep(delay);
+    unit.retry_count += 1;
+    connect(req, unit, use_pooled, redirect_count, body)
+}
+
+/// Send the request again to the url of a redirect.
+fn follow_redirect(
+    req: &Request,
+    unit: Unit,
+    use_pooled: bool,
+    redirect_count: u32,
+    status: u16,
+    new_url: Url,
+    replay: Option<SizedReader>,
+) -> Result<Response, Error> {
+    // perform the redirect differently depending on 3xx code. `None` keeps
+    // the method and body unchanged.
+    let get_method = match status {
+        307 | 308 => None,
+        // see other: the result is fetched with GET.
+        303 => Some(if unit.is_head() { "HEAD" } else { "GET" }),
+        // 301 and 302 turn a POST into GET, which is what browsers and curl do.
+        _ if unit.is_head() => Some("HEAD"),
+        _ if ["GET", "POST"]
+            .iter()
+            .any(|m| unit.method.eq_ignore_ascii_case(m)) =>
+        {
+            Some("GET")
+        }
+        _ => None,
+    };
+    // making the same request again can't get anywhere.
+    let mut visited = unit.visited;
+    visited.push((unit.method.clone(), unit.url.to_string()));
+    let method = get_method.map(String::from).unwrap_or(unit.method);
+    let new_str = new_url.to_string();
+    if visited
+        .iter()
+        .any(|(m, u)| m.eq_ignore_ascii_case(&method) && *u == new_str)
+    {
+        return Err(Error::RedirectLoop(new_str));
+    }
+    let mut history = unit.history;
+    history.push((status, new_str));
+
+    let empty = Payload::Empty.into_read();
+    let body = match get_method {
+        None => replay.ok_or(Error::BodyNotReplayable)?,
+        Some(_) => empty,
+    };
+    // credentials set by the user are only for the origin of the request url, they
+    // must not leak to another host, nor come back after a redirect to one.
+    let strip_credentials = unit.credentials_stripped || !is_same_origin(&unit.url, &new_url);
+    // recreate the unit to get a new hostname and cookies for the new host.
+    let mut new_unit = Unit::new(req, &new_url, false, &body, strip_credentials);
+    new_unit.method = method;
+    // the overall timeout covers all redirects.
+    new_unit.deadline = unit.deadline;
+    new_unit.started = unit.started;
+    new_unit.history = history;
+    new_unit.redirect_responses = unit.redirect_responses;
+    new_unit.visited = visited;
+    connect(req, new_unit, use_pooled, redirect_count + 1, body)
+}
+
+/// Wait for the server to accept the body with `100 Continue`. Returns the response
+/// if the server answers with a final status instead, or a read fails. After `wait`
+/// without an answer, we carry on as if accepted.
+fn wait_for_continue(unit: &Unit, stream: &mut DeadlineStream, wait: Duration) -> Option<Response> {
+    let timeout_read = unit.read_timeout();
+    stream.set_timeout_read(Some(timeout_read.map_or(wait, |t| t.min(wait))));
+    let resp = loop {
+        let resp = Response::from_read(&mut *stream);
+        if resp.synthetic() || !is_interim(resp.status()) {
+            break Some(resp);
+        }
+        if resp.status() == 100 {
+            break None;
+        }
+    };
+    stream.set_timeout_read(timeout_read);
+    match resp {
+        Some(resp) if matches!(resp.synthetic_error(), Some(Error::ReadTimeout)) => None,
+        resp => resp,
+    }
+}
+
+/// Read a response, skipping interim responses such as a `100 Continue` that
+/// arrives after we gave up waiting for it.
+fn read_final_response(stream: &mut DeadlineStream) -> Response {
+    loop {
+        let resp = Response::from_read(&mut *stream);
+        if resp.synthetic() || !is_interim(resp.status()) {
+            return resp;
+        }
+    }
+}
+
+// 1xx responses come before the final one, except 101 which switches protocols.
+fn is_interim(status: u16) -> bool {
+    (100..200).contains(&status) && status != 101
+}
+
 #[cfg(feature = "cookie")]
-fn extract_cookies(state: &std::sync::Mutex<Option<AgentState>>, url: &Url) -> Vec<Header> {
+fn extract_cookies(
+    state: &std::sync::Mutex<Option<AgentState>>,
+    url: &Url,
+    same_site: bool,
+) -> Vec<String> {
     let state = state.lock().unwrap();
-    let is_secure = url.scheme().eq_ignore_ascii_case("https");
-    let hostname = url.host_str().unwrap_or(DEFAULT_HOST).to_string();
-
-    match state.as_ref().map(|state| &state.jar) {
+    match state.as_ref() {
         None => vec![],
-        Some(jar) => match_cookies(jar, &hostname, url.path(), is_secure),
+        Some(state) => {
+            let mut cookies = match_cookies(state, url, same_site);
+            // RFC 6265, section 5.4: cookies with longer paths are listed first.
+            cookies.sort_by_key(|c| std::cmp::Reverse(c.path().map(str::len).unwrap_or(0)));
+            cookies
+                .into_iter()
+                .map(|c| Cookie::new(c.name(), c.value()).encoded().to_string())
+                .collect()
+        }
     }
 }
 
 #[cfg(not(feature = "cookie"))]
-fn extract_cookies(_state: &std::sync::Mutex<Option<AgentState>>, _url: &Url) -> Vec<Header> {
+fn extract_cookies(
+    _state: &std::sync::Mutex<Option<AgentState>>,
+    _url: &Url,
+    _same_site: bool,
+) -> Vec<String> {
     vec![]
 }
 
+/// The cookies of the jar to send with a request to the url.
 // TODO check so cookies can't be set for tld:s
 #[cfg(feature = "cookie")]
-fn match_cookies(jar: &CookieJar, domain: &str, path: &str, is_secure: bool) -> Vec<Header> {
-    jar.iter()
+pub(crate) fn match_cookies<'a>(
+    state: &'a AgentState,
+    url: &Url,
+    same_site: bool,
+) -> Vec<&'a Cookie<'static>> {
+    let is_secure = url.scheme().eq_ignore_ascii_case("https");
+    let domain = url.host_str().unwrap_or(DEFAULT_HOST);
+    let path = url.path();
+    let now = ::time::OffsetDateTime::now_utc();
+    state
+        .jar
+        .iter()
         .filter(|c| {
-            // if there is a domain, it must be matched.
+            // if there is a domain, it must be matched, exactly for a host-only cookie.
             // if there is no domain, then ignore cookie
-            let domain_ok = c
-                .domain()
-                .map(|cdom| domain.contains(cdom))
-                .unwrap_or(false);
-            // a path must match the beginning of request path.
+            let domain_ok = match c.domain() {
+                Some(cdom) if state.host_only.contains(c.name()) => {
+                    domain.eq_ignore_ascii_case(cdom)
+                }
+                Some(cdom) => domain_match(domain, cdom),
+                None => false,
+            };
             // no cookie path, we say is ok. is it?!
             let path_ok = c
                 .path()
-                .map(|cpath| path.find(cpath).map(|pos| pos == 0).unwrap_or(false))
+                .map(|cpath| path_match(path, cpath))
                 .unwrap_or(true);
             // either the cookie isnt secure, or we're not doing a secure request.
             let secure_ok = !c.secure().unwrap_or(false) || is_secure;
+            let same_site_ok = same_site || c.same_site() != Some(SameSite::Strict);
+            let expired = c.expires().map(|t| t <= now).unwrap_or(false);
 
-            domain_ok && path_ok && secure_ok
-        })
-        .map(|c| {
-            let name = c.name().to_string();
-            let value = c.value().to_string();
-            let nameval = Cookie::new(name, value).encoded().to_string();
-            Header::new("Cookie", &nameval)
+            domain_ok && path_ok && secure_ok && same_site_ok && !expired
         })
         .collect()
 }
 
+/// Domain matching of RFC 6265, section 5.1.3. The host is either the cookie
+/// domain itself or a subdomain of it, and isn't an IP address.
+#[cfg(feature = "cookie")]
+fn domain_match(host: &str, cookie_domain: &str) -> bool {
+    let host = host.to_ascii_lowercase();
+    let cookie_domain = cookie_domain.trim_start_matches('.').to_ascii_lowercase();
+    if host == cookie_domain {
+        return true;
+    }
+    let is_ip = host.parse::<std::net::IpAddr>().is_ok() || host.starts_with('[');
+    !is_ip
+        && host.ends_with(&cookie_domain)
+        && host[..host.len() - cookie_domain.len()].ends_with('.')
+}
+
+/// Path matching of RFC 6265, section 5.1.4. The cookie path is a prefix of
+/// the request path that ends at a `/`.
+#[cfg(feature = "cookie")]
+fn path_match(path: &str, cookie_path: &str) -> bool {
+    path == cookie_path
+        || (path.starts_with(cookie_path)
+            && (cookie_path.ends_with('/') || path[cookie_path.len()..].starts_with('/')))
+}
+
+/// The default cookie path of RFC 6265, section 5.1.4: the request path up to,
+/// but not including, its last `/`.
+#[cfg(feature = "cookie")]
+fn default_cookie_path(path: &str) -> &str {
+    match path.rfind('/') {
+        Some(0) | None => "/",
+        Some(pos) => &path[..pos],
+    }
+}
+
 /// Combine the query of the url and the query options set on the request object.
 pub(crate) fn combine_query(url: &Url, query: &QString, mix_queries: bool) -> String {
-    match (url.query(), !query.is_empty() && mix_queries) {
+    // a url ending in `?` has an empty query.
+    match (
+        url.query().filter(|q| !q.is_empty()),
+        !query.is_empty() && mix_queries,
+    ) {
         (Some(urlq), true) => format!("?{}&{}", urlq, query),
         (Some(urlq), false) => format!("?{}", urlq),
         (None, true) => format!("?{}", query),
@@ -290,22 +869,29 @@ pub(crate) fn combine_query(url: &Url, query: &QString, mix_queries: bool) -> St
 }
 
 /// Connect the socket, either by using the pool or grab a new one.
+fn take_pooled(unit: &Unit) -> Option<Stream> {
+    let mut state = unit.agent.lock().unwrap();
+    let agent = state.as_mut()?;
+    agent.pool.try_get_connection(&PoolKey::for_unit(unit))
+}
+
 fn connect_socket(unit: &Unit, use_pooled: bool) -> Result<(Stream, bool), Error> {
     match unit.url.scheme() {
         "http" | "https" | "test" => (),
+        #[cfg(unix)]
+        "unix" => (),
         _ => return Err(Error::UnknownScheme(unit.url.scheme().to_string())),
     };
-    if use_pooled {
-        let state = &mut unit.agent.lock().unwrap();
-        if let Some(agent) = state.as_mut() {
-            // The connection may have been closed by the server
-            // due to idle timeout while it was sitting in the pool.
-            // Loop until we find one that is still good or run out of connections.
-            while let Some(stream) = agent.pool.try_get_connection(&unit.url, &unit.proxy) {
-                let server_closed = stream.server_closed()?;
-                if !server_closed {
-                    return Ok((stream, true));
-                }
+    if use_pooled && unit.keepalive {
+        // The connection may have been closed by the server
+        // due to idle timeout while it was sitting in the pool.
+        // Loop until we find one that is still good or run out of connections.
+        // The agent is only locked to take a connection, not while checking it,
+        // so requests on other threads aren't held up.
+        while let Some(stream) = take_pooled(unit) {
+            let server_closed = stream.server_closed()?;
+            if !server_closed {
+                return Ok((stream, true));
             }
         }
     }
@@ -313,32 +899,99 @@ fn connect_socket(unit: &Unit, use_pooled: bool) -> Result<(Stream, bool), Error
         "http" => stream::connect_http(&unit),
         "https" => stream::connect_https(&unit),
         "test" => connect_test(&unit),
+        #[cfg(unix)]
+        "unix" => stream::connect_unix(&unit),
         _ => Err(Error::UnknownScheme(unit.url.scheme().to_string())),
     };
-    Ok((stream?, false))
+    // running out of the overall time while connecting is the same as anywhere
+    // else in the request.
+    let stream = stream.map_err(|e| match e {
+        Error::ConnectTimeout if is_past_deadline(unit) => Error::DeadlineExceeded,
+        e => e,
+    })?;
+    Ok((stream, false))
 }
 
+// What may not be sent as is in the path or query of the request line (RFC 3986),
+// on top of what the url crate already encodes. "%" is left alone, so that what
+// is already percent-encoded isn't encoded again.
+const TARGET_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'<')
+    .add(b'>')
+    .add(b'\\')
+    .add(b'^')
+    .add(b'`')
+    .add(b'{')
+    .add(b'|')
+    .add(b'}')
+    .add(b'[')
+    .add(b']');
+
 /// Send request line + headers (all up until the body).
 #[allow(clippy::write_with_newline)]
-fn send_prelude(unit: &Unit, stream: &mut Stream, redir: bool) -> IoResult<()> {
+fn send_prelude(unit: &Unit, stream: &mut DeadlineStream) -> IoResult<()> {
     //
 
     // build into a buffer and send in one go.
     let mut prelude: Vec<u8> = vec![];
 
-    // request line
+    // request line, with the absolute url for a proxy to pass it on.
+    let origin = if unit.is_forward_proxied() {
+        unit.url.origin().ascii_serialization()
+    } else {
+        String::new()
+    };
     write!(
         prelude,
-        "{} {}{} HTTP/1.1\r\n",
+        "{} {}{}{} HTTP/1.1\r\n",
         unit.method,
-        unit.url.path(),
-        &unit.query_string
+        origin,
+        utf8_percent_encode(unit.url.path(), TARGET_ENCODE_SET),
+        utf8_percent_encode(&unit.query_string, TARGET_ENCODE_SET),
     )?;
 
-    // host header if not set by user.
+    let auto = added_headers(unit);
+
+    // other headers, after the ones we add unless the user asked for an order.
+    let mut headers: Vec<&Header> = auto.iter().chain(unit.headers.iter()).collect();
+    if !unit.header_order.is_empty() {
+        let order = &unit.header_order;
+        headers.sort_by_key(|h| {
+            order
+                .iter()
+                .position(|name| h.is_name(name))
+                .unwrap_or(order.len())
+        });
+    }
+    for header in headers {
+        // an empty User-Agent or Host means to send none at all.
+        if (header.is_name("user-agent") || header.is_name("host")) && header.value().is_empty() {
+            continue;
+        }
+        write!(prelude, "{}: {}\r\n", header.name(), header.value())?;
+    }
+
+    // finish
+    write!(prelude, "\r\n")?;
+
+    // write all to the wire
+    stream.write_all(&prelude[..])?;
+
+    Ok(())
+}
+
+/// The headers we add to the request, each unless set by the user.
+fn added_headers(unit: &Unit) -> Vec<Header> {
+    let mut auto: Vec<Header> = vec![];
     if !header::has_header(&unit.headers, "host") {
         let host = unit.url.host().unwrap();
         match unit.url.port() {
+            // the host of a unix url is the socket path, which means nothing to the server.
+            _ if unit.url.scheme() == "unix" => {
+                auto.push(Header::new("Host", "localhost"));
+            }
             Some(port) => {
                 let scheme_default: u16 = match unit.url.scheme() {
                     "http" => 80,
@@ -346,37 +999,43 @@ fn send_prelude(unit: &Unit, stream: &mut Stream, redir: bool) -> IoResult<()> {
                     _ => 0,
                 };
                 if scheme_default != 0 && scheme_default == port {
-                    write!(prelude, "Host: {}\r\n", host)?;
+                    auto.push(Header::new("Host", &host.to_string()));
                 } else {
-                    write!(prelude, "Host: {}:{}\r\n", host, port)?;
+                    auto.push(Header::new("Host", &format!("{}:{}", host, port)));
                 }
             }
             None => {
-                write!(prelude, "Host: {}\r\n", host)?;
+                auto.push(Header::new("Host", &host.to_string()));
             }
         }
     }
     if !header::has_header(&unit.headers, "user-agent") {
-        write!(prelude, "User-Agent: ureq\r\n")?;
+        auto.push(Header::new("User-Agent", "ureq"));
     }
     if !header::has_header(&unit.headers, "accept") {
-        write!(prelude, "Accept: */*\r\n")?;
+        auto.push(Header::new("Accept", "*/*"));
     }
-
-    // other headers
-    for header in &unit.headers {
-        if !redir || !header.is_name("Authorization") {
-            write!(prelude, "{}: {}\r\n", header.name(), header.value())?;
+    if !unit.keepalive && !header::has_header(&unit.headers, "connection") {
+        auto.push(Header::new("Connection", "close"));
+    }
+    #[cfg(any(feature = "gzip", feature = "brotli"))]
+    {
+        if unit.compression && !header::has_header(&unit.headers, "accept-encoding") {
+            auto.push(Header::new(
+                "Accept-Encoding",
+                crate::decoder::ACCEPT_ENCODING,
+            ));
         }
     }
 
-    // finish
-    write!(prelude, "\r\n")?;
-
-    // write all to the wire
-    stream.write_all(&prelude[..])?;
+    // without a tunnel, the proxy sees the credentials meant for it here.
+    if unit.is_forward_proxied() && !header::has_header(&unit.headers, "proxy-authorization") {
+        if let Some(auth) = unit.proxy.as_ref().and_then(|p| p.authorization()) {
+            auto.push(Header::new("Proxy-Authorization", &auth));
+        }
+    }
 
-    Ok(())
+    auto
 }
 
 #[cfg(not(feature = "cookie"))]
@@ -394,19 +1053,40 @@ fn save_cookies(unit: &Unit, resp: &Response) {
 
     // only lock if we know there is something to process
     let state = &mut unit.agent.lock().unwrap();
-    if let Some(add_jar) = state.as_mut().map(|state| &mut state.jar) {
+    if let Some(state) = state.as_mut() {
+        let host = unit.url.host_str().unwrap_or(DEFAULT_HOST);
         for raw_cookie in cookies.iter() {
-            let to_parse = if raw_cookie.to_lowercase().contains("domain=") {
+            let host_only = !raw_cookie.to_lowercase().contains("domain=");
+            let to_parse = if !host_only {
                 (*raw_cookie).to_string()
             } else {
-                let host = &unit.url.host_str().unwrap_or(DEFAULT_HOST).to_string();
                 format!("{}; Domain={}", raw_cookie, host)
             };
             match Cookie::parse_encoded(&to_parse[..]) {
                 Err(_) => (), // ignore unparseable cookies
                 Ok(cookie) => {
-                    let cookie = cookie.into_owned();
-                    add_jar.add(cookie)
+                    let mut cookie = cookie.into_owned();
+                    // a server can't set cookies for another domain.
+                    if !cookie
+                        .domain()
+                        .map(|d| domain_match(host, d))
+                        .unwrap_or(false)
+                    {
+                        continue;
+                    }
+                    if cookie.path().map(|p| !p.starts_with('/')).unwrap_or(true) {
+                        cookie.set_path(default_cookie_path(unit.url.path()).to_string());
+                    }
+                    // keep when a Max-Age runs out, so the cookie can be saved.
+                    if let (Some(max_age), None) = (cookie.max_age(), cookie.expires()) {
+                        cookie.set_expires(::time::OffsetDateTime::now_utc() + max_age);
+                    }
+                    if host_only {
+                        state.host_only.insert(cookie.name().to_string());
+                    } else {
+                        state.host_only.remove(cookie.name());
+                    }
+                    state.jar.add(cookie)
                 }
             }
         }
@@ -0,0 +1,74 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::time::Duration;
+
+use crate::stream::Stream;
+
+/// The connection of a request the server switched to another protocol, made by
+/// [`into_upgraded()`](struct.Response.html#method.into_upgraded).
+///
+/// Reading and writing go straight to the socket (through TLS for `https`), starting
+/// right after the headers of the `101 Switching Protocols` response. The timeouts of
+/// the request no longer apply, set new ones with
+/// [`set_read_timeout()`](#method.set_read_timeout) and
+/// [`set_write_timeout()`](#method.set_write_timeout).
+///
+/// The connection is never returned to the pool, it is closed when this is dropped.
+/// For a response that was parsed rather than received there is only the rest of the
+/// input to read, and writing fails with `ErrorKind::NotConnected`.
+pub struct Upgraded {
+    stream: Stream,
+}
+
+impl Upgraded {
+    pub(crate) fn new(stream: Stream) -> io::Result<Upgraded> {
+        stream.set_read_timeout(None)?;
+        stream.set_write_timeout(None)?;
+        Ok(Upgraded { stream })
+    }
+
+    /// How long a read waits for the server before failing, `None` to wait forever.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    /// How long a write waits for the server before failing, `None` to wait forever.
+    pub fn set_write_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_write_timeout(timeout)
+    }
+
+    // a response parsed from a string reads from memory, there's no one to write to.
+    fn check_writable(&self) -> io::Result<()> {
+        match self.stream {
+            Stream::Cursor(_) => Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "upgraded response has no connection to write to",
+            )),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Upgraded({:?})", self.stream)
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.stream.read(buf)
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check_writable()?;
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.check_writable()?;
+        self.stream.flush()
+    }
+}
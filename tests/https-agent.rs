@@ -17,6 +17,7 @@ fn tls_connection_close() {
 #[cfg(feature = "cookies")]
 #[cfg(feature = "json")]
 #[test]
+#[allow(deprecated)]
 fn agent_set_cookie() {
     use serde::Deserialize;
     use std::collections::HashMap;